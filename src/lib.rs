@@ -0,0 +1,7660 @@
+use std::fmt;
+use std::error::Error;
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::f64::consts;
+use std::collections::HashMap;
+use std::fs;
+use std::mem;
+use std::str::Chars;
+use std::iter::Peekable;
+use std::ops::RangeInclusive;
+use std::panic;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use MathError::*;
+use Token::*;
+
+const MAX_CALL_DEPTH: usize = 256;
+// Enough for the deepest recursion allowed, even unoptimized.
+const EVAL_STACK_SIZE: usize = 128 << 20;
+const MAX_BISECTIONS: usize = 20;
+const MAX_ITERATIONS: usize = 100;
+const RATIO_TOLERANCE: f64 = 1e-6;
+const MAX_SERIES_ORDER: usize = 64;
+const MAX_LIST_LEN: usize = 1 << 20;
+const MAX_PRECISION: usize = 1000;
+const GUARD_DIGITS: usize = 10;
+const FIXED_PLACES: i64 = 18;
+const MAX_BIGINT_BITS: u64 = 1 << 16;
+const EXACT_INTEGER_LIMIT: f64 = 9_007_199_254_740_992.0;
+const MAX_BIG_FACTORIAL: u64 = 5000;
+const MAX_WARNINGS: usize = 8;
+// Digits shown after the point, or significant ones, beyond which floats are all noise.
+const MAX_PLACES: usize = 17;
+// Digits a difference may cancel before it is warned about.
+const CANCELLED_DIGITS: f64 = 10.0;
+// Largest denominator of fractions shown in fraction mode.
+const MAX_SHOWN_DENOMINATOR: i64 = 1_000_000;
+// Largest power of a unit, so that the dimensions of a quantity can't overflow.
+const MAX_UNIT_POWER: i32 = 100;
+
+const CONSTANTS: &[(&str, f64)] = &[
+    ("pi", consts::PI),
+    ("e", consts::E),
+    ("tau", consts::TAU),
+    ("phi", 1.618_033_988_749_895),
+];
+
+pub const CONSTANTS_FILE: &str = "constants.toml";
+pub const RATES_FILE: &str = "rates.toml";
+
+const SI_SUFFIXES: &[(&str, f64)] = &[
+    ("T", 1e12), ("G", 1e9), ("M", 1e6), ("k", 1e3),
+    ("m", 1e-3), ("u", 1e-6), ("µ", 1e-6), ("n", 1e-9), ("p", 1e-12),
+];
+
+const BYTE_SUFFIXES: &[(&str, f64)] = &[
+    ("B", 1.0),
+    ("kB", 1e3), ("KB", 1e3), ("MB", 1e6), ("GB", 1e9), ("TB", 1e12), ("PB", 1e15),
+    ("KiB", 1024.0), ("MiB", 1048576.0), ("GiB", 1073741824.0),
+    ("TiB", 1099511627776.0), ("PiB", 1125899906842624.0),
+];
+
+// Suffixes written directly after a number to make a duration, in seconds.
+const DURATION_SUFFIXES: &[(&str, f64)] = &[
+    ("w", 604800.0), ("d", 86400.0), ("h", 3600.0), ("min", 60.0), ("s", 1.0), ("ms", 1e-3),
+];
+
+// Exponents of length, mass, time, current, temperature, amount of substance and currency.
+type Dims = [i32; 7];
+
+const CURRENCY: Dims = [0, 0, 0, 0, 0, 0, 1];
+
+const UNITS: &[(&str, f64, Dims)] = &[
+    ("m", 1.0, [1, 0, 0, 0, 0, 0, 0]), ("km", 1e3, [1, 0, 0, 0, 0, 0, 0]),
+    ("cm", 1e-2, [1, 0, 0, 0, 0, 0, 0]), ("mm", 1e-3, [1, 0, 0, 0, 0, 0, 0]),
+    ("um", 1e-6, [1, 0, 0, 0, 0, 0, 0]), ("nm", 1e-9, [1, 0, 0, 0, 0, 0, 0]),
+    ("mi", 1609.344, [1, 0, 0, 0, 0, 0, 0]), ("yd", 0.9144, [1, 0, 0, 0, 0, 0, 0]),
+    ("ft", 0.3048, [1, 0, 0, 0, 0, 0, 0]), ("inch", 0.0254, [1, 0, 0, 0, 0, 0, 0]),
+    ("ha", 1e4, [2, 0, 0, 0, 0, 0, 0]), ("L", 1e-3, [3, 0, 0, 0, 0, 0, 0]), ("mL", 1e-6, [3, 0, 0, 0, 0, 0, 0]),
+    ("kg", 1.0, [0, 1, 0, 0, 0, 0, 0]), ("g", 1e-3, [0, 1, 0, 0, 0, 0, 0]), ("mg", 1e-6, [0, 1, 0, 0, 0, 0, 0]),
+    ("lb", 0.453_592_37, [0, 1, 0, 0, 0, 0, 0]), ("oz", 0.028_349_523_125, [0, 1, 0, 0, 0, 0, 0]),
+    ("s", 1.0, [0, 0, 1, 0, 0, 0, 0]), ("ms", 1e-3, [0, 0, 1, 0, 0, 0, 0]), ("min", 60.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("h", 3600.0, [0, 0, 1, 0, 0, 0, 0]), ("day", 86400.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("week", 604800.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("A", 1.0, [0, 0, 0, 1, 0, 0, 0]), ("K", 1.0, [0, 0, 0, 0, 1, 0, 0]), ("mol", 1.0, [0, 0, 0, 0, 0, 1, 0]),
+    ("Hz", 1.0, [0, 0, -1, 0, 0, 0, 0]), ("N", 1.0, [1, 1, -2, 0, 0, 0, 0]), ("Pa", 1.0, [-1, 1, -2, 0, 0, 0, 0]),
+    ("J", 1.0, [2, 1, -2, 0, 0, 0, 0]), ("kJ", 1e3, [2, 1, -2, 0, 0, 0, 0]), ("cal", 4.184, [2, 1, -2, 0, 0, 0, 0]),
+    ("kcal", 4184.0, [2, 1, -2, 0, 0, 0, 0]), ("kWh", 3.6e6, [2, 1, -2, 0, 0, 0, 0]),
+    ("eV", 1.602_176_634e-19, [2, 1, -2, 0, 0, 0, 0]),
+    ("W", 1.0, [2, 1, -3, 0, 0, 0, 0]), ("kW", 1e3, [2, 1, -3, 0, 0, 0, 0]), ("V", 1.0, [2, 1, -3, -1, 0, 0, 0]),
+    ("mph", 0.44704, [1, 0, -1, 0, 0, 0, 0]),
+];
+
+
+// Parses `input` into the expression tree of each of its statements, without evaluating anything.
+pub fn parse(input: &str) -> Result<Vec<Expr>, MathError> {
+    let tokens = Tokens::parse(input, &Environment::new())?;
+    let statements: Vec<Expr> = tokens.statements().map(|statement| statement.lower_chains().shunting()).collect::<Result<_, _>>()?;
+    match statements.len() {
+        0 => Err(NotEnoughTokens(tokens)),
+        _ => Ok(statements),
+    }
+}
+
+// Evaluates `input` with the default settings, to a number.
+pub fn eval(input: &str) -> Result<f64, MathError> {
+    let mut env = Environment::new();
+    match on_eval_stack(|| Tokens::eval(input, &mut env))? {
+        Some(value) => value.as_num(),
+        None => {
+            // Nothing to evaluate is an error, as it is for `parse`.
+            let tokens = Tokens::parse(input, &env)?;
+            let statements = tokens.statements().count();
+            match statements {
+                0 => Err(NotEnoughTokens(tokens)),
+                _ => Err(Generic(format!("{} has no value", input.trim()))),
+            }
+        },
+    }
+}
+
+// Evaluates a single expression in another number type, with the default settings.
+pub fn eval_in<N: Numeric + Clone>(input: &str, context: N::Context) -> Result<N, MathError> {
+    Environment::new().eval_in(input, context)
+}
+
+// Runs an evaluation on a thread of its own, as the calling thread's stack may be too small
+// for recursive functions.
+fn on_eval_stack<T: Send>(eval: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| {
+        let handle = thread::Builder::new().stack_size(EVAL_STACK_SIZE).spawn_scoped(scope, eval).expect("cannot start evaluation thread");
+        handle.join().unwrap_or_else(|payload| panic::resume_unwind(payload))
+    })
+}
+
+// Where a token was read from, as a range of bytes in the input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+// A token with the span it was read from.
+type Spanned = (Token, Span);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tokens(Vec<Spanned>);
+impl Tokens {
+    fn parse_num(input: &mut Peekable<Chars>, settings: &Settings) -> Result<f64, MathError> {
+        let mut buf = String::new();
+
+        if input.peek() == Some(&'0') {
+            let radix = match input.clone().nth(1) {
+                Some('x') => Some(16),
+                Some('o') => Some(8),
+                Some('b') => Some(2),
+                _ => None,
+            };
+            if let Some(radix) = radix {
+                buf.extend(input.take(2));
+                return Tokens::parse_radix(input, buf, radix);
+            }
+        }
+
+        let digit_follows = |input: &Peekable<Chars>| matches!(input.clone().nth(1), Some('0'..='9'));
+        loop {
+            match input.peek().copied() {
+                Some('0'..='9' | '_') => buf.push(input.next().unwrap()),
+                Some('.') if !settings.comma => buf.push(input.next().unwrap()),
+                Some(',' | '.') if settings.comma && !buf.contains(',') && digit_follows(input) => buf.push(input.next().unwrap()),
+                Some(',') if settings.thousands && !buf.contains('.') && digit_follows(input) => buf.push(input.next().unwrap()),
+                _ => break,
+            }
+        }
+        if settings.comma {
+            // Swapped around, `1.234,5` reads as `1,234.5`.
+            let swapped: String = buf.chars().map(|chr| match chr {
+                '.' => ',',
+                ',' => '.',
+                chr => chr,
+            }).collect();
+            buf = match swapped.contains(',') {
+                true => Tokens::strip_grouping(swapped).map_err(|_| BadGrouping(buf))?,
+                false => swapped,
+            };
+        } else if buf.contains(',') {
+            buf = Tokens::strip_grouping(buf)?;
+        }
+        if input.peek() == Some(&'#') && !buf.is_empty() && buf.chars().all(|chr| chr.is_ascii_digit()) {
+            let radix = match buf.parse::<u32>() {
+                Ok(radix) if (2..=36).contains(&radix) => radix,
+                _ => return Err(BadRadix(buf)),
+            };
+            buf.push(input.next().unwrap());
+            return Tokens::parse_radix(input, buf, radix);
+        }
+        if let Some(exp) = input.next_if(|&chr| chr == 'e' || chr == 'E') {
+            buf.push(exp);
+            if let Some(sign) = input.next_if(|&chr| chr == '+' || chr == '-') {
+                buf.push(sign);
+            }
+            if !matches!(input.peek(), Some('0'..='9')) {
+                return Err(ParseNum(buf, None));
+            }
+            while matches!(input.peek(), Some('0'..='9' | '.' | '_')) {
+                buf.push(input.next().unwrap());
+            }
+        }
+        let float = match Tokens::strip_separators(&buf, &buf, 10)?.parse::<f64>() {
+            Ok(float) => float,
+            Err(error) => return Err(ParseNum(buf, Some(error)))
+        };
+        let suffix: String = input.clone().take_while(|chr| chr.is_alphabetic()).collect();
+        match SI_SUFFIXES.iter().chain(BYTE_SUFFIXES).find(|(name, _)| *name == suffix) {
+            Some((_, scale)) => {
+                input.nth(suffix.chars().count() - 1);
+                Ok(float * scale)
+            },
+            None => Ok(float),
+        }
+    }
+
+    // Written without a point or exponent, so that `2.0` stays a float in integer-strict mode.
+    fn is_int_literal(literal: &str, float: f64, settings: &Settings) -> bool {
+        let radix = literal.contains('#') || ["0x", "0o", "0b"].iter().any(|prefix| literal.starts_with(prefix));
+        !literal.contains(if settings.comma { ',' } else { '.' }) && (radix || !literal.contains(&['e', 'E'][..]))
+            && float.fract() == 0.0 && float.abs() < i64::MAX as f64
+    }
+
+    // An integer literal past 2^53, where a float would round it, read digit by digit instead.
+    fn exact_int(literal: &str, float: f64, settings: &Settings) -> Option<BigInt> {
+        if float.abs() < EXACT_INTEGER_LIMIT || literal.contains(if settings.comma { ',' } else { '.' }) {
+            return None;
+        }
+        let (digits, radix) = match literal.get(..2) {
+            Some("0x") => (&literal[2..], 16),
+            Some("0o") => (&literal[2..], 8),
+            Some("0b") => (&literal[2..], 2),
+            _ => match literal.split_once('#') {
+                Some((radix, digits)) => (digits, radix.parse().ok()?),
+                None => (literal, 10),
+            },
+        };
+        let digits: String = digits.chars().filter(|chr| !matches!(chr, '_' | ',' | '.')).collect();
+        // Exponents and suffixes aren't digits, and leave the float as it is.
+        if !digits.chars().all(|chr| chr.is_digit(radix)) {
+            return None;
+        }
+        match radix {
+            10 => Some(BigInt::new(false, BigUint::parse(&digits))),
+            _ => u128::from_str_radix(&digits, radix).ok().map(|int| BigInt::new(false, BigUint::from_u128(int))),
+        }
+    }
+
+    // An ISO `YYYY-MM-DD` date, written without spaces so that it isn't read as subtraction.
+    fn is_date(input: &Peekable<Chars>) -> bool {
+        let literal: Vec<char> = input.clone().take(11).collect();
+        literal.len() >= 10 && literal.get(10).is_none_or(|chr| !chr.is_ascii_alphanumeric())
+            && literal[..10].iter().enumerate().all(|(i, chr)| match i {
+                4 | 7 => *chr == '-',
+                _ => chr.is_ascii_digit(),
+            })
+    }
+
+    fn parse_date(input: &mut Peekable<Chars>) -> Result<i64, MathError> {
+        let literal: String = input.take(10).collect();
+        let field = |range: std::ops::Range<usize>| literal[range].parse::<i64>().unwrap();
+        date_to_days(field(0..4), field(5..7), field(8..10))
+    }
+
+    // A time suffix attached to a number, as in `90min`, or several run together, as in `2h30min`.
+    fn parse_duration(mut float: f64, input: &mut Peekable<Chars>, settings: &Settings) -> Result<Option<f64>, MathError> {
+        let suffix = |input: &Peekable<Chars>| {
+            let suffix: String = input.clone().take_while(|chr| chr.is_alphabetic()).collect();
+            DURATION_SUFFIXES.iter().find(|(name, _)| *name == suffix).map(|(name, scale)| (name.len(), *scale))
+        };
+        let mut seconds = None;
+        while let Some((len, scale)) = suffix(input) {
+            input.nth(len - 1);
+            seconds = Some(seconds.unwrap_or(0.0) + float * scale);
+            if !matches!(input.peek(), Some('0'..='9')) {
+                break;
+            }
+            let mut lookahead = input.clone();
+            float = Tokens::parse_num(&mut lookahead, settings)?;
+            if suffix(&lookahead).is_none() {
+                break;
+            }
+            *input = lookahead;
+        }
+        Ok(seconds)
+    }
+
+    // A unit name following a number literal, with an optional integer power such as `m^2`.
+    fn parse_unit(input: &mut Peekable<Chars>, env: &Environment) -> Option<Token> {
+        let mut lookahead = input.clone();
+        while lookahead.next_if(|chr| *chr == ' ' || *chr == '\t').is_some() {}
+        if !matches!(lookahead.peek(), Some('a'..='z' | 'A'..='Z' | '_')) {
+            return None;
+        }
+        let unit = env.get_unit(&Tokens::parse_ident(&mut lookahead))?;
+        if lookahead.clone().find(|chr| !chr.is_whitespace()) == Some('(') {
+            return None;
+        }
+        let mut power = 1;
+        if lookahead.peek() == Some(&'^') {
+            let mut exponent = lookahead.clone();
+            exponent.next();
+            let sign = if exponent.next_if_eq(&'-').is_some() { -1 } else { 1 };
+            let digits: String = exponent.clone().take_while(char::is_ascii_digit).collect();
+            if let Ok(int) = digits.parse::<i32>() {
+                exponent.nth(digits.len() - 1);
+                power = sign * int;
+                lookahead = exponent;
+            }
+        }
+        *input = lookahead;
+        Some(Unit(unit, power))
+    }
+
+    fn parse_radix(input: &mut Peekable<Chars>, mut buf: String, radix: u32) -> Result<f64, MathError> {
+        let prefix_len = buf.len();
+
+        while matches!(input.peek(), Some('a'..='z' | 'A'..='Z' | '0'..='9' | '_')) {
+            buf.push(input.next().unwrap());
+        }
+        let digits = Tokens::strip_separators(&buf, &buf[prefix_len..], radix)?;
+        if let Some(digit) = digits.chars().find(|chr| !chr.is_digit(radix)) {
+            return Err(BadDigit(digit, radix));
+        }
+        match u128::from_str_radix(&digits, radix) {
+            Ok(int) => Ok(int as f64),
+            Err(error) => Err(ParseRadix(buf, radix, error)),
+        }
+    }
+
+    // With thousands grouping on, `1,234,567.89` is one literal: a comma directly followed by a
+    // digit groups the integer part, so function arguments need a space after the comma.
+    fn strip_grouping(buf: String) -> Result<String, MathError> {
+        let mut groups = buf.split(',');
+        let valid = groups.next().is_some_and(|first| (1..=3).contains(&first.len()))
+            && groups.all(|group| group.chars().take_while(char::is_ascii_digit).count() == 3);
+        match valid && !buf.contains('_') {
+            true => Ok(buf.replace(',', "")),
+            false => Err(BadGrouping(buf)),
+        }
+    }
+
+    // Underscores are only allowed between two digits: `1_000` but not `_1`, `1_` or `1__0`.
+    fn strip_separators(literal: &str, digits: &str, radix: u32) -> Result<String, MathError> {
+        let chars: Vec<char> = digits.chars().collect();
+        let is_digit = |idx: usize| chars.get(idx).is_some_and(|chr| chr.is_digit(radix));
+        for i in (0..chars.len()).filter(|&i| chars[i] == '_') {
+            if i == 0 || !is_digit(i - 1) || !is_digit(i + 1) {
+                return Err(BadSeparator(literal.to_string()));
+            }
+        }
+        Ok(digits.replace('_', ""))
+    }
+
+    fn parse_ident(input: &mut Peekable<Chars>) -> String {
+        let mut buf = String::new();
+
+        while matches!(input.peek(), Some('a'..='z' | 'A'..='Z' | '0'..='9' | '_')) {
+            buf.push(input.next().unwrap());
+        }
+        buf
+    }
+
+    fn in_abs_bar(tokens: &[Token]) -> bool {
+        let mut depth = 0;
+        for token in tokens.iter().rev() {
+            match token {
+                ParenClose(_) => depth += 1,
+                ParenOpen(paren) if depth == 0 => return *paren == Paren::Bar,
+                ParenOpen(_) => depth -= 1,
+                _ => (),
+            }
+        }
+        false
+    }
+
+    // Reads the `name =` part of `let name = value in body`.
+    fn parse_let(input: &mut Peekable<Chars>) -> Result<String, MathError> {
+        while input.next_if(|chr| chr.is_whitespace()).is_some() {}
+        let name = match input.peek() {
+            Some('a'..='z' | 'A'..='Z' | '_') => Tokens::parse_ident(input),
+            _ => return Err(BadLet),
+        };
+        while input.next_if(|chr| chr.is_whitespace()).is_some() {}
+        match (input.next(), input.peek()) {
+            (Some('='), Some(next)) if *next != '=' => Ok(name),
+            _ => Err(BadLet),
+        }
+    }
+
+    // Errors in reading a token are located at it.
+    fn parse(input: &str, env: &Environment) -> Result<Self, MathError> {
+        let offset = |chars: &Peekable<Chars>| input.len() - chars.clone().map(char::len_utf8).sum::<usize>();
+        let mut chars = input.chars().peekable();
+        let (mut tokens, mut spans) = (Vec::<Token>::new(), Vec::<Span>::new());
+        loop {
+            let start = offset(&chars);
+            match Tokens::lex_token(&mut chars, &mut tokens, env) {
+                Ok(true) => spans.resize(tokens.len(), Span { start, end: offset(&chars) }),
+                Ok(false) => return Ok(Tokens(tokens.into_iter().zip(spans).collect())),
+                Err(error) => {
+                    let end = offset(&chars).max(start + input[start..].chars().next().map_or(0, char::len_utf8));
+                    return Err(error.at(Span { start, end }));
+                },
+            }
+        }
+    }
+
+    // Reads the next token, or a few for shorthands like `|x|`. `false` at the end of the input.
+    fn lex_token(chars: &mut Peekable<Chars>, tokens: &mut Vec<Token>, env: &Environment) -> Result<bool, MathError> {
+        let settings = &env.settings;
+        match chars.peek().copied() {
+            Some('0'..='9') if Tokens::is_date(chars) => tokens.push(Date(Tokens::parse_date(chars)?)),
+            Some('0'..='9' | '.') => {
+                let start = chars.clone();
+                let float = Tokens::parse_num(chars, settings)?;
+                let literal: String = start.clone().take(start.count() - chars.clone().count()).collect();
+                match Tokens::parse_duration(float, chars, settings)? {
+                    Some(seconds) => tokens.push(Duration(seconds)),
+                    None => {
+                        match Tokens::exact_int(&literal, float, settings) {
+                            Some(int) => tokens.push(Token::BigInt(int)),
+                            None if settings.integer && Tokens::is_int_literal(&literal, float, settings) => tokens.push(Int(float as i64)),
+                            None => tokens.push(Num(float)),
+                        }
+                        if let Some(unit) = Tokens::parse_unit(chars, env) {
+                            tokens.push(unit);
+                        }
+                    },
+                }
+            },
+            Some('a'..='z' | 'A'..='Z' | '_') => {
+                let ident = Tokens::parse_ident(chars);
+                if ident.starts_with('_') && ident.chars().all(|chr| chr == '_' || chr.is_ascii_digit()) {
+                    return Err(BadSeparator(ident));
+                }
+                match ident.as_str() {
+                    "xor" => tokens.push(Oper(Operator::BitXor)),
+                    "inf" | "nan" if !settings.ieee => return Err(NeedsIeee(ident)),
+                    "inf" => tokens.push(Num(f64::INFINITY)),
+                    "nan" => tokens.push(Num(f64::NAN)),
+                    "let" => tokens.push(Let(Tokens::parse_let(chars)?)),
+                    "in" => tokens.push(In),
+                    _ if chars.clone().find(|chr| !chr.is_whitespace()) != Some('(') => {
+                        // A constant being assigned to stays a name, so the assignment can be refused.
+                        let next: String = chars.clone().skip_while(|chr| chr.is_whitespace()).take(2).collect();
+                        let assigned = next.starts_with('=') && next != "==" || ["+=", "-=", "*=", "/="].contains(&next.as_str());
+                        match env.get_const(&ident).filter(|_| !assigned) {
+                            Some(value) => tokens.push(Num(value)),
+                            None => tokens.push(Var(ident)),
+                        }
+                    },
+                    _ => match Function::from_name(&ident) {
+                        Some(func) => tokens.push(Func(func, 0)),
+                        None => tokens.push(Call(ident, 0)),
+                    },
+                }
+            },
+            Some('-') if chars.clone().nth(1) == Some('>') => {
+                chars.nth(1).unwrap();
+                tokens.push(Arrow);
+            },
+            Some('-' | '−') if tokens.last().is_none_or(Token::precedes_operand) => {
+                chars.next().unwrap();
+                tokens.push(Unary(UnaryOperator::Neg));
+            },
+            Some('√') => {
+                chars.next().unwrap();
+                tokens.push(Unary(UnaryOperator::Sqrt));
+            },
+            Some('π') => {
+                chars.next().unwrap();
+                tokens.push(Num(consts::PI));
+            },
+            Some('+' | '-' | '*' | '/') if chars.clone().nth(1) == Some('=') => {
+                let oper = Operator::from_char(chars.next().unwrap());
+                chars.next().unwrap();
+                tokens.push(Assign(Some(oper)));
+            },
+            Some('=' | '!') if chars.clone().nth(1) == Some('=') => {
+                match chars.next().unwrap() {
+                    '=' => tokens.push(Oper(Operator::Eq)),
+                    _ => tokens.push(Oper(Operator::Ne)),
+                }
+                chars.next().unwrap();
+            },
+            Some('<' | '>') => {
+                let chr = chars.next().unwrap();
+                match (chr, chars.next_if(|&next| next == '=' || next == chr)) {
+                    ('<', None) => tokens.push(Oper(Operator::Lt)),
+                    ('<', Some('=')) => tokens.push(Oper(Operator::Le)),
+                    ('<', Some(_)) => tokens.push(Oper(Operator::Shl)),
+                    ('>', None) => tokens.push(Oper(Operator::Gt)),
+                    ('>', Some('=')) => tokens.push(Oper(Operator::Ge)),
+                    _ => tokens.push(Oper(Operator::Shr)),
+                }
+            },
+            Some('&' | '|') if chars.clone().nth(1) == chars.peek().copied()
+                    && !(chars.peek() == Some(&'|') && (Tokens::in_abs_bar(tokens) || tokens.last().is_none_or(Token::precedes_operand))) => {
+                match chars.next().unwrap() {
+                    '&' => tokens.push(Oper(Operator::And)),
+                    _ => tokens.push(Oper(Operator::Or)),
+                }
+                chars.next().unwrap();
+            },
+            Some('&') => {
+                chars.next().unwrap();
+                tokens.push(Oper(Operator::BitAnd));
+            },
+            // `|x|` is shorthand for `abs(x)`: a bar opens where an operand is expected, even
+            // doubled as in `||x| - 1|`, and closes when the innermost open group is a bar,
+            // otherwise it is bitwise or.
+            Some('|') if tokens.last().is_none_or(Token::precedes_operand) => {
+                chars.next().unwrap();
+                tokens.push(Func(Function::Abs, 0));
+                tokens.push(ParenOpen(Paren::Bar));
+            },
+            Some('|') if Tokens::in_abs_bar(tokens) => {
+                chars.next().unwrap();
+                tokens.push(ParenClose(Paren::Bar));
+            },
+            Some('|') => {
+                chars.next().unwrap();
+                tokens.push(Oper(Operator::BitOr));
+            },
+            Some('!') if tokens.last().is_none_or(Token::precedes_operand) => {
+                chars.next().unwrap();
+                tokens.push(Unary(UnaryOperator::Not));
+            },
+            Some('!') => {
+                chars.next().unwrap();
+                tokens.push(Postfix(PostfixOperator::Factorial));
+            },
+            Some('/') => {
+                chars.next().unwrap();
+                match chars.next_if_eq(&'/') {
+                    Some(_) => tokens.push(Oper(Operator::FloorDiv)),
+                    None => tokens.push(Oper(Operator::Div)),
+                }
+            },
+            Some('+' | '-' | '−' | '*' | '×' | '÷' | '%' | '^' | '±' | '(' | ')' | '[' | ']' | '{' | '}' | ',' | '?' | ':' | ';') => tokens.push(Token::from_char(chars.next().unwrap())),
+            Some('=') => {
+                chars.next().unwrap();
+                tokens.push(Assign(None));
+            },
+            // `//` is floor division, so `#` is the only comment marker.
+            Some('#') => {
+                while chars.next_if(|&chr| chr != '\n').is_some() {}
+            },
+            Some(chr) if chr.is_whitespace() => {
+                chars.next().unwrap();
+            },
+            Some(badchar) => return Err(BadChar(badchar)),
+            None => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // Rewrites `a < b <= c` into `(a < b && b <= c)`, one chain per pass so nested chains are
+    // lowered too once their enclosing chain has been duplicated.
+    fn lower_chains(self) -> Self {
+        let mut tokens = self.0;
+        while let Some((start, ops, end)) = Tokens::find_chain(&tokens) {
+            let mut lowered = vec![(ParenOpen(Paren::Round), tokens[start].1)];
+            let mut left = start;
+            for (n, op) in ops.iter().enumerate() {
+                let right = ops.get(n + 1).copied().unwrap_or(end);
+                if n > 0 {
+                    lowered.push((Oper(Operator::And), tokens[ops[n - 1]].1));
+                }
+                lowered.extend_from_slice(&tokens[left..right]);
+                left = op + 1;
+            }
+            lowered.push((ParenClose(Paren::Round), tokens[end - 1].1));
+            tokens.splice(start..end, lowered);
+        }
+        Tokens(tokens)
+    }
+
+    fn find_chain(tokens: &[Spanned]) -> Option<(usize, Vec<usize>, usize)> {
+        let is_relational = |token: &Token| matches!(token, Oper(oper) if oper.is_relational());
+        for first in (0..tokens.len()).filter(|&i| is_relational(&tokens[i].0)) {
+            let mut ops = vec![first];
+            let (mut depth, mut end) = (0, tokens.len());
+            for (i, (token, _)) in tokens.iter().enumerate().skip(first + 1) {
+                match token {
+                    ParenOpen(_) => depth += 1,
+                    ParenClose(_) if depth == 0 => { end = i; break; },
+                    ParenClose(_) => depth -= 1,
+                    _ if depth > 0 => (),
+                    _ if is_relational(token) => ops.push(i),
+                    _ if token.is_chain_boundary() => { end = i; break; },
+                    _ => (),
+                }
+            }
+            if ops.len() < 2 {
+                continue;
+            }
+            let (mut depth, mut start) = (0, 0);
+            for (i, (token, _)) in tokens.iter().enumerate().take(first).rev() {
+                match token {
+                    ParenClose(_) => depth += 1,
+                    ParenOpen(_) if depth == 0 => { start = i + 1; break; },
+                    ParenOpen(_) => depth -= 1,
+                    _ if depth > 0 => (),
+                    _ if token.is_chain_boundary() => { start = i + 1; break; },
+                    _ => (),
+                }
+            }
+            return Some((start, ops, end));
+        }
+        None
+    }
+
+    fn in_equation(op_stack: &[Spanned]) -> bool {
+        let open = op_stack.iter().rposition(|(token, _)| matches!(token, ParenOpen(_)));
+        matches!(open.and_then(|i| op_stack.get(i.checked_sub(1)?)), Some((Func(func, _), _)) if func.takes_equation())
+    }
+
+    // Builds the tree of a statement, each operator taking its operands from the output as it
+    // leaves the stack. Nodes that can fail are wrapped in the span of the token they came from.
+    fn shunting(self) -> Result<Expr, MathError> {
+        let mut op_stack = Vec::<Spanned>::new();
+        let mut output = Vec::<Expr>::new();
+        // One entry per open paren: where each argument starts in the output if it belongs to a
+        // function call or a list.
+        let mut arg_starts = Vec::<Option<Vec<usize>>>::new();
+        // The names of the `let`s whose `in` has been read, innermost last.
+        let mut names = Vec::<String>::new();
+        let mut prev: Option<&Token> = None;
+        let whole = self.span();
+
+        for &(ref token, span) in &self.0 {
+            match token {
+                Num(float) => output.push(Expr::Num(*float)),
+                Int(int) => output.push(Expr::Int(*int)),
+                Token::BigInt(int) => output.push(Expr::BigInt(int.clone())),
+                Var(name) => output.push(Expr::Var(name.clone()).at(span)),
+                Duration(seconds) => output.push(Expr::Duration(*seconds)),
+                Date(days) => output.push(Expr::Date(*days)),
+                Postfix(oper) => match output.pop() {
+                    Some(x) => output.push(Expr::Postfix(*oper, Box::new(x)).at(span)),
+                    None => return Err(NotEnoughTokens(self).at(span)),
+                },
+                Unit(unit, power) => match output.pop() {
+                    Some(x) => output.push(Expr::Unit(Box::new(x), unit.clone(), *power).at(span)),
+                    None => return Err(NotEnoughTokens(self).at(span)),
+                },
+                Unary(_) | Func(..) | Call(..) => op_stack.push((token.clone(), span)),
+                ParenOpen(open) => {
+                    match (prev, open) {
+                        (Some(Func(..) | Call(..)), _) | (_, Paren::Square) => arg_starts.push(Some(vec![output.len()])),
+                        _ => arg_starts.push(None),
+                    }
+                    op_stack.push((token.clone(), span));
+                },
+                ParenClose(close) => {
+                    while op_stack.last().is_some_and(|(top, _)| !matches!(top, ParenOpen(_))) {
+                        match op_stack.pop().unwrap() {
+                            (TernaryIf, at) => return Err(UnmatchedTernary(self).at(at)),
+                            (Let(_), at) => return Err(UnmatchedLet(self).at(at)),
+                            top => self.reduce(top, &mut output, &mut names)?,
+                        }
+                    }
+                    let open_span = match op_stack.pop() {
+                        Some((ParenOpen(open), _)) if open != *close => return Err(MismatchedParens(open, *close).at(span)),
+                        Some((_, open_span)) => open_span,
+                        None => return Err(UnmatchedParens(self).at(span)),
+                    };
+                    if let Some(starts) = arg_starts.pop().unwrap() {
+                        // Each argument must have made one tree.
+                        let args = match prev {
+                            Some(ParenOpen(_)) => Vec::new(),
+                            _ if output.len() == starts.last().unwrap() + 1 => output.split_off(starts[0]),
+                            _ => return Err(NotEnoughTokens(self).at(span)),
+                        };
+                        if *close == Paren::Square {
+                            output.push(Expr::List(args).at(Span { start: open_span.start, end: span.end }));
+                        } else {
+                            match op_stack.pop() {
+                                Some((Func(func, _), at)) if func.is_lazy() => output.push(Expr::Lazy(func, args).at(at)),
+                                Some((Func(func, _), at)) => output.push(Expr::Call(func, args).at(at)),
+                                Some((Call(name, _), at)) => output.push(Expr::UserCall(name, args).at(at)),
+                                _ => return Err(UnmatchedParens(self).at(span)),
+                            }
+                        }
+                    }
+                },
+                Comma => {
+                    while op_stack.last().is_some_and(|(top, _)| !matches!(top, ParenOpen(_))) {
+                        match op_stack.pop().unwrap() {
+                            (TernaryIf, at) => return Err(UnmatchedTernary(self).at(at)),
+                            (Let(_), at) => return Err(UnmatchedLet(self).at(at)),
+                            top => self.reduce(top, &mut output, &mut names)?,
+                        }
+                    }
+                    match arg_starts.last_mut() {
+                        Some(Some(starts)) if output.len() == starts.last().unwrap() + 1 => starts.push(output.len()),
+                        Some(Some(_)) => return Err(NotEnoughTokens(self).at(span)),
+                        _ => return Err(MisplacedComma(self).at(span)),
+                    }
+                },
+                // `?` and `:` stay on the operator stack like a pair of parens; once `:` replaces
+                // its `?`, the marker makes the conditional when something lower pops it.
+                TernaryIf => {
+                    while op_stack.last().is_some_and(|(top, _)| matches!(top, Oper(_) | Unary(_))) {
+                        self.reduce(op_stack.pop().unwrap(), &mut output, &mut names)?;
+                    }
+                    op_stack.push((token.clone(), span));
+                },
+                TernaryElse => {
+                    loop {
+                        match op_stack.pop() {
+                            Some((TernaryIf, _)) => break,
+                            Some((ParenOpen(_), _)) | None => return Err(UnmatchedTernary(self).at(span)),
+                            Some((Let(_), at)) => return Err(UnmatchedLet(self).at(at)),
+                            Some(top) => self.reduce(top, &mut output, &mut names)?,
+                        }
+                    }
+                    op_stack.push((token.clone(), span));
+                },
+                // `let` waits on the stack for its `in`, which then stays on the stack until the
+                // body ends, where it makes the binding of the value to the body.
+                Let(_) => op_stack.push((token.clone(), span)),
+                In => {
+                    loop {
+                        match op_stack.pop() {
+                            Some((Let(name), _)) => break names.push(name),
+                            Some((ParenOpen(_) | TernaryIf, _)) | None => return Err(UnmatchedLet(self).at(span)),
+                            Some(top) => self.reduce(top, &mut output, &mut names)?,
+                        }
+                    }
+                    op_stack.push((In, span));
+                },
+                Oper(_) => {
+                    while op_stack.last().is_some_and(|(top, _)| token.is_lower(top)) {
+                        self.reduce(op_stack.pop().unwrap(), &mut output, &mut names)?;
+                    }
+                    op_stack.push((token.clone(), span));
+                },
+                // As loose as a comma, and only in the argument of a function that takes it.
+                Assign(None) if Tokens::in_equation(&op_stack) => {
+                    while op_stack.last().is_some_and(|(top, _)| matches!(top, Oper(_) | Unary(_))) {
+                        self.reduce(op_stack.pop().unwrap(), &mut output, &mut names)?;
+                    }
+                    op_stack.push((Equation, span));
+                },
+                Assign(_) => return Err(MisplacedAssign(self).at(span)),
+                Arrow => return Err(MisplacedArrow(self).at(span)),
+                Semicolon | Equation => return Err(UnexpectedToken(token.clone()).at(span)),
+            }
+            prev = Some(token);
+        }
+        while let Some((top, span)) = op_stack.pop() {
+            match top {
+                ParenOpen(_) => return Err(UnclosedParens(self).at(span)),
+                TernaryIf => return Err(UnmatchedTernary(self).at(span)),
+                Let(_) => return Err(UnmatchedLet(self).at(span)),
+                Oper(_) | Unary(_) | TernaryElse | In => self.reduce((top, span), &mut output, &mut names)?,
+                // Left by `=` in an argument whose paren never closed.
+                Equation => return Err(UnclosedParens(self).at(span)),
+                _ => return Err(UnexpectedToken(top).at(span)),
+            }
+        }
+        match (output.pop(), output.is_empty(), whole) {
+            (Some(expr), true, _) => Ok(expr),
+            (_, _, Some(span)) => Err(NotEnoughTokens(self).at(span)),
+            _ => Err(NotEnoughTokens(self)),
+        }
+    }
+
+    // Replaces the last trees in the output with an operator leaving the stack applied to them.
+    fn reduce(&self, (op, span): Spanned, output: &mut Vec<Expr>, names: &mut Vec<String>) -> Result<(), MathError> {
+        let argc = match op {
+            Unary(_) => 1,
+            TernaryElse => 3,
+            _ => 2,
+        };
+        if output.len() < argc {
+            return Err(NotEnoughTokens(self.clone()).at(span));
+        }
+        let mut pop = || Box::new(output.pop().unwrap());
+        let expr = match op {
+            Oper(oper) => {
+                let (y, x) = (pop(), pop());
+                Expr::BinaryOp(oper, x, y).at(span)
+            },
+            // Zero where the equation holds.
+            Equation => {
+                let (rhs, lhs) = (pop(), pop());
+                Expr::BinaryOp(Operator::Sub, lhs, rhs).at(span)
+            },
+            Unary(oper) => Expr::UnaryOp(oper, pop()).at(span),
+            TernaryElse => {
+                let (other, then, cond) = (pop(), pop(), pop());
+                Expr::Ternary(cond, then, other).at(span)
+            },
+            In => {
+                let (body, value) = (pop(), pop());
+                Expr::Let(names.pop().unwrap(), value, body)
+            },
+            op => return Err(UnexpectedToken(op).at(span)),
+        };
+        output.push(expr);
+        Ok(())
+    }
+
+    // From the start of the first token to the end of the last.
+    fn span(&self) -> Option<Span> {
+        Some(Span { start: self.0.first()?.1.start, end: self.0.last()?.1.end })
+    }
+
+    fn eval(input: &str, env: &mut Environment) -> Result<Option<Value>, MathError> {
+        // `expr @ hex` shows an integer result in that base.
+        if let Some((expr, radix)) = input.rsplit_once('@').and_then(|(expr, base)| Some((expr, Settings::parse_base(base.trim())?))) {
+            let suffix = Span { start: expr.len(), end: input.trim_end().len() };
+            return match Tokens::eval(expr, env)? {
+                Some(value) => Ok(Some(Value::Radix(value.as_exact_int().map_err(|error| error.at(suffix))?, radix))),
+                None => Ok(None),
+            };
+        }
+        // Like a blank line, one holding only a comment shows nothing.
+        let mut result = None;
+        for statement in Tokens::parse(input, env)?.statements() {
+            result = statement.exec(env)?;
+        }
+        Ok(result)
+    }
+
+    // The statements between semicolons, leaving out empty ones.
+    fn statements(&self) -> impl Iterator<Item = Tokens> + '_ {
+        self.0.split(|(token, _)| *token == Semicolon).filter(|tokens| !tokens.is_empty()).map(|tokens| Tokens(tokens.to_vec()))
+    }
+
+    fn exec(self, env: &mut Environment) -> Result<Option<Value>, MathError> {
+        let tokens = self.lower_chains();
+        if let [(Var(name), span), (Assign(_), _), ..] = &tokens.0[..] {
+            if env.get_const(name).is_some() {
+                return Err(ReadOnly(name.clone()).at(*span));
+            }
+        }
+        if let Some((name, params, body)) = Tokens::split_function(&tokens.0) {
+            // Its spans are in this input, not in those that later call it.
+            let source = Tokens(body.to_vec()).shunting()?.unlocated();
+            let body = source.clone().fold_constants(env);
+            env.define(name, Lambda { params, source, body });
+            return Ok(None);
+        }
+        match &tokens.0[..] {
+            [(Var(name), name_span), (Assign(oper), span), rest @ ..] => {
+                let mut value = Tokens(rest.to_vec()).evaluate(env)?;
+                if let Some(oper) = oper {
+                    match env.get(name) {
+                        Some(current) => value = oper.apply(current, value, &env.settings).map_err(|error| error.at(*span))?,
+                        None => return Err(UnknownIdent(name.clone()).at(*name_span)),
+                    }
+                }
+                env.set(name, value.clone());
+                Ok(Some(value))
+            },
+            _ => Ok(Some(tokens.evaluate(env)?)),
+        }
+    }
+
+    fn evaluate(self, env: &Environment) -> Result<Value, MathError> {
+        match Tokens::split_conversion(&self.0) {
+            Some((expr, span, target)) => {
+                let value = Tokens(expr.to_vec()).evaluate(env)?;
+                value.convert(Tokens(target.to_vec()).shunting()?.solve(env)?).map_err(|error| error.at(span))
+            },
+            None => {
+                let whole = self.span();
+                let tree = self.shunting()?;
+                let precision = env.settings.precision;
+                if let Some(fixed) = Some(()).filter(|_| env.settings.decimal).and_then(|_| tree.solve_in::<Fixed>(env, ())) {
+                    return Ok(Value::Decimal(fixed.to_decimal()));
+                }
+                if let Some(float) = Some(env.settings.angle).filter(|_| env.settings.f32).and_then(|angle| tree.solve_in::<f32>(env, angle)) {
+                    return Value::Single(float).finite(&env.settings, || tree.to_string());
+                }
+                if let Some(q) = env.settings.qformat.and_then(|format| tree.solve_in::<QNum>(env, format)) {
+                    if q.overflowed {
+                        env.warn(Warning::Saturated(q.format));
+                    }
+                    return Ok(Value::Q(q));
+                }
+                if let Some(decimal) = precision.and_then(|digits| tree.solve_in::<Decimal>(env, digits + GUARD_DIGITS)) {
+                    return Ok(Value::Decimal(decimal.round(precision.unwrap())));
+                }
+                // Variables without a value leave the expression to be simplified symbolically,
+                // which works without the spans, so its errors are put on the whole statement.
+                let result = match tree.solve(env) {
+                    Err(error) if matches!(error.inner(), UnknownIdent(_) | ExpectedNumber(Value::Symbolic(_))) => match tree.symbolic(env, &[]) {
+                        Some(expr) => match (expr.simplify(&env.settings), whole) {
+                            (Err(error), Some(span)) => Err(error.at(span)),
+                            (simplified, _) => Ok(simplified?.into_value()),
+                        },
+                        None => Err(error),
+                    },
+                    result => result,
+                };
+                if let (Some(digits), Ok(Value::Num(_))) = (precision, &result) {
+                    env.warn(Warning::FloatFallback(digits));
+                }
+                if env.settings.decimal && result.as_ref().is_ok_and(Value::is_float) {
+                    env.warn(Warning::DecimalFallback);
+                }
+                if env.settings.f32 && result.as_ref().is_ok_and(Value::is_float) {
+                    env.warn(Warning::SingleFallback);
+                }
+                // A number still shows in the format, raw integer and all.
+                match (env.settings.qformat, result) {
+                    (Some(format), Ok(Value::Num(float))) => {
+                        let q = format.quantize(float);
+                        env.warn(Warning::QFallback(format));
+                        if q.overflowed {
+                            env.warn(Warning::Saturated(format));
+                        }
+                        Ok(Value::Q(q))
+                    },
+                    (Some(format), Ok(value)) if value.is_float() => {
+                        env.warn(Warning::QFallback(format));
+                        Ok(value)
+                    },
+                    (_, result) => result,
+                }
+            },
+        }
+    }
+
+    // Splits `expr in unit` at the last top-level `in` that doesn't belong to a `let`.
+    fn split_conversion(tokens: &[Spanned]) -> Option<(&[Spanned], Span, &[Spanned])> {
+        let (mut lets, mut depth, mut split) = (0, 0, None);
+        for (i, (token, _)) in tokens.iter().enumerate() {
+            match token {
+                Let(_) => lets += 1,
+                In if lets > 0 => lets -= 1,
+                In if depth == 0 => split = Some(i),
+                ParenOpen(_) => depth += 1,
+                ParenClose(_) => depth -= 1,
+                _ => (),
+            }
+        }
+        split.map(|i| (&tokens[..i], tokens[i].1, &tokens[i + 1..]))
+    }
+
+    // Matches `f(x, y, ...) = body`, `f = (x, y, ...) -> body` and `f = x -> body`.
+    fn split_function(tokens: &[Spanned]) -> Option<(&str, Vec<String>, &[Spanned])> {
+        match tokens {
+            [(Call(name, _), _), rest @ ..] => {
+                let assign = rest.iter().position(|(token, _)| *token == Assign(None))?;
+                Some((name, Tokens::parse_params(&rest[..assign])?, &rest[assign + 1..]))
+            },
+            [(Var(name), _), (Assign(None), _), rest @ ..] => {
+                let arrow = rest.iter().position(|(token, _)| *token == Arrow)?;
+                let params = match &rest[..arrow] {
+                    [(Var(param), _)] => vec![param.clone()],
+                    params => Tokens::parse_params(params)?,
+                };
+                Some((name, params, &rest[arrow + 1..]))
+            },
+            _ => None,
+        }
+    }
+
+    // Matches a parenthesized, comma-separated list of parameter names.
+    fn parse_params(tokens: &[Spanned]) -> Option<Vec<String>> {
+        let params = match tokens {
+            [(ParenOpen(Paren::Round), _), params @ .., (ParenClose(Paren::Round), _)] => params,
+            _ => return None,
+        };
+        if params.len() % 2 == 0 && !params.is_empty() {
+            return None;
+        }
+        let mut names = Vec::new();
+        for (i, (token, _)) in params.iter().enumerate() {
+            match (i % 2, token) {
+                (0, Var(param)) => names.push(param.clone()),
+                (1, Comma) => (),
+                _ => return None,
+            }
+        }
+        Some(names)
+    }
+
+}
+impl fmt::Display for Tokens {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut tokens = self.0.iter().map(|(token, _)| token);
+
+        write!(f, "{{")?;
+        if let Some(token) = tokens.next() {
+            write!(f, "{}", token)?;
+        }
+        for token in tokens {
+            write!(f, ", {}", token)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Num(f64),
+    // An integer to be displayed in the given base.
+    Radix(BigInt, u32),
+    // A fraction in lowest terms, with a positive denominator.
+    Ratio(i64, i64),
+    Decimal(Decimal),
+    // An integer beyond the range floats hold exactly.
+    BigInt(BigInt),
+    // Lower and upper bounds on an uncertain number.
+    Interval(f64, f64),
+    List(Vec<f64>),
+    Matrix(Vec<Vec<f64>>),
+    Quantity(Quantity),
+    // In seconds.
+    Duration(f64),
+    // In days since 1970-01-01.
+    Date(i64),
+    // Coefficients of a polynomial in the named variable, in powers of its offset from a point.
+    Polynomial(String, f64, Vec<f64>),
+    Q(QNum),
+    // An integer in integer-strict mode, as opposed to a float that happens to be whole.
+    Int(i64),
+    // A result of single-precision evaluation.
+    Single(f32),
+    // A value and its standard uncertainty.
+    Uncertain(f64, f64),
+    // An expression in variables that have no value.
+    Symbolic(Expr),
+}
+impl Value {
+    // Numbers in square brackets form a list and equal-length lists form the rows of a matrix,
+    // while a single number in square brackets is only a grouping.
+    fn from_items(items: Vec<Value>) -> Result<Value, MathError> {
+        match &items[..] {
+            [value @ (Value::Num(_) | Value::Radix(..) | Value::Ratio(..) | Value::Decimal(_) | Value::BigInt(_) | Value::Q(_) | Value::Single(_) | Value::Int(_))] => Ok(value.clone()),
+            [Value::List(row), ..] if row.is_empty() => Err(ExpectedNumber(Value::List(Vec::new()))),
+            [Value::List(_), ..] => {
+                let mut rows = Vec::new();
+                for item in items {
+                    match item {
+                        Value::List(row) if row.len() == rows.first().map_or(row.len(), Vec::len) => rows.push(row),
+                        other => return Err(ShapeMismatch(format!("[{}]", rows[0].len()), other.shape())),
+                    }
+                }
+                Ok(Value::Matrix(rows))
+            },
+            _ => Ok(Value::List(items.iter().map(Value::as_num).collect::<Result<_, _>>()?)),
+        }
+    }
+
+    // Keeps the magnitude but displays it in the units of `target`.
+    fn convert(self, target: Value) -> Result<Value, MathError> {
+        let (value, target) = (Quantity::from_value(self)?, Quantity::from_value(target)?);
+        match value.dims() == target.dims() {
+            true => Ok(Quantity { value: value.value, unit: target.unit }.into_value()),
+            false => Err(IncompatibleUnits(value.unit_name(), target.unit_name())),
+        }
+    }
+
+    // Whether it is made of floats, as any result of double-precision evaluation is.
+    fn is_float(&self) -> bool {
+        matches!(self, Value::Num(_) | Value::Interval(..) | Value::List(_) | Value::Matrix(_) | Value::Quantity(_)
+            | Value::Duration(_) | Value::Polynomial(..) | Value::Uncertain(..))
+    }
+
+    fn as_num(&self) -> Result<f64, MathError> {
+        match self {
+            Value::Num(float) => Ok(*float),
+            Value::Radix(int, _) => Ok(int.to_f64()),
+            Value::Ratio(numer, denom) => Ok(*numer as f64 / *denom as f64),
+            Value::Decimal(decimal) => Ok(decimal.to_f64()),
+            Value::BigInt(int) => Ok(int.to_f64()),
+            Value::Q(q) => Ok(q.to_f64()),
+            Value::Single(float) => Ok(*float as f64),
+            Value::Int(int) => Ok(*int as f64),
+            _ => Err(ExpectedNumber(self.clone())),
+        }
+    }
+
+    // Integers that are known exactly: floats only up to 2^53, past which they have gaps.
+    fn as_bigint(&self) -> Option<BigInt> {
+        match self {
+            Value::Num(float) if float.fract() == 0.0 && float.abs() <= EXACT_INTEGER_LIMIT => Some(BigInt::from_i128(*float as i128)),
+            Value::Ratio(int, 1) | Value::Int(int) => Some(BigInt::from_i128(*int as i128)),
+            Value::Radix(int, _) | Value::BigInt(int) => Some(int.clone()),
+            _ => None,
+        }
+    }
+
+    fn as_exact_int(&self) -> Result<BigInt, MathError> {
+        match self.as_bigint() {
+            Some(int) => Ok(int),
+            None => Ok(BigInt::from_i128(as_integer(self.as_num()?)? as i128)),
+        }
+    }
+
+    // A literal as the fraction its decimal digits spell out, or as is if that doesn't fit.
+    // Past 15 significant digits a float need not be what was typed, and is more likely a
+    // constant such as `pi`, so it stays inexact.
+    fn exact(float: f64) -> Value {
+        let ratio = Decimal::from_f64(float).filter(|decimal| decimal.mantissa.magnitude.digits() <= 15).and_then(|decimal| {
+            let numer = decimal.mantissa.magnitude.to_u128().and_then(|numer| i128::try_from(numer).ok())?;
+            let numer = if decimal.mantissa.negative { -numer } else { numer };
+            match decimal.exponent < 0 {
+                true => reduced(numer, 10i128.checked_pow(u32::try_from(-decimal.exponent).ok()?)?),
+                false => reduced(numer.checked_mul(10i128.checked_pow(decimal.exponent as u32)?)?, 1),
+            }
+        });
+        match ratio {
+            Some((numer, denom)) => Value::Ratio(numer, denom),
+            None => Value::Num(float),
+        }
+    }
+
+    fn as_uncertain(&self) -> Result<(f64, f64), MathError> {
+        match self {
+            Value::Uncertain(value, sigma) => Ok((*value, *sigma)),
+            value => value.as_num().map(|x| (x, 0.0)),
+        }
+    }
+
+    fn as_interval(&self) -> Result<(f64, f64), MathError> {
+        match self {
+            Value::Interval(lo, hi) => Ok((*lo, *hi)),
+            value => value.as_num().map(|x| (x, x)),
+        }
+    }
+
+    fn as_ratio(&self) -> Option<(i64, i64)> {
+        match self {
+            Value::Ratio(numer, denom) => Some((*numer, *denom)),
+            Value::Num(float) if float.fract() == 0.0 && float.abs() <= EXACT_INTEGER_LIMIT => Some((*float as i64, 1)),
+            Value::Radix(int, _) => Some((i64::try_from(int.to_i128()?).ok()?, 1)),
+            Value::Int(int) => Some((*int, 1)),
+            _ => None,
+        }
+    }
+
+    // The residue of an integer in modular mode, for results that no operator has reduced:
+    // literals, negations and factorials.
+    fn reduced(self, settings: &Settings) -> Value {
+        match (settings.modulus, &self) {
+            (Some(modulus), Value::Num(_) | Value::Int(_) | Value::BigInt(_)) => match self.as_bigint() {
+                Some(int) => Value::from_bigint(int.divmod(&BigInt::from_i128(modulus as i128)).1),
+                None => self,
+            },
+            _ => self,
+        }
+    }
+
+    fn from_bigint(int: BigInt) -> Value {
+        match int.magnitude.to_u128() {
+            Some(magnitude) if magnitude <= EXACT_INTEGER_LIMIT as u128 => Value::Num(int.to_f64()),
+            _ => Value::BigInt(int),
+        }
+    }
+
+    // Infinities and NaN are errors naming the operation that gave them, unless IEEE semantics
+    // are on.
+    fn finite(self, settings: &Settings, operation: impl FnOnce() -> String) -> Result<Value, MathError> {
+        let non_finite = match &self {
+            _ if settings.ieee => None,
+            Value::Num(x) | Value::Duration(x) => Some(*x),
+            Value::Single(x) => Some(*x as f64),
+            Value::Quantity(quantity) => Some(quantity.value),
+            Value::Interval(lo, hi) => Some(if lo.is_finite() { *hi } else { *lo }),
+            Value::Uncertain(value, sigma) => Some(if value.is_finite() { *sigma } else { *value }),
+            Value::List(list) | Value::Polynomial(_, _, list) => list.iter().copied().find(|x| !x.is_finite()),
+            Value::Matrix(rows) => rows.iter().flatten().copied().find(|x| !x.is_finite()),
+            _ => None,
+        };
+        match non_finite.filter(|x| !x.is_finite()) {
+            Some(x) => Err(NonFinite(operation(), x)),
+            None => Ok(self),
+        }
+    }
+
+    fn shown<'a>(&'a self, settings: &'a Settings) -> Shown<'a> {
+        Shown(self, settings)
+    }
+
+    fn elements(&self) -> Result<Vec<f64>, MathError> {
+        match self {
+            Value::List(list) => Ok(list.clone()),
+            Value::Matrix(rows) => Ok(rows.concat()),
+            _ => Ok(vec![self.as_num()?]),
+        }
+    }
+
+    fn shape(&self) -> String {
+        match self {
+            Value::Num(_) | Value::Radix(..) | Value::Ratio(..) | Value::Decimal(_) | Value::BigInt(_) | Value::Q(_) | Value::Single(_) | Value::Int(_) | Value::Quantity(_) | Value::Duration(_) | Value::Date(_) => String::from("number"),
+            Value::Polynomial(..) => String::from("polynomial"),
+            Value::Interval(..) => String::from("interval"),
+            Value::Uncertain(..) => String::from("uncertain number"),
+            Value::Symbolic(_) => String::from("expression"),
+            Value::List(list) => format!("[{}]", list.len()),
+            Value::Matrix(rows) => format!("[{}x{}]", rows.len(), rows.first().map_or(0, Vec::len)),
+        }
+    }
+
+    fn map(self, f: impl Fn(f64) -> Result<f64, MathError>) -> Result<Value, MathError> {
+        match self {
+            Value::Num(float) => Ok(Value::Num(f(float)?)),
+            Value::Radix(int, _) => Ok(Value::Num(f(int.to_f64())?)),
+            Value::Ratio(numer, denom) => Ok(Value::Num(f(numer as f64 / denom as f64)?)),
+            Value::Decimal(decimal) => Ok(Value::Num(f(decimal.to_f64())?)),
+            Value::BigInt(int) => Ok(Value::Num(f(int.to_f64())?)),
+            Value::Q(q) => Ok(Value::Num(f(q.to_f64())?)),
+            Value::Single(float) => Ok(Value::Num(f(float as f64)?)),
+            Value::Int(int) => Ok(Value::Num(f(int as f64)?)),
+            Value::List(list) => Ok(Value::List(list.into_iter().map(f).collect::<Result<_, _>>()?)),
+            Value::Quantity(_) | Value::Duration(_) | Value::Date(_) | Value::Polynomial(..) | Value::Interval(..) | Value::Uncertain(..) | Value::Symbolic(_) => Err(ExpectedNumber(self)),
+            Value::Matrix(rows) => {
+                let rows = rows.into_iter()
+                    .map(|row| row.into_iter().map(&f).collect::<Result<_, _>>())
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::Matrix(rows))
+            },
+        }
+    }
+
+    // Numbers are broadcast over lists and matrices, which are combined element by element.
+    fn zip(self, other: Value, f: impl Fn(f64, f64) -> Result<f64, MathError>) -> Result<Value, MathError> {
+        let plain = |value| match value {
+            Value::Radix(int, _) => Value::Num(int.to_f64()),
+            Value::Ratio(numer, denom) => Value::Num(numer as f64 / denom as f64),
+            Value::Decimal(decimal) => Value::Num(decimal.to_f64()),
+            Value::BigInt(int) => Value::Num(int.to_f64()),
+            Value::Q(q) => Value::Num(q.to_f64()),
+            Value::Single(float) => Value::Num(float as f64),
+            Value::Int(int) => Value::Num(int as f64),
+            value => value,
+        };
+        match (plain(self), plain(other)) {
+            (Value::Num(x), Value::Num(y)) => Ok(Value::Num(f(x, y)?)),
+            (x, Value::Num(y)) => x.map(|x| f(x, y)),
+            (Value::Num(x), y) => y.map(|y| f(x, y)),
+            (Value::List(xs), Value::List(ys)) if xs.len() == ys.len() => {
+                Ok(Value::List(xs.into_iter().zip(ys).map(|(x, y)| f(x, y)).collect::<Result<_, _>>()?))
+            },
+            (Value::Matrix(xs), Value::Matrix(ys)) if xs.len() == ys.len() && xs[0].len() == ys[0].len() => {
+                let rows = xs.into_iter().zip(ys)
+                    .map(|(xs, ys)| xs.into_iter().zip(ys).map(|(x, y)| f(x, y)).collect::<Result<_, _>>())
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::Matrix(rows))
+            },
+            (x, y) => Err(ShapeMismatch(x.shape(), y.shape())),
+        }
+    }
+
+}
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.shown(&Settings::new()))
+    }
+}
+
+// A value written out as the settings say.
+struct Shown<'a>(&'a Value, &'a Settings);
+impl fmt::Display for Shown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let settings = self.1;
+        let list = |list: &[f64]| list.iter().map(|&float| settings.format_num(float)).collect::<Vec<_>>().join(", ");
+        if let Some(radix) = settings.outbase {
+            if let (Value::Num(_) | Value::Int(_) | Value::Ratio(_, 1) | Value::BigInt(_), Some(int)) = (self.0, self.0.as_bigint()) {
+                return write!(f, "{}", Value::Radix(int, radix));
+            }
+        }
+        match self.0 {
+            Value::Num(float) => write!(f, "{}", settings.format_num(*float)),
+            Value::Radix(int, radix) => {
+                let sign = if int.negative { "-" } else { "" };
+                let prefix = match radix {
+                    2 => "0b",
+                    8 => "0o",
+                    10 => "",
+                    _ => "0x",
+                };
+                write!(f, "{}{}{}", sign, prefix, int.magnitude.to_radix(*radix))
+            },
+            Value::Ratio(numer, 1) => write!(f, "{}", numer),
+            Value::Ratio(numer, denom) => write!(f, "{}/{}", numer, denom),
+            Value::Decimal(decimal) => write!(f, "{}", settings.localize(decimal.to_string())),
+            Value::BigInt(int) => write!(f, "{}", settings.localize(int.to_string())),
+            // Widened through its shortest digits, so no noise from the extra precision shows.
+            Value::Single(float) => write!(f, "{}", settings.format_num(float.to_string().parse().unwrap_or(*float as f64))),
+            Value::Int(int) => write!(f, "{}", settings.localize(int.to_string())),
+            Value::Q(q) => {
+                let bits = 1 + q.format.int_bits + q.format.frac_bits;
+                let raw = q.raw as u64 & (u64::MAX >> (64 - bits));
+                write!(f, "{} ({} raw {:#0width$x} = {})", q.to_f64(), q.format, raw, q.raw, width = 2 + (bits as usize).div_ceil(4))
+            },
+            Value::Interval(lo, hi) => write!(f, "[{}, {}]", settings.format_num(*lo), settings.format_num(*hi)),
+            Value::Uncertain(value, sigma) => write!(f, "{} ± {}", settings.format_num(*value), settings.format_num(*sigma)),
+            Value::Symbolic(expr) => write!(f, "{}", expr),
+            Value::List(elements) => write!(f, "[{}]", list(elements)),
+            Value::Matrix(rows) => {
+                let rows: Vec<String> = rows.iter().map(|row| format!("[{}]", list(row))).collect();
+                write!(f, "[{}]", rows.join(", "))
+            },
+            Value::Quantity(quantity) => write!(f, "{} {}", settings.format_num(quantity.value / quantity.scale()), quantity.unit_name()),
+            Value::Duration(seconds) => {
+                let (sign, mut rest) = (if *seconds < 0.0 { "-" } else { "" }, seconds.abs());
+                let mut parts = Vec::new();
+                for (name, scale) in &[("d", 86400.0), ("h", 3600.0), ("min", 60.0)] {
+                    let whole = (rest / scale).floor();
+                    if whole > 0.0 {
+                        parts.push(format!("{}{}", whole, name));
+                        rest -= whole * scale;
+                    }
+                }
+                if rest > 0.0 || parts.is_empty() {
+                    match rest.fract() == 0.0 {
+                        true => parts.push(format!("{}s", rest)),
+                        false => parts.push(format!("{}s", settings.format_num(rest))),
+                    }
+                }
+                write!(f, "{}{}", sign, parts.join(" "))
+            },
+            Value::Date(days) => {
+                let (year, month, day) = days_to_date(*days);
+                write!(f, "{:04}-{:02}-{:02}", year, month, day)
+            },
+            Value::Polynomial(var, around, coefs) => {
+                let base = match *around {
+                    0.0 => var.clone(),
+                    around if around < 0.0 => format!("({} + {})", var, -around),
+                    around => format!("({} - {})", var, around),
+                };
+                // Rounding error leaves tiny coefficients where exact arithmetic would give zero.
+                let largest = coefs.iter().fold(0.0, |max: f64, coef| max.max(coef.abs()));
+                let mut terms = coefs.iter().enumerate().filter(|(_, coef)| coef.abs() > largest * 1e-13);
+                let monomial = |power| match power {
+                    0 => String::new(),
+                    1 => base.clone(),
+                    power => format!("{}^{}", base, power),
+                };
+                match terms.next() {
+                    None => write!(f, "0"),
+                    Some((power, coef)) => {
+                        write!(f, "{}{}", if *coef < 0.0 { "-" } else { "" }, polynomial_term(coef.abs(), &monomial(power)))?;
+                        for (power, coef) in terms {
+                            write!(f, " {} {}", if *coef < 0.0 { '-' } else { '+' }, polynomial_term(coef.abs(), &monomial(power)))?;
+                        }
+                        Ok(())
+                    },
+                }
+            },
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitDef {
+    symbol: String,
+    scale: f64,
+    dims: Dims,
+}
+impl UnitDef {
+    fn builtin(name: &str) -> Option<Self> {
+        UNITS.iter().find(|(symbol, _, _)| *symbol == name)
+            .map(|(symbol, scale, dims)| UnitDef { symbol: symbol.to_string(), scale: *scale, dims: *dims })
+    }
+
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    // Always in SI base units, whichever unit it is displayed in.
+    value: f64,
+    unit: Vec<(UnitDef, i32)>,
+}
+impl Quantity {
+    fn new(x: f64, unit: Vec<(UnitDef, i32)>) -> Self {
+        let mut quantity = Quantity { value: 0.0, unit };
+        quantity.value = x * quantity.scale();
+        quantity
+    }
+
+    fn from_value(value: Value) -> Result<Self, MathError> {
+        match value {
+            Value::Quantity(quantity) => Ok(quantity),
+            // Mixed with other units, a duration takes the largest of these units it fills.
+            Value::Duration(seconds) => {
+                let symbol = match seconds.abs() {
+                    abs if abs >= 3600.0 => "h",
+                    abs if abs >= 60.0 => "min",
+                    _ => "s",
+                };
+                Ok(Quantity { value: seconds, unit: vec![(UnitDef::builtin(symbol).unwrap(), 1)] })
+            },
+            value => Ok(Quantity { value: value.as_num()?, unit: Vec::new() }),
+        }
+    }
+
+    // Units that cancel out leave a plain number.
+    fn into_value(self) -> Value {
+        match self.dims() == [0; 7] {
+            true => Value::Num(self.value),
+            false => Value::Quantity(self),
+        }
+    }
+
+    fn scale(&self) -> f64 {
+        self.unit.iter().map(|(unit, power)| unit.scale.powi(*power)).product()
+    }
+
+    fn dims(&self) -> Dims {
+        let mut dims = [0; 7];
+        for (unit, power) in &self.unit {
+            for (dim, exp) in dims.iter_mut().zip(unit.dims) {
+                *dim += exp * *power;
+            }
+        }
+        dims
+    }
+
+    fn checked(self) -> Result<Quantity, MathError> {
+        match self.unit.iter().find(|(_, power)| power.abs() > MAX_UNIT_POWER) {
+            Some((unit, power)) => Err(BadUnitPower(unit.symbol.clone(), *power as f64)),
+            None => Ok(self),
+        }
+    }
+
+    // Multiplies by `other` raised to `sign`, folding each of its units into one of the same
+    // dimension where there is one, so `km * m` is shown in `km^2`.
+    fn combine(mut self, other: Quantity, sign: i32) -> Quantity {
+        self.value *= other.value.powi(sign);
+        for (unit, power) in other.unit {
+            match self.unit.iter_mut().find(|(own, _)| own.dims == unit.dims) {
+                Some((_, own)) => *own += sign * power,
+                None => self.unit.push((unit, sign * power)),
+            }
+        }
+        self.unit.retain(|(_, power)| *power != 0);
+        self
+    }
+
+    fn pow(mut self, exp: f64) -> Result<Quantity, MathError> {
+        let powers = self.unit.iter().map(|(_, power)| *power as f64 * exp).collect::<Vec<_>>();
+        if powers.iter().any(|power| power.fract() != 0.0 || power.abs() > MAX_UNIT_POWER as f64) {
+            return Err(BadUnitPower(self.unit_name(), exp));
+        }
+        self.value = self.value.powf(exp);
+        for ((_, power), new) in self.unit.iter_mut().zip(powers) {
+            *power = new as i32;
+        }
+        Ok(self)
+    }
+
+    fn unit_name(&self) -> String {
+        let term = |unit: &UnitDef, power: i32| match power {
+            1 => unit.symbol.clone(),
+            _ => format!("{}^{}", unit.symbol, power),
+        };
+        let numerator: Vec<String> = self.unit.iter().filter(|(_, power)| *power > 0)
+            .map(|(unit, power)| term(unit, *power)).collect();
+        let denominator = self.unit.iter().filter(|(_, power)| *power < 0);
+        match numerator.is_empty() {
+            true if self.unit.is_empty() => String::from("1"),
+            true => denominator.map(|(unit, power)| term(unit, *power)).collect::<Vec<_>>().join("*"),
+            false => denominator.fold(numerator.join("*"), |name, (unit, power)| format!("{}/{}", name, term(unit, -power))),
+        }
+    }
+
+}
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", Settings::new().format_num(self.value / self.scale()), self.unit_name())
+    }
+}
+
+#[cfg(test)]
+mod quantity_tests {
+    use crate::tests::run;
+    use super::*;
+
+    #[test]
+    fn units_combine_by_dimension() {
+        assert_eq!(run(&["3 km + 200 m"]), "3.2 km");
+        assert_eq!(run(&["10 m / 2 s"]), "5 m/s");
+        assert_eq!(run(&["2 m * 3 m"]), "6 m^2");
+        assert_eq!(run(&["sqrt(4 m^2)"]), "2 m");
+        assert_eq!(run(&["1 kg + 1 m"]), "Incompatible units: `kg` and `m`");
+        assert_eq!(run(&["1 N^100 + 1 N^100"]), "2 N^100");
+        assert_eq!(run(&["1 m^256 + 1"]), "Cannot raise `m` to the power `256`");
+        assert_eq!(run(&["(1 m^60) * (1 m^60)"]), "Cannot raise `m` to the power `120`");
+        assert_eq!(run(&["(1 m^2)^60"]), "Cannot raise `m^2` to the power `60`");
+        assert_eq!(run(&["2 kg * 3"]), "6 kg");
+        assert_eq!(run(&["(3 m)^2"]), "9 m^2");
+        assert_eq!(run(&["1 m + 1"]), "Incompatible units: `m` and `1`");
+        assert_eq!(run(&["5 m in kg"]), "Incompatible units: `m` and `kg`");
+    }
+
+    #[test]
+    fn units_convert() {
+        assert_eq!(run(&["3 km + 200 m in m"]), "3200 m");
+        assert_eq!(run(&["1 h in min"]), "60 min");
+    }
+
+    #[test]
+    fn temperatures_convert() {
+        assert_eq!(run(&["c_to_f(100)"]), "212");
+        assert_eq!(run(&["f_to_c(212)"]), "100");
+        assert_eq!(run(&["f_to_c(-40)"]), "-40");
+        assert_eq!(run(&["c_to_k(0)"]), "273.15 K");
+        assert_eq!(run(&["k_to_c(0)"]), "-273.15");
+        assert_eq!(run(&["c_to_f(-300)"]), "`c_to_f` is not defined for `-300`");
+        assert_eq!(run(&["k_to_c(-1)"]), "`k_to_c` is not defined for `-1`");
+    }
+
+    #[test]
+    fn currencies_use_loaded_rates() {
+        let mut env = Environment::new();
+        env.load_rates("USD = 1\nEUR = 1.1").unwrap();
+        assert_eq!(env.run("120 USD + 80 EUR in USD").unwrap().unwrap(), "208 USD");
+        assert!(matches!(env.load_rates("m = 2"), Err(BadRate(1, _))));
+        env.load_rates("code,rate\nGBP,1.25").unwrap();
+        assert_eq!(env.run("8 GBP in USD").unwrap().unwrap(), "10 USD");
+        assert_eq!(env.run("10 EUR * 2").unwrap().unwrap(), "20 EUR");
+        assert!(matches!(env.run("1 USD + 1 m").unwrap_err().inner(), IncompatibleUnits(..)));
+        assert!(matches!(env.load_rates("JPY = -1"), Err(BadRate(1, _))));
+    }
+
+    #[test]
+    fn durations_print_as_durations() {
+        assert_eq!(run(&["2h + 30min + 45s"]), "2h 30min 45s");
+        assert_eq!(run(&["90min / 2"]), "45min");
+        assert_eq!(run(&["3661s"]), "1h 1min 1s");
+        assert_eq!(run(&["2h - 3h"]), "-1h");
+        assert_eq!(run(&["2h in min"]), "120 min");
+    }
+
+    #[test]
+    fn dates_count_in_days() {
+        assert_eq!(run(&["days_between(2024-01-01, 2024-03-01)"]), "60");
+        assert_eq!(run(&["date(2024, 1, 31) + 90"]), "2024-04-30");
+        assert_eq!(run(&["2024-01-01 + 1"]), "2024-01-02");
+        assert_eq!(days_to_date(date_to_days(2000, 2, 29).unwrap()), (2000, 2, 29));
+        assert_eq!(run(&["2024-03-01 - 2024-01-01"]), "60");
+        assert_eq!(run(&["today() > 2020-01-01"]), "1");
+        assert_eq!(run(&["date(2024, 2, 30)"]), "Invalid date: 2024-02-30");
+        assert_eq!(run(&["date(2024, 1, 1) + 0.5"]), "Expected an integer, got: `0.5`");
+    }
+
+}
+
+
+// An expression tree, as the shunting stage builds it from the tokens of a statement. Symbolic
+// work with variables that have no value takes the arithmetic part of it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(f64),
+    // An integer literal, in integer-strict mode.
+    Int(i64),
+    BigInt(BigInt),
+    Duration(f64),
+    Date(i64),
+    Var(String),
+    BinaryOp(Operator, Box<Expr>, Box<Expr>),
+    UnaryOp(UnaryOperator, Box<Expr>),
+    Postfix(PostfixOperator, Box<Expr>),
+    // A literal in a unit, to a power.
+    Unit(Box<Expr>, UnitDef, i32),
+    Call(Function, Vec<Expr>),
+    // A call to a built-in that takes its arguments unevaluated.
+    Lazy(Function, Vec<Expr>),
+    // A call to a function the user defined.
+    UserCall(String, Vec<Expr>),
+    List(Vec<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Let(String, Box<Expr>, Box<Expr>),
+    // Where a node was read from, so that its errors are located there.
+    At(Span, Box<Expr>),
+}
+impl Expr {
+    fn solve(&self, env: &Environment) -> Result<Value, MathError> {
+        self.solve_scoped(env, Vec::new(), 0)
+    }
+
+    // Bound variables take precedence over the environment's, the latest binding first.
+    fn solve_scoped(&self, env: &Environment, mut scopes: Vec<(String, Value)>, depth: usize) -> Result<Value, MathError> {
+        Ok(self.value(env, &mut scopes, depth)?.reduced(&env.settings))
+    }
+
+    fn value(&self, env: &Environment, scopes: &mut Vec<(String, Value)>, depth: usize) -> Result<Value, MathError> {
+        match self {
+            Expr::Num(float) if env.settings.exact => Ok(Value::exact(*float)),
+            Expr::Num(float) => Ok(Value::Num(*float)),
+            Expr::Int(int) => Ok(Value::Int(*int)),
+            Expr::BigInt(int) => Ok(Value::BigInt(int.clone())),
+            Expr::Duration(seconds) => Ok(Value::Duration(*seconds)),
+            Expr::Date(days) => Ok(Value::Date(*days)),
+            Expr::Var(name) => match scopes.iter().rev().find(|(bound, _)| bound == name) {
+                Some((_, value)) => Ok(value.clone()),
+                None => match (env.get(name), env.get_unit(name)) {
+                    (Some(value), _) => Ok(value),
+                    (None, Some(unit)) => Ok(Value::Quantity(Quantity::new(1.0, vec![(unit, 1)]))),
+                    (None, None) => Err(UnknownIdent(name.clone())),
+                },
+            },
+            Expr::BinaryOp(Operator::Add | Operator::Sub, ..) if env.settings.kahan && env.settings.modulus.is_none() => {
+                let (sum, error) = self.compensated(env, scopes, depth)?;
+                Ok(Expr::settle(sum, error))
+            },
+            Expr::BinaryOp(oper, x, y) => {
+                let (x, y) = (x.value(env, scopes, depth)?, y.value(env, scopes, depth)?);
+                oper.evaluate(x, y, env)
+            },
+            Expr::UnaryOp(oper, x) => oper.apply(x.value(env, scopes, depth)?),
+            Expr::Postfix(oper, x) => {
+                let x = x.value(env, scopes, depth)?;
+                let operand = x.as_num().ok();
+                oper.apply(x)?.finite(&env.settings, || match operand {
+                    Some(x) => format!("{:?}{}", x, oper),
+                    None => oper.to_string(),
+                })
+            },
+            Expr::Unit(x, unit, power) => Ok(Value::Quantity(Quantity::new(x.value(env, scopes, depth)?.as_num()?, vec![(unit.clone(), *power)]).checked()?)),
+            Expr::Call(func, args) => {
+                let args = args.iter().map(|arg| arg.value(env, scopes, depth)).collect::<Result<Vec<_>, _>>()?;
+                if !func.arity().contains(&args.len()) {
+                    return Err(BadArity(func.to_string(), args.len()));
+                }
+                match func.apply(&args, &env.settings)? {
+                    Value::Num(float) if env.settings.integer && func.gives_integer(&args) && float.abs() < i64::MAX as f64 => Ok(Value::Int(float as i64)),
+                    value => value.finite(&env.settings, || {
+                        let args: Vec<String> = args.iter().map(|arg| arg.as_num().map_or(arg.to_string(), |x| format!("{:?}", x))).collect();
+                        format!("{}({})", func, args.join(", "))
+                    }),
+                }
+            },
+            Expr::Lazy(func, args) => {
+                if !func.arity().contains(&args.len()) {
+                    return Err(BadArity(func.to_string(), args.len()));
+                }
+                let warned = env.warnings.borrow().len();
+                let value = func.call_lazy(args, env, scopes, depth);
+                if func.iterates() {
+                    env.warnings.borrow_mut().truncate(warned);
+                }
+                value
+            },
+            Expr::UserCall(name, args) => {
+                let args = args.iter().map(|arg| arg.value(env, scopes, depth)).collect::<Result<Vec<_>, _>>()?;
+                let lambda = match env.get_func(name) {
+                    Some(lambda) => lambda,
+                    None => return Err(UnknownFunction(name.clone())),
+                };
+                if lambda.params.len() != args.len() {
+                    return Err(BadArity(name.clone(), args.len()));
+                }
+                if depth >= MAX_CALL_DEPTH {
+                    return Err(RecursionLimit(name.clone()));
+                }
+                let bindings = lambda.params.iter().cloned().zip(args).collect();
+                lambda.body.solve_scoped(env, bindings, depth + 1)
+            },
+            // Which leaves no way to write two-column matrices in interval mode.
+            Expr::List(items) if env.settings.interval && items.len() == 2 => {
+                let (lo, hi) = (items[0].value(env, scopes, depth)?.as_num()?, items[1].value(env, scopes, depth)?.as_num()?);
+                match lo <= hi {
+                    true => Ok(Value::Interval(lo, hi)),
+                    false => Err(BadInterval(lo, hi)),
+                }
+            },
+            // A lone value in brackets is only grouped, unless it is a row of a matrix.
+            Expr::List(items) => {
+                let mut values = Vec::new();
+                for item in items {
+                    values.push(match item.bare() {
+                        Expr::List(row) if row.len() == 1 => Value::List(vec![row[0].value(env, scopes, depth)?.as_num()?]),
+                        _ => item.value(env, scopes, depth)?,
+                    });
+                }
+                Value::from_items(values)
+            },
+            Expr::Ternary(cond, then, other) => match cond.value(env, scopes, depth)?.as_num()? != 0.0 {
+                true => then.value(env, scopes, depth),
+                false => other.value(env, scopes, depth),
+            },
+            Expr::Let(name, value, body) => {
+                let value = value.value(env, scopes, depth)?;
+                scopes.push((name.clone(), value));
+                let result = body.value(env, scopes, depth);
+                scopes.pop();
+                result
+            },
+            Expr::At(span, expr) => expr.value(env, scopes, depth).map_err(|error| error.at(*span)),
+        }
+    }
+
+    // With compensated summation, a sum of numbers and the rounding error it lost, which is only
+    // added back once something other than another term needs the sum.
+    fn compensated(&self, env: &Environment, scopes: &mut Vec<(String, Value)>, depth: usize) -> Result<(Value, f64), MathError> {
+        match self {
+            Expr::BinaryOp(oper @ (Operator::Add | Operator::Sub), x, y) => {
+                let (x, y) = (x.compensated(env, scopes, depth)?, y.compensated(env, scopes, depth)?);
+                match (x, y) {
+                    ((Value::Num(x), x_error), (Value::Num(y), y_error)) => {
+                        let sign = if *oper == Operator::Sub { -1.0 } else { 1.0 };
+                        let (sum, error) = two_sum(x, sign * y);
+                        let sum = Value::Num(sum).finite(&env.settings, || format!("{:?} {} {:?}", x, oper, y))?;
+                        Ok((sum, x_error + sign * y_error + error))
+                    },
+                    ((x, x_error), (y, y_error)) => Ok((oper.evaluate(Expr::settle(x, x_error), Expr::settle(y, y_error), env)?, 0.0)),
+                }
+            },
+            Expr::At(span, expr) => expr.compensated(env, scopes, depth).map_err(|error| error.at(*span)),
+            _ => Ok((self.value(env, scopes, depth)?, 0.0)),
+        }
+    }
+
+    fn settle(sum: Value, error: f64) -> Value {
+        match sum {
+            Value::Num(sum) => Value::Num(sum + error),
+            sum => sum,
+        }
+    }
+
+    // Solves plain arithmetic in another number type, or gives `None` for anything it lacks so
+    // that the tree can be solved in floating point instead. Constants such as `pi` are already
+    // floating-point literals by now.
+    pub fn solve_in<N: Numeric + Clone>(&self, env: &Environment, context: N::Context) -> Option<N> {
+        self.solve_bound(env, &[], context)
+    }
+
+    // Bound variables take precedence over the environment's, the latest binding first.
+    fn solve_bound<N: Numeric + Clone>(&self, env: &Environment, bindings: &[(&str, N)], context: N::Context) -> Option<N> {
+        let solve = |expr: &Expr| expr.solve_bound(env, bindings, context);
+        match self {
+            Expr::Num(float) => N::from_f64(*float, context),
+            Expr::Int(int) => N::from_f64(*int as f64, context),
+            Expr::BigInt(int) => N::from_value(Value::BigInt(int.clone()), context),
+            Expr::Var(name) => match bindings.iter().rev().find(|(bound, _)| bound == name) {
+                Some((_, x)) => Some(x.clone()),
+                None => N::from_value(env.get(name)?, context),
+            },
+            Expr::BinaryOp(oper, x, y) => N::call(oper, solve(x)?, solve(y)?, context),
+            Expr::UnaryOp(oper, x) => match (oper, &**x) {
+                // A negative literal can reach one further than a positive one in fixed point.
+                (UnaryOperator::Neg, Expr::Num(float)) => N::from_f64(-float, context),
+                _ => N::call_unary(oper, solve(x)?, context),
+            },
+            Expr::Call(func, args) => N::call_func(func, args.iter().map(solve).collect::<Option<_>>()?, context),
+            Expr::At(_, expr) => solve(expr),
+            _ => None,
+        }
+    }
+
+    // The tree over power series in the bound variables, for Taylor expansions. Anything that
+    // does not depend on them is solved as usual and taken as a constant.
+    fn series(&self, env: &Environment, bindings: &mut Vec<(String, Series)>, scopes: &[(String, Value)], len: usize, depth: usize) -> Result<Series, MathError> {
+        let constant = |value: Value| Ok::<_, MathError>(Series::constant(value.as_num()?, len));
+        match self {
+            Expr::Num(float) => Ok(Series::constant(*float, len)),
+            Expr::Int(int) => Ok(Series::constant(*int as f64, len)),
+            Expr::BigInt(int) => Ok(Series::constant(int.to_f64(), len)),
+            Expr::Var(name) => match bindings.iter().rev().find(|(bound, _)| bound == name) {
+                Some((_, series)) => Ok(series.clone()),
+                None => constant(self.solve_scoped(env, scopes.to_vec(), depth)?),
+            },
+            Expr::Let(name, value, body) => {
+                let value = value.series(env, bindings, scopes, len, depth)?;
+                bindings.push((name.clone(), value));
+                let series = body.series(env, bindings, scopes, len, depth);
+                bindings.pop();
+                series
+            },
+            Expr::BinaryOp(oper, x, y) => {
+                let (x, y) = (x.series(env, bindings, scopes, len, depth)?, y.series(env, bindings, scopes, len, depth)?);
+                oper.expand(&x, &y)
+            },
+            Expr::UnaryOp(oper, x) => {
+                let x = x.series(env, bindings, scopes, len, depth)?;
+                match oper {
+                    UnaryOperator::Neg => Ok(x.scale(-1.0)),
+                    UnaryOperator::Sqrt => x.powf(0.5).ok_or_else(|| NoSeries(oper.to_string())),
+                    _ if x.is_constant() => constant(Value::Num(oper.call(x.0[0])?)),
+                    _ => Err(NoSeries(oper.to_string())),
+                }
+            },
+            Expr::Postfix(oper, x) => match x.series(env, bindings, scopes, len, depth)? {
+                x if x.is_constant() => constant(Value::Num(oper.call(x.0[0])?)),
+                _ => Err(NoSeries(oper.to_string())),
+            },
+            Expr::Call(func, args) => {
+                let args = args.iter().map(|arg| arg.series(env, bindings, scopes, len, depth)).collect::<Result<Vec<_>, _>>()?;
+                if !func.arity().contains(&args.len()) {
+                    return Err(BadArity(func.to_string(), args.len()));
+                }
+                func.expand(&args, &env.settings, len)
+            },
+            Expr::UserCall(name, args) => {
+                let args = args.iter().map(|arg| arg.series(env, bindings, scopes, len, depth)).collect::<Result<Vec<_>, _>>()?;
+                let lambda = match env.get_func(name) {
+                    Some(lambda) => lambda,
+                    None => return Err(UnknownFunction(name.clone())),
+                };
+                if lambda.params.len() != args.len() {
+                    return Err(BadArity(name.clone(), args.len()));
+                }
+                if depth >= MAX_CALL_DEPTH {
+                    return Err(RecursionLimit(name.clone()));
+                }
+                let mut bindings = lambda.params.iter().cloned().zip(args).collect();
+                lambda.body.series(env, &mut bindings, &[], len, depth + 1)
+            },
+            Expr::Ternary(cond, then, other) => {
+                let cond = cond.series(env, bindings, scopes, len, depth)?;
+                match cond.is_constant() {
+                    true if cond.0[0] != 0.0 => then.series(env, bindings, scopes, len, depth),
+                    true => other.series(env, bindings, scopes, len, depth),
+                    false => Err(NoSeries(String::from("?"))),
+                }
+            },
+            Expr::At(span, expr) => expr.series(env, bindings, scopes, len, depth).map_err(|error| error.at(*span)),
+            expr => Err(NoSeries(expr.to_string())),
+        }
+    }
+
+    // Evaluates the parts of a function body that don't depend on any variable once, for bodies
+    // that are run many times. Only finite floats are folded, and nothing that warns, so that
+    // whatever would fail or warn still does when run. Other modes of arithmetic leave the tree
+    // alone, and it is folded again whenever they change.
+    fn fold_constants(self, env: &Environment) -> Expr {
+        if !env.settings.plain_arithmetic() {
+            return self;
+        }
+        let fold = |expr: Box<Expr>| Box::new(expr.fold_constants(env));
+        let fold_all = |exprs: Vec<Expr>| exprs.into_iter().map(|expr| expr.fold_constants(env)).collect::<Vec<_>>();
+        let expr = match self {
+            Expr::BinaryOp(oper, x, y) => Expr::BinaryOp(oper, fold(x), fold(y)),
+            Expr::UnaryOp(oper, x) => Expr::UnaryOp(oper, fold(x)),
+            Expr::Postfix(oper, x) => Expr::Postfix(oper, fold(x)),
+            Expr::Call(func, args) => Expr::Call(func, fold_all(args)),
+            Expr::Unit(x, unit, power) => return Expr::Unit(fold(x), unit, power),
+            Expr::Lazy(func, args) => return Expr::Lazy(func, fold_all(args)),
+            Expr::UserCall(name, args) => return Expr::UserCall(name, fold_all(args)),
+            Expr::List(items) => return Expr::List(fold_all(items)),
+            Expr::Ternary(cond, then, other) => return Expr::Ternary(fold(cond), fold(then), fold(other)),
+            Expr::Let(name, value, body) => return Expr::Let(name, fold(value), fold(body)),
+            // What folds to a literal can no longer fail.
+            Expr::At(span, expr) => return match expr.fold_constants(env) {
+                expr if expr.is_literal() => expr,
+                expr => expr.at(span),
+            },
+            expr => return expr,
+        };
+        let constant = match &expr {
+            Expr::BinaryOp(_, x, y) => x.is_literal() && y.is_literal(),
+            Expr::UnaryOp(_, x) | Expr::Postfix(_, x) => x.is_literal(),
+            Expr::Call(func, args) => func.is_constant() && args.iter().all(Expr::is_literal),
+            _ => false,
+        };
+        if constant {
+            let warned = env.warnings.borrow().len();
+            let result = expr.solve(env);
+            let quiet = env.warnings.borrow().len() == warned;
+            env.warnings.borrow_mut().truncate(warned);
+            if let (Ok(Value::Num(float)), true) = (result, quiet) {
+                if float.is_finite() {
+                    return Expr::Num(float);
+                }
+            }
+        }
+        expr
+    }
+
+    fn is_literal(&self) -> bool {
+        matches!(self, Expr::Num(_) | Expr::Int(_) | Expr::BigInt(_))
+    }
+
+    fn at(self, span: Span) -> Expr {
+        Expr::At(span, Box::new(self))
+    }
+
+    // The node under any spans.
+    fn bare(&self) -> &Expr {
+        match self {
+            Expr::At(_, expr) => expr.bare(),
+            expr => expr,
+        }
+    }
+
+    // The tree with its spans taken out, for one that outlives its input.
+    fn unlocated(self) -> Expr {
+        let strip = |expr: Box<Expr>| Box::new(expr.unlocated());
+        let strip_all = |exprs: Vec<Expr>| exprs.into_iter().map(Expr::unlocated).collect();
+        match self {
+            Expr::BinaryOp(oper, x, y) => Expr::BinaryOp(oper, strip(x), strip(y)),
+            Expr::UnaryOp(oper, x) => Expr::UnaryOp(oper, strip(x)),
+            Expr::Postfix(oper, x) => Expr::Postfix(oper, strip(x)),
+            Expr::Unit(x, unit, power) => Expr::Unit(strip(x), unit, power),
+            Expr::Call(func, args) => Expr::Call(func, strip_all(args)),
+            Expr::Lazy(func, args) => Expr::Lazy(func, strip_all(args)),
+            Expr::UserCall(name, args) => Expr::UserCall(name, strip_all(args)),
+            Expr::List(items) => Expr::List(strip_all(items)),
+            Expr::Ternary(cond, then, other) => Expr::Ternary(strip(cond), strip(then), strip(other)),
+            Expr::Let(name, value, body) => Expr::Let(name, strip(value), strip(body)),
+            Expr::At(_, expr) => expr.unlocated(),
+            expr => expr,
+        }
+    }
+
+    // The arithmetic of the tree, with variables that have a value replaced by it unless they
+    // are to be kept free. `None` if it does more than arithmetic and calls to built-ins, or
+    // uses values that aren't plain numbers.
+    fn symbolic(&self, env: &Environment, free: &[&str]) -> Option<Expr> {
+        let symbolic = |expr: &Expr| expr.symbolic(env, free);
+        match self {
+            Expr::Num(float) => Some(Expr::Num(*float)),
+            Expr::Int(int) => Some(Expr::Num(*int as f64)),
+            Expr::BigInt(int) => Some(Expr::Num(int.to_f64())),
+            Expr::Var(name) if free.contains(&name.as_str()) => Some(self.clone()),
+            Expr::Var(name) => match (env.get(name), env.get_unit(name)) {
+                (Some(Value::Symbolic(expr)), _) => Some(expr),
+                (Some(value), _) => Some(Expr::Num(value.as_num().ok()?)),
+                (None, Some(_)) => None,
+                (None, None) => Some(self.clone()),
+            },
+            Expr::BinaryOp(Operator::PlusMinus, ..) => None,
+            Expr::BinaryOp(oper, x, y) => Some(Expr::BinaryOp(*oper, Box::new(symbolic(x)?), Box::new(symbolic(y)?))),
+            Expr::UnaryOp(oper, x) => Some(Expr::UnaryOp(*oper, Box::new(symbolic(x)?))),
+            Expr::Call(func, args) if func.arity().contains(&args.len()) => Some(Expr::Call(*func, args.iter().map(symbolic).collect::<Option<_>>()?)),
+            Expr::At(_, expr) => symbolic(expr),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Expr::Num(float) => Value::Num(float),
+            expr => Value::Symbolic(expr),
+        }
+    }
+
+    fn substitute(self, var: &str, value: &Expr) -> Expr {
+        match self {
+            Expr::Var(name) if name == var => value.clone(),
+            Expr::BinaryOp(oper, x, y) => Expr::BinaryOp(oper, Box::new(x.substitute(var, value)), Box::new(y.substitute(var, value))),
+            Expr::UnaryOp(oper, x) => Expr::UnaryOp(oper, Box::new(x.substitute(var, value))),
+            Expr::Call(func, args) => Expr::Call(func, args.into_iter().map(|arg| arg.substitute(var, value)).collect()),
+            expr => expr,
+        }
+    }
+
+    fn contains(&self, var: &str) -> bool {
+        match self {
+            Expr::Var(name) => name == var,
+            Expr::BinaryOp(_, x, y) => x.contains(var) || y.contains(var),
+            Expr::UnaryOp(_, x) | Expr::Postfix(_, x) | Expr::Unit(x, ..) => x.contains(var),
+            Expr::Call(_, args) | Expr::Lazy(_, args) | Expr::UserCall(_, args) | Expr::List(args) => args.iter().any(|arg| arg.contains(var)),
+            Expr::Ternary(cond, then, other) => cond.contains(var) || then.contains(var) || other.contains(var),
+            Expr::Let(name, value, body) => value.contains(var) || name != var && body.contains(var),
+            Expr::At(_, expr) => expr.contains(var),
+            _ => false,
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::BinaryOp(oper, ..) => oper.precedence(),
+            Expr::UnaryOp(oper, _) => oper.precedence(),
+            Expr::Num(float) if *float < 0.0 => UnaryOperator::Neg.precedence(),
+            Expr::Ternary(..) | Expr::Let(..) => 0,
+            Expr::At(_, expr) => expr.precedence(),
+            _ => u8::MAX,
+        }
+    }
+
+    // Folds constants, then gathers like terms of sums and like factors of products, which
+    // also drops adding zero and multiplying by one. Dividing by what simplifies to zero is
+    // an error, as it is for numbers, unless IEEE semantics are on.
+    fn simplify(self, settings: &Settings) -> Result<Expr, MathError> {
+        let expr = match self {
+            Expr::BinaryOp(oper, x, y) => Expr::BinaryOp(oper, Box::new(x.simplify(settings)?), Box::new(y.simplify(settings)?)),
+            Expr::UnaryOp(oper, x) => Expr::UnaryOp(oper, Box::new(x.simplify(settings)?)),
+            Expr::Call(func, args) => Expr::Call(func, args.into_iter().map(|arg| arg.simplify(settings)).collect::<Result<_, _>>()?),
+            expr => expr,
+        };
+        if let Some(float) = expr.fold(settings) {
+            return Ok(Expr::Num(float));
+        }
+        match expr {
+            Expr::BinaryOp(oper @ (Operator::Div | Operator::Mod), x, y) if *y == Expr::Num(0.0) && !settings.ieee => {
+                let numer = match x.precedence() < oper.precedence() {
+                    true => format!("({})", x),
+                    false => x.to_string(),
+                };
+                Err(DivisionByZero(oper, numer))
+            },
+            Expr::BinaryOp(Operator::Add | Operator::Sub | Operator::Mul | Operator::Div | Operator::Pow, ..) | Expr::UnaryOp(UnaryOperator::Neg, _) => {
+                let mut terms = Vec::new();
+                expr.terms(1.0, &mut terms);
+                Ok(Expr::sum(terms))
+            },
+            expr => Ok(expr),
+        }
+    }
+
+    // By the usual rules, with trigonometry in the angle mode. Left for `simplify` to tidy.
+    fn derivative(&self, var: &str, angle: Angle) -> Result<Expr, MathError> {
+        let op = |oper: Operator, x: Expr, y: Expr| Expr::BinaryOp(oper, Box::new(x), Box::new(y));
+        let call = |func: Function, x: &Expr| Expr::Call(func, vec![x.clone()]);
+        let neg = |x: Expr| Expr::UnaryOp(UnaryOperator::Neg, Box::new(x));
+        match self {
+            Expr::Num(_) => Ok(Expr::Num(0.0)),
+            Expr::Var(name) => Ok(Expr::Num(if name == var { 1.0 } else { 0.0 })),
+            Expr::BinaryOp(oper, x, y) => {
+                let (dx, dy) = (x.derivative(var, angle)?, y.derivative(var, angle)?);
+                let (x, y) = (*x.clone(), *y.clone());
+                match oper {
+                    Operator::Add | Operator::Sub => Ok(op(*oper, dx, dy)),
+                    Operator::Mul => Ok(op(Operator::Add, op(Operator::Mul, dx, y), op(Operator::Mul, x, dy))),
+                    Operator::Div => {
+                        let numer = op(Operator::Sub, op(Operator::Mul, dx, y.clone()), op(Operator::Mul, x, dy));
+                        Ok(op(Operator::Div, numer, op(Operator::Pow, y, Expr::Num(2.0))))
+                    },
+                    Operator::Pow if !y.contains(var) => {
+                        let lowered = op(Operator::Pow, x, op(Operator::Sub, y.clone(), Expr::Num(1.0)));
+                        Ok(op(Operator::Mul, op(Operator::Mul, y, lowered), dx))
+                    },
+                    Operator::Pow => {
+                        let rate = op(Operator::Add, op(Operator::Mul, dy, call(Function::Ln, &x)), op(Operator::Div, op(Operator::Mul, y, dx), x));
+                        Ok(op(Operator::Mul, self.clone(), rate))
+                    },
+                    _ => Err(NoDerivative(oper.to_string())),
+                }
+            },
+            Expr::UnaryOp(UnaryOperator::Neg, x) => Ok(neg(x.derivative(var, angle)?)),
+            Expr::UnaryOp(UnaryOperator::Sqrt, x) => Ok(op(Operator::Div, x.derivative(var, angle)?, op(Operator::Mul, Expr::Num(2.0), self.clone()))),
+            Expr::UnaryOp(oper, _) => Err(NoDerivative(oper.to_string())),
+            Expr::Call(func, args) => {
+                let x = match &args[..] {
+                    [x] => x,
+                    _ => return Err(NoDerivative(func.to_string())),
+                };
+                // Radians per unit of the angle mode.
+                let radians = Expr::Num(angle.radians_from(1.0));
+                let outer = match func {
+                    Function::Sin => op(Operator::Mul, radians, call(Function::Cos, x)),
+                    Function::Cos => neg(op(Operator::Mul, radians, call(Function::Sin, x))),
+                    Function::Tan => op(Operator::Div, radians, op(Operator::Pow, call(Function::Cos, x), Expr::Num(2.0))),
+                    Function::Atan => op(Operator::Div, Expr::Num(angle.radians_to(1.0)), op(Operator::Add, Expr::Num(1.0), op(Operator::Pow, x.clone(), Expr::Num(2.0)))),
+                    Function::Sqrt => op(Operator::Div, Expr::Num(1.0), op(Operator::Mul, Expr::Num(2.0), self.clone())),
+                    Function::Cbrt => op(Operator::Div, Expr::Num(1.0), op(Operator::Mul, Expr::Num(3.0), op(Operator::Pow, self.clone(), Expr::Num(2.0)))),
+                    Function::Ln => op(Operator::Div, Expr::Num(1.0), x.clone()),
+                    Function::Log => op(Operator::Div, Expr::Num(1.0), op(Operator::Mul, x.clone(), Expr::Num(consts::LN_10))),
+                    Function::Log2 => op(Operator::Div, Expr::Num(1.0), op(Operator::Mul, x.clone(), Expr::Num(consts::LN_2))),
+                    Function::Abs => call(Function::Sign, x),
+                    _ => return Err(NoDerivative(func.to_string())),
+                };
+                Ok(op(Operator::Mul, outer, x.derivative(var, angle)?))
+            },
+            expr => Err(NoDerivative(expr.to_string())),
+        }
+    }
+
+    // Multiplies out products and whole powers of sums, with polynomials in one variable in
+    // descending powers.
+    fn expand(self, settings: &Settings) -> Result<Expr, MathError> {
+        let expanded = self.multiply_out(settings)?;
+        match expanded.polynomial() {
+            Some((var, coefs)) => Ok(Expr::from_coefs(&var, &coefs)),
+            None => Ok(expanded),
+        }
+    }
+
+    fn multiply_out(self, settings: &Settings) -> Result<Expr, MathError> {
+        let sum = |summands: Vec<Expr>| summands.into_iter()
+            .reduce(|x, y| Expr::BinaryOp(Operator::Add, Box::new(x), Box::new(y)))
+            .unwrap_or(Expr::Num(0.0));
+        // Every term of one times every term of the other.
+        let distribute = |x: Expr, y: Expr| {
+            let ys = y.summands();
+            let products = x.summands().into_iter()
+                .flat_map(|x| ys.iter().map(move |y| Expr::BinaryOp(Operator::Mul, Box::new(x.clone()), Box::new(y.clone()))))
+                .collect();
+            sum(products).simplify(settings)
+        };
+        match self {
+            Expr::BinaryOp(Operator::Add | Operator::Sub, ..) => {
+                sum(self.summands().into_iter().map(|term| term.multiply_out(settings)).collect::<Result<_, _>>()?).simplify(settings)
+            },
+            Expr::UnaryOp(UnaryOperator::Neg, x) => Expr::UnaryOp(UnaryOperator::Neg, Box::new(x.multiply_out(settings)?)).simplify(settings),
+            Expr::BinaryOp(Operator::Mul, x, y) => distribute(x.multiply_out(settings)?, y.multiply_out(settings)?),
+            Expr::BinaryOp(Operator::Div, x, y) => {
+                let quotients = x.multiply_out(settings)?.summands().into_iter().map(|term| Expr::BinaryOp(Operator::Div, Box::new(term), y.clone())).collect();
+                sum(quotients).simplify(settings)
+            },
+            Expr::BinaryOp(Operator::Pow, x, y) => match *y {
+                Expr::Num(exp) if exp.fract() == 0.0 && (1.0..=MAX_SERIES_ORDER as f64).contains(&exp) => {
+                    let base = x.multiply_out(settings)?;
+                    (1..exp as usize).try_fold(base.clone(), |power, _| distribute(power, base.clone()))
+                },
+                y => {
+                    let base = x.multiply_out(settings)?;
+                    match y {
+                        Expr::Num(exp) if exp.fract() == 0.0 && exp > 1.0 && base.clone().summands().len() > 1 => Err(PowerTooLarge(exp)),
+                        y => Ok(Expr::BinaryOp(Operator::Pow, Box::new(base), Box::new(y))),
+                    }
+                },
+            },
+            expr => Ok(expr),
+        }
+    }
+
+    fn summands(self) -> Vec<Expr> {
+        let negated = |terms: Vec<Expr>| terms.into_iter().map(|term| Expr::UnaryOp(UnaryOperator::Neg, Box::new(term))).collect::<Vec<_>>();
+        match self {
+            Expr::BinaryOp(Operator::Add, x, y) => [x.summands(), y.summands()].concat(),
+            Expr::BinaryOp(Operator::Sub, x, y) => [x.summands(), negated(y.summands())].concat(),
+            Expr::UnaryOp(UnaryOperator::Neg, x) => negated(x.summands()),
+            expr => vec![expr],
+        }
+    }
+
+    // The coefficients in ascending powers of its only variable, if it is a polynomial in one.
+    fn polynomial(&self) -> Option<(String, Vec<f64>)> {
+        let mut terms = Vec::new();
+        self.clone().terms(1.0, &mut terms);
+        let (mut var, mut coefs) = (None, Vec::new());
+        for (coef, factors) in terms {
+            let power = match &factors[..] {
+                [] => 0,
+                [(Expr::Var(name), power)] if power.fract() == 0.0 && *power > 0.0 && *power <= MAX_SERIES_ORDER as f64 => {
+                    if var.get_or_insert_with(|| name.clone()) != name {
+                        return None;
+                    }
+                    *power as usize
+                },
+                _ => return None,
+            };
+            if coefs.len() <= power {
+                coefs.resize(power + 1, 0.0);
+            }
+            coefs[power] += coef;
+        }
+        Some((var?, coefs))
+    }
+
+    fn from_coefs(var: &str, coefs: &[f64]) -> Expr {
+        let terms = coefs.iter().enumerate().rev().map(|(power, &coef)| match power {
+            0 => (coef, Vec::new()),
+            power => (coef, vec![(Expr::Var(var.to_string()), power as f64)]),
+        });
+        Expr::sum(terms.collect())
+    }
+
+    // Pulls out the content, powers of the variable and a linear factor `q*x - p` for each
+    // rational root `p/q`, which by the rational root theorem has `p` dividing the constant
+    // coefficient and `q` the leading one. What has no rational roots is left as it is.
+    fn factor(self, settings: &Settings) -> Result<Expr, MathError> {
+        if let Expr::Num(_) = self {
+            return Ok(self);
+        }
+        let not_polynomial = || NotPolynomial(self.to_string());
+        let (var, coefs) = self.clone().multiply_out(settings)?.polynomial().ok_or_else(not_polynomial)?;
+        if coefs.iter().any(|coef| coef.fract() != 0.0 || coef.abs() > EXACT_INTEGER_LIMIT) {
+            return Err(not_polynomial());
+        }
+        let mut coefs: Vec<i128> = coefs.iter().map(|&coef| coef as i128).collect();
+        while coefs.last() == Some(&0) {
+            coefs.pop();
+        }
+        let mut factors = Vec::new();
+        let add_factor = |factor: Expr, factors: &mut Vec<(Expr, f64)>| match factors.iter_mut().find(|(like, _)| *like == factor) {
+            Some((_, power)) => *power += 1.0,
+            None => factors.push((factor, 1.0)),
+        };
+        let zeros = coefs.iter().take_while(|&&coef| coef == 0).count();
+        if zeros > 0 && zeros < coefs.len() {
+            coefs.drain(..zeros);
+            factors.push((Expr::Var(var.clone()), zeros as f64));
+        }
+        let leading = coefs.last().copied().unwrap_or(0);
+        let content = coefs.iter().fold(0, |divisor, coef| gcd(divisor, coef.unsigned_abs())) as i128 * leading.signum();
+        if content == 0 {
+            return Ok(Expr::Num(0.0));
+        }
+        coefs.iter_mut().for_each(|coef| *coef /= content);
+        'search: while coefs.len() > 2 {
+            for p in divisors(coefs[0]).into_iter().flat_map(|p| vec![p, -p]) {
+                for q in divisors(*coefs.last().unwrap()) {
+                    if gcd(p.unsigned_abs(), q as u128) != 1 {
+                        continue;
+                    }
+                    if let Some(quotient) = divide_root(&coefs, p, q) {
+                        add_factor(Expr::from_coefs(&var, &[-p as f64, q as f64]), &mut factors);
+                        coefs = quotient;
+                        continue 'search;
+                    }
+                }
+            }
+            break;
+        }
+        if coefs.len() > 1 {
+            add_factor(Expr::from_coefs(&var, &coefs.iter().map(|&coef| coef as f64).collect::<Vec<_>>()), &mut factors);
+        }
+        Ok(Expr::product(content as f64, factors))
+    }
+
+    // The value of an operation on constants, if it has a finite one.
+    fn fold(&self, settings: &Settings) -> Option<f64> {
+        let num = |expr: &Expr| match expr {
+            Expr::Num(float) => Some(Value::Num(*float)),
+            _ => None,
+        };
+        let value = match self {
+            Expr::BinaryOp(oper, x, y) => oper.apply(num(x)?, num(y)?, settings),
+            Expr::UnaryOp(oper, x) => oper.apply(num(x)?),
+            Expr::Call(func, args) => func.apply(&args.iter().map(num).collect::<Option<Vec<_>>>()?, settings),
+            _ => return None,
+        };
+        match value {
+            Ok(Value::Num(float)) if float.is_finite() => Some(float),
+            _ => None,
+        }
+    }
+
+    // Adds the expression, as multiples of products of powers, to like terms.
+    fn terms(self, sign: f64, terms: &mut Vec<(f64, Vec<(Expr, f64)>)>) {
+        match self {
+            Expr::BinaryOp(Operator::Add, x, y) => {
+                x.terms(sign, terms);
+                y.terms(sign, terms);
+            },
+            Expr::BinaryOp(Operator::Sub, x, y) => {
+                x.terms(sign, terms);
+                y.terms(-sign, terms);
+            },
+            Expr::UnaryOp(UnaryOperator::Neg, x) => x.terms(-sign, terms),
+            expr => {
+                let (mut coef, mut factors) = (sign, Vec::new());
+                expr.factors(1.0, &mut coef, &mut factors);
+                factors.retain(|(_, power)| *power != 0.0);
+                // So that `x*y` and `y*x` are like terms. Variables go first.
+                factors.sort_by_cached_key(|(base, _)| (!matches!(base, Expr::Var(_)), base.to_string()));
+                match terms.iter_mut().find(|(_, like)| *like == factors) {
+                    Some((total, _)) => *total += coef,
+                    None => terms.push((coef, factors)),
+                }
+            },
+        }
+    }
+
+    // Multiplies the expression, to a power, into a coefficient and like factors. Only
+    // integer powers distribute, as `(x^2)^0.5` is not `x` for negative `x`.
+    fn factors(self, power: f64, coef: &mut f64, factors: &mut Vec<(Expr, f64)>) {
+        match self {
+            Expr::Num(float) => *coef *= float.powf(power),
+            Expr::BinaryOp(Operator::Mul, x, y) => {
+                x.factors(power, coef, factors);
+                y.factors(power, coef, factors);
+            },
+            Expr::BinaryOp(Operator::Div, x, y) => {
+                x.factors(power, coef, factors);
+                y.factors(-power, coef, factors);
+            },
+            Expr::BinaryOp(Operator::Pow, x, y) if matches!(*y, Expr::Num(exp) if exp.fract() == 0.0) => {
+                if let Expr::Num(exp) = *y {
+                    x.factors(power * exp, coef, factors);
+                }
+            },
+            Expr::UnaryOp(UnaryOperator::Neg, x) if power.fract() == 0.0 => {
+                *coef *= (-1.0f64).powf(power);
+                x.factors(power, coef, factors);
+            },
+            expr => match factors.iter_mut().find(|(base, _)| *base == expr) {
+                Some((_, total)) => *total += power,
+                None => factors.push((expr, power)),
+            },
+        }
+    }
+
+    // Constant terms go last.
+    fn sum(terms: Vec<(f64, Vec<(Expr, f64)>)>) -> Expr {
+        let (constants, mut terms): (Vec<_>, Vec<_>) = terms.into_iter().partition(|(_, factors)| factors.is_empty());
+        terms.push((constants.iter().map(|(coef, _)| coef).sum(), Vec::new()));
+        let mut sum: Option<Expr> = None;
+        for (coef, factors) in terms.into_iter().filter(|(coef, _)| *coef != 0.0) {
+            sum = Some(match sum {
+                None => Expr::product(coef, factors),
+                Some(sum) if coef < 0.0 => Expr::BinaryOp(Operator::Sub, Box::new(sum), Box::new(Expr::product(-coef, factors))),
+                Some(sum) => Expr::BinaryOp(Operator::Add, Box::new(sum), Box::new(Expr::product(coef, factors))),
+            });
+        }
+        sum.unwrap_or(Expr::Num(0.0))
+    }
+
+    // Negative powers go in the denominator, and a coefficient of minus one negates the first
+    // factor.
+    fn product(coef: f64, factors: Vec<(Expr, f64)>) -> Expr {
+        if coef == 0.0 {
+            return Expr::Num(0.0);
+        }
+        let power = |base: Expr, exp: f64| match exp == 1.0 {
+            true => base,
+            false => Expr::BinaryOp(Operator::Pow, Box::new(base), Box::new(Expr::Num(exp))),
+        };
+        let (numer, denom): (Vec<_>, Vec<_>) = factors.into_iter().partition(|(_, exp)| *exp > 0.0);
+        let mut numer: Vec<Expr> = numer.into_iter().map(|(base, exp)| power(base, exp)).collect();
+        let denom: Vec<Expr> = denom.into_iter().map(|(base, exp)| power(base, -exp)).collect();
+        match numer.first_mut() {
+            Some(first) if coef == -1.0 => *first = Expr::UnaryOp(UnaryOperator::Neg, Box::new(mem::replace(first, Expr::Num(0.0)))),
+            Some(_) if coef == 1.0 => (),
+            _ => numer.insert(0, Expr::Num(coef)),
+        }
+        let multiply = |factors: Vec<Expr>| factors.into_iter().reduce(|x, y| Expr::BinaryOp(Operator::Mul, Box::new(x), Box::new(y)));
+        let numer = multiply(numer).unwrap();
+        match multiply(denom) {
+            Some(denom) => Expr::BinaryOp(Operator::Div, Box::new(numer), Box::new(denom)),
+            None => numer,
+        }
+    }
+
+}
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Operands that bind less tightly than their place needs are parenthesized.
+        let operand = |expr: &Expr, min: u8| match expr.precedence() < min {
+            true => format!("({})", expr),
+            false => expr.to_string(),
+        };
+        let list = |exprs: &[Expr]| exprs.iter().map(Expr::to_string).collect::<Vec<_>>().join(", ");
+        match self {
+            Expr::Num(float) => write!(f, "{}", float),
+            Expr::Int(int) => write!(f, "{}", int),
+            Expr::BigInt(int) => write!(f, "{}", int),
+            Expr::Duration(seconds) => write!(f, "{}", Value::Duration(*seconds)),
+            Expr::Date(days) => write!(f, "{}", Value::Date(*days)),
+            Expr::Var(name) => write!(f, "{}", name),
+            Expr::BinaryOp(oper, x, y) => {
+                let precedence = oper.precedence();
+                let (left, right) = match oper.is_right_assoc() {
+                    true => (precedence + 1, precedence),
+                    false => (precedence, precedence + 1),
+                };
+                match oper {
+                    Operator::Mul | Operator::Div | Operator::Pow => write!(f, "{}{}{}", operand(x, left), oper, operand(y, right)),
+                    _ => write!(f, "{} {} {}", operand(x, left), oper, operand(y, right)),
+                }
+            },
+            Expr::UnaryOp(oper, x) => write!(f, "{}{}", oper, operand(x, oper.precedence() + 1)),
+            Expr::Postfix(oper, x) => write!(f, "{}{}", operand(x, u8::MAX), oper),
+            Expr::Unit(x, unit, 1) => write!(f, "{} {}", operand(x, u8::MAX), unit.symbol),
+            Expr::Unit(x, unit, power) => write!(f, "{} {}^{}", operand(x, u8::MAX), unit.symbol, power),
+            Expr::Call(func, args) | Expr::Lazy(func, args) => write!(f, "{}({})", func, list(args)),
+            Expr::UserCall(name, args) => write!(f, "{}({})", name, list(args)),
+            Expr::List(items) => write!(f, "[{}]", list(items)),
+            Expr::Ternary(cond, then, other) => write!(f, "{} ? {} : {}", operand(cond, 1), operand(then, 1), other),
+            Expr::Let(name, value, body) => write!(f, "let {} = {} in {}", name, value, body),
+            Expr::At(_, expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod expr_tests {
+    use crate::tests::run;
+    use super::*;
+
+    #[test]
+    fn unbound_variables_simplify() {
+        assert_eq!(run(&["x + x + 0*y"]), "2*x");
+        assert_eq!(run(&["x = 3", "x + y"]), "y + 3");
+        assert_eq!(run(&["2*x*3 + x*1 + 0"]), "7*x");
+        assert_eq!(run(&["x - x"]), "0");
+        assert_eq!(run(&["x/(y - y)"]), "Division by zero: `x / 0` (`:ieee on` allows it)");
+        assert_eq!(run(&["expand((x + 1)/0)"]), "Division by zero: `(x + 1) / 0` (`:ieee on` allows it)");
+        assert_eq!(run(&[":ieee on", "x/0"]), "inf*x");
+        assert_eq!(run(&["x*x"]), "x^2");
+        assert_eq!(run(&["x^2 * x^3"]), "x^5");
+        assert_eq!(run(&["x*y + y*x"]), "2*x*y");
+        assert_eq!(run(&["(x + y) - y"]), "x");
+        assert_eq!(run(&["2 + 3*x - 1"]), "3*x + 1");
+        assert_eq!(run(&["1*x"]), "x");
+        assert_eq!(run(&["x^0"]), "1");
+        assert_eq!(run(&["x/x"]), "1");
+        assert_eq!(run(&["-(-x)"]), "x");
+        assert_eq!(run(&["sin(x) + sin(x)"]), "2*sin(x)");
+    }
+
+    #[test]
+    fn diff_differentiates() {
+        assert_eq!(run(&["diff(x^2 + sin(x), x)"]), "2*x + cos(x)");
+        assert_eq!(run(&["diff(x^3, x)"]), "3*x^2");
+        assert_eq!(run(&["diff(x*y, x)"]), "y");
+        assert_eq!(run(&["diff(sin(x)^2, x)"]), "2*cos(x)*sin(x)");
+        assert_eq!(run(&["diff(cos(2*x), x)"]), "-2*sin(2*x)");
+        assert_eq!(run(&["diff(ln(x), x)"]), "1/x");
+        assert_eq!(run(&["diff(1/x, x)"]), "-1/x^2");
+        assert_eq!(run(&["diff(5, x)"]), "0");
+        assert_eq!(run(&["diff(y^2, y)"]), "2*y");
+        assert_eq!(run(&["diff(x, 2)"]), "Function `diff` expects a variable name, found: `2`");
+    }
+
+    #[test]
+    fn solvefor_solves_linear_equations() {
+        assert_eq!(run(&["solvefor(2*x + 3 = 11, x)"]), "4");
+        assert_eq!(run(&["solvefor(3*x - 6, x)"]), "2");
+        assert_eq!(run(&["solvefor(2*x = x + 3, x)"]), "3");
+        assert_eq!(run(&["solvefor(3 = 1 + x, x)"]), "2");
+        assert_eq!(run(&["solvefor(2*(x - 1) = 4, x)"]), "3");
+        assert_eq!(run(&["solvefor(x/2 + 1 = 0, x)"]), "-2");
+        assert_eq!(run(&["solvefor(a*x = 6, x)"]), "6/a");
+        assert_eq!(run(&["a = 3", "solvefor(a*x = 6, x)"]), "2");
+        assert_eq!(run(&["solvefor(x^2 = 4, x)"]), "Equation is not linear in `x`");
+        assert_eq!(run(&["solvefor(0*x = 1, x)"]), "Equation does not determine `x`");
+        assert_eq!(run(&["solvefor(x = x, x)"]), "Equation does not determine `x`");
+        assert_eq!(run(&["solvefor(2*x + 3 = 11, 5)"]), "Function `solvefor` expects a variable name, found: `5`");
+        assert_eq!(run(&["solvefor(x = 1"]), "Opened parentheses were not closed: {Func(solvefor/0), ParenOpen((), Var(x), Assign, Num(1)}");
+        assert!(matches!(parse("solvefor(x = 1").unwrap_err().inner(), UnclosedParens(_)));
+    }
+
+    #[test]
+    fn expand_and_factor() {
+        assert_eq!(run(&["expand((x+1)^3)"]), "x^3 + 3*x^2 + 3*x + 1");
+        assert_eq!(run(&["expand((x+1)^100)"]), "Cannot multiply out a power above 64 of a sum: `100`");
+        assert_eq!(run(&["factor((x+1)^100)"]), "Cannot multiply out a power above 64 of a sum: `100`");
+        assert_eq!(run(&["expand(x^100 + x)"]), "x^100 + x");
+        assert_eq!(run(&["factor(x^2 - 1)"]), "(x - 1)*(x + 1)");
+        assert_eq!(run(&["factor(6)"]), "6");
+        assert_eq!(run(&["expand((x+1)*(x-1))"]), "x^2 - 1");
+        assert_eq!(run(&["expand((x+y)^2)"]), "x^2 + 2*x*y + y^2");
+        assert_eq!(run(&["expand(2*(x+3))"]), "2*x + 6");
+        assert_eq!(run(&["expand((x+1)^0)"]), "1");
+        assert_eq!(run(&["factor(x^2 + 2*x + 1)"]), "(x + 1)^2");
+        assert_eq!(run(&["factor(x^2 - 5*x + 6)"]), "(x - 2)*(x - 3)");
+        assert_eq!(run(&["factor(x^3 - x)"]), "x*(x - 1)*(x + 1)");
+        assert_eq!(run(&["factor(2*x + 4)"]), "2*(x + 2)");
+        // Without rational roots, a polynomial stays as it is.
+        assert_eq!(run(&["factor(x^2 + 1)"]), "x^2 + 1");
+        assert_eq!(run(&["factor(x^2 - 2)"]), "x^2 - 2");
+    }
+
+    #[test]
+    fn taylor_expands_around_a_point() {
+        assert_eq!(run(&["taylor(sin(x), x, 0, 5)"]), "x - x^3/6 + x^5/120");
+        assert_eq!(run(&["taylor(e^x, x, 0, 3)"]), "1 + x + x^2/2 + x^3/6");
+        assert_eq!(run(&["taylor(ln(x), x, 1, 2)"]), "(x - 1) - (x - 1)^2/2");
+        assert_eq!(run(&["taylor(sin(x), x, 0, 100)"]), "`taylor` is not defined for `100`");
+        assert_eq!(run(&["taylor(1/x, x, 0, 2)"]), "`/` has no power series expansion here");
+    }
+
+    #[test]
+    fn parse_builds_trees() {
+        let tree = parse("2 * (3 + x) - sin(y)^2").unwrap();
+        assert_eq!(tree[0].to_string(), "2*(3 + x) - sin(y)^2");
+        assert!(matches!(tree[0], Expr::At(Span { start: 12, end: 13 }, _)));
+        assert_eq!(tree[0].clone().unlocated(), Expr::BinaryOp(
+            Operator::Sub,
+            Box::new(Expr::BinaryOp(Operator::Mul, Box::new(Expr::Num(2.0)),
+                Box::new(Expr::BinaryOp(Operator::Add, Box::new(Expr::Num(3.0)), Box::new(Expr::Var("x".to_string())))))),
+            Box::new(Expr::BinaryOp(Operator::Pow, Box::new(Expr::Call(Function::Sin, vec![Expr::Var("y".to_string())])), Box::new(Expr::Num(2.0)))),
+        ));
+        let trees = parse("5!; f(2); [1, 2]; let x = 2 in x + 1; x ? 1 : 2; sum(i, 1, 3, i); 2 m^2; (1 + 2)!").unwrap();
+        let shown: Vec<String> = trees.iter().map(Expr::to_string).collect();
+        assert_eq!(shown, ["5!", "f(2)", "[1, 2]", "let x = 2 in x + 1", "x ? 1 : 2", "sum(i, 1, 3, i)", "2 m^2", "(1 + 2)!"]);
+        assert_eq!(trees[1].clone().unlocated(), Expr::UserCall("f".to_string(), vec![Expr::Num(2.0)]));
+        assert!(matches!(trees[5].bare(), Expr::Lazy(Function::Sum, args) if args.len() == 4));
+        assert!(matches!(parse("1 +").unwrap_err().inner(), NotEnoughTokens(_)));
+        assert!(matches!(parse("f(1 2)").unwrap_err().inner(), NotEnoughTokens(_)));
+        assert!(matches!(parse("(1 + 2").unwrap_err().inner(), UnclosedParens(_)));
+        // Trees print back with only the parentheses their grouping needs.
+        let shown: Vec<String> = ["-x^2", "2^3^2", "(2^3)^2", "1 - (2 - 3)", "1 - 2 - 3", "a / (b * c)", "-(x + 1)"].iter().map(|input| parse(input).unwrap()[0].to_string()).collect();
+        assert_eq!(shown, ["-x^2", "2^3^2", "(2^3)^2", "1 - (2 - 3)", "1 - 2 - 3", "a/(b*c)", "-(x + 1)"]);
+        assert!(matches!(parse("-x^2").unwrap()[0].bare(), Expr::UnaryOp(UnaryOperator::Neg, _)));
+        assert!(matches!(parse("2^3^2").unwrap()[0].bare(), Expr::BinaryOp(Operator::Pow, x, _) if **x == Expr::Num(2.0)));
+    }
+
+}
+
+
+// A power series truncated to a fixed number of coefficients, lowest order first. Operations
+// that would need negative or fractional powers of the variable give `None`.
+#[derive(Debug, Clone, PartialEq)]
+struct Series(Vec<f64>);
+impl Series {
+    fn constant(x: f64, len: usize) -> Self {
+        let mut coefs = vec![0.0; len];
+        coefs[0] = x;
+        Series(coefs)
+    }
+
+    fn is_constant(&self) -> bool {
+        self.0[1..].iter().all(|&coef| coef == 0.0)
+    }
+
+    fn add(&self, other: &Series, sign: f64) -> Series {
+        Series(self.0.iter().zip(&other.0).map(|(a, b)| a + sign * b).collect())
+    }
+
+    fn scale(&self, factor: f64) -> Series {
+        Series(self.0.iter().map(|a| a * factor).collect())
+    }
+
+    fn mul(&self, other: &Series) -> Series {
+        Series((0..self.0.len()).map(|k| (0..=k).map(|j| self.0[j] * other.0[k - j]).sum()).collect())
+    }
+
+    fn div(&self, other: &Series) -> Option<Series> {
+        if other.0[0] == 0.0 {
+            return None;
+        }
+        let mut quotient = Vec::with_capacity(self.0.len());
+        for k in 0..self.0.len() {
+            let known: f64 = (0..k).map(|j| quotient[j] * other.0[k - j]).sum();
+            quotient.push((self.0[k] - known) / other.0[0]);
+        }
+        Some(Series(quotient))
+    }
+
+    fn derivative(&self) -> Series {
+        let mut coefs: Vec<f64> = (1..self.0.len()).map(|k| k as f64 * self.0[k]).collect();
+        coefs.push(0.0);
+        Series(coefs)
+    }
+
+    // The antiderivative of `self` that equals `at_zero` at the expansion point.
+    fn integral(&self, at_zero: f64) -> Series {
+        let mut coefs = vec![at_zero];
+        coefs.extend((1..self.0.len()).map(|k| self.0[k - 1] / k as f64));
+        Series(coefs)
+    }
+
+    fn exp(&self) -> Series {
+        let mut coefs = vec![self.0[0].exp()];
+        for k in 1..self.0.len() {
+            coefs.push((1..=k).map(|j| j as f64 * self.0[j] * coefs[k - j]).sum::<f64>() / k as f64);
+        }
+        Series(coefs)
+    }
+
+    fn ln(&self) -> Option<Series> {
+        match self.0[0] > 0.0 {
+            true => Some(self.derivative().div(self)?.integral(self.0[0].ln())),
+            false => None,
+        }
+    }
+
+    fn sin_cos(&self) -> (Series, Series) {
+        let (mut sin, mut cos) = (vec![self.0[0].sin()], vec![self.0[0].cos()]);
+        for k in 1..self.0.len() {
+            let term = |other: &[f64]| (1..=k).map(|j| j as f64 * self.0[j] * other[k - j]).sum::<f64>() / k as f64;
+            let (next_sin, next_cos) = (term(&cos), -term(&sin));
+            sin.push(next_sin);
+            cos.push(next_cos);
+        }
+        (Series(sin), Series(cos))
+    }
+
+    fn atan(&self) -> Option<Series> {
+        let one = Series::constant(1.0, self.0.len());
+        Some(self.derivative().div(&one.add(&self.mul(self), 1.0))?.integral(self.0[0].atan()))
+    }
+
+    fn powf(&self, exp: f64) -> Option<Series> {
+        let a0 = self.0[0];
+        if a0 == 0.0 {
+            // Only whole powers of a series without a constant term are still power series.
+            if exp.fract() != 0.0 || exp < 0.0 {
+                return None;
+            }
+            if exp >= self.0.len() as f64 {
+                return Some(Series::constant(0.0, self.0.len()));
+            }
+            let one = Series::constant(1.0, self.0.len());
+            return Some((0..exp as usize).fold(one, |power, _| power.mul(self)));
+        }
+        if a0 < 0.0 && exp.fract() != 0.0 {
+            return None;
+        }
+        let mut coefs = vec![a0.powf(exp)];
+        for k in 1..self.0.len() {
+            let sum: f64 = (1..=k).map(|j| ((exp + 1.0) * j as f64 - k as f64) * self.0[j] * coefs[k - j]).sum();
+            coefs.push(sum / (k as f64 * a0));
+        }
+        Some(Series(coefs))
+    }
+
+    fn pow(&self, exp: &Series) -> Option<Series> {
+        match exp.is_constant() {
+            true => self.powf(exp.0[0]),
+            false => Some(exp.mul(&self.ln()?).exp()),
+        }
+    }
+
+}
+
+
+// An arbitrary-precision natural number, as little-endian base 2^32 limbs with no leading zero
+// limbs, so zero has none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BigUint(Vec<u32>);
+impl BigUint {
+    fn from_u128(mut n: u128) -> Self {
+        let mut limbs = Vec::new();
+        while n > 0 {
+            limbs.push(n as u32);
+            n >>= 32;
+        }
+        BigUint(limbs)
+    }
+
+    fn parse(digits: &str) -> Self {
+        digits.bytes().fold(BigUint(Vec::new()), |n, digit| n.mul_small(10).add(&BigUint::from_u128((digit - b'0') as u128)))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn trim(mut self) -> Self {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+        self
+    }
+
+    fn to_u128(&self) -> Option<u128> {
+        match self.0.len() <= 4 {
+            true => Some(self.0.iter().rev().fold(0, |n, &limb| n << 32 | limb as u128)),
+            false => None,
+        }
+    }
+
+    fn add(&self, other: &BigUint) -> BigUint {
+        let (long, short) = if self.0.len() >= other.0.len() { (self, other) } else { (other, self) };
+        let mut limbs = Vec::with_capacity(long.0.len() + 1);
+        let mut carry = 0u64;
+        for (i, &limb) in long.0.iter().enumerate() {
+            let sum = limb as u64 + *short.0.get(i).unwrap_or(&0) as u64 + carry;
+            limbs.push(sum as u32);
+            carry = sum >> 32;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        BigUint(limbs)
+    }
+
+    // Assumes `self >= other`.
+    fn sub(&self, other: &BigUint) -> BigUint {
+        let mut limbs = Vec::with_capacity(self.0.len());
+        let mut borrow = 0u64;
+        for (i, &limb) in self.0.iter().enumerate() {
+            let (limb, sub) = (limb as u64, *other.0.get(i).unwrap_or(&0) as u64 + borrow);
+            borrow = (limb < sub) as u64;
+            limbs.push((limb + (borrow << 32) - sub) as u32);
+        }
+        BigUint(limbs).trim()
+    }
+
+    fn mul(&self, other: &BigUint) -> BigUint {
+        let mut limbs = vec![0u32; self.0.len() + other.0.len()];
+        for (i, &x) in self.0.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &y) in other.0.iter().enumerate() {
+                let product = x as u64 * y as u64 + limbs[i + j] as u64 + carry;
+                limbs[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            limbs[i + other.0.len()] = carry as u32;
+        }
+        BigUint(limbs).trim()
+    }
+
+    fn mul_small(&self, factor: u32) -> BigUint {
+        self.mul(&BigUint::from_u128(factor as u128))
+    }
+
+    fn pow(&self, mut exp: u64) -> BigUint {
+        let (mut base, mut result) = (self.clone(), BigUint::from_u128(1));
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.mul(&base);
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.mul(&base);
+            }
+        }
+        result
+    }
+
+    fn divmod_small(&self, divisor: u32) -> (BigUint, u32) {
+        let mut limbs = vec![0u32; self.0.len()];
+        let mut rem = 0u64;
+        for (i, &limb) in self.0.iter().enumerate().rev() {
+            let current = rem << 32 | limb as u64;
+            limbs[i] = (current / divisor as u64) as u32;
+            rem = current % divisor as u64;
+        }
+        (BigUint(limbs).trim(), rem as u32)
+    }
+
+    fn shl_bits(&self, shift: u32) -> BigUint {
+        if shift == 0 {
+            return self.clone();
+        }
+        let mut limbs = Vec::with_capacity(self.0.len() + 1);
+        let mut carry = 0u32;
+        for &limb in &self.0 {
+            limbs.push(limb << shift | carry);
+            carry = limb >> (32 - shift);
+        }
+        limbs.push(carry);
+        BigUint(limbs).trim()
+    }
+
+    // Knuth's algorithm D; assumes a nonzero divisor.
+    fn divmod(&self, other: &BigUint) -> (BigUint, BigUint) {
+        if *self < *other {
+            return (BigUint(Vec::new()), self.clone());
+        }
+        if other.0.len() == 1 {
+            let (quotient, rem) = self.divmod_small(other.0[0]);
+            return (quotient, BigUint::from_u128(rem as u128));
+        }
+        // Normalising makes the top limb of the divisor large enough for quotient estimates.
+        let shift = other.0.last().unwrap().leading_zeros();
+        let divisor = other.shl_bits(shift).0;
+        let mut rem = self.shl_bits(shift).0;
+        rem.resize(self.0.len() + 1, 0);
+        let n = divisor.len();
+        let mut quotient = vec![0u32; rem.len() - n];
+        for j in (0..quotient.len()).rev() {
+            let top = (rem[j + n] as u64) << 32 | rem[j + n - 1] as u64;
+            let (mut qhat, mut rhat) = (top / divisor[n - 1] as u64, top % divisor[n - 1] as u64);
+            while qhat >> 32 != 0 || qhat * divisor[n - 2] as u64 > (rhat << 32 | rem[j + n - 2] as u64) {
+                qhat -= 1;
+                rhat += divisor[n - 1] as u64;
+                if rhat >> 32 != 0 {
+                    break;
+                }
+            }
+            let (mut carry, mut borrow) = (0u64, 0u64);
+            for i in 0..=n {
+                let product = qhat * *divisor.get(i).unwrap_or(&0) as u64 + carry;
+                carry = product >> 32;
+                let (limb, sub) = (rem[i + j] as u64, (product & 0xffff_ffff) + borrow);
+                borrow = (limb < sub) as u64;
+                rem[i + j] = (limb + (borrow << 32) - sub) as u32;
+            }
+            // The estimate was one too large: add the divisor back once.
+            if borrow != 0 {
+                qhat -= 1;
+                let mut carry = 0u64;
+                for i in 0..=n {
+                    let sum = rem[i + j] as u64 + *divisor.get(i).unwrap_or(&0) as u64 + carry;
+                    rem[i + j] = sum as u32;
+                    carry = sum >> 32;
+                }
+            }
+            quotient[j] = qhat as u32;
+        }
+        rem.truncate(n);
+        let rem = match shift {
+            0 => BigUint(rem).trim(),
+            _ => {
+                let limbs = (0..n).map(|i| rem[i] >> shift | rem.get(i + 1).map_or(0, |next| next << (32 - shift))).collect();
+                BigUint(limbs).trim()
+            },
+        };
+        (BigUint(quotient).trim(), rem)
+    }
+
+    // Newton's method from an overestimate, which then decreases to the floor of the root.
+    fn sqrt(&self) -> BigUint {
+        if self.is_zero() {
+            return self.clone();
+        }
+        let bits = self.0.len() as u32 * 32 - self.0.last().unwrap().leading_zeros();
+        let mut root = BigUint::from_u128(1).shl_limbs((bits / 2 + 1) as usize);
+        loop {
+            let next = root.add(&self.divmod(&root).0).divmod_small(2).0;
+            if next >= root {
+                return root;
+            }
+            root = next;
+        }
+    }
+
+    fn shl_limbs(&self, bits: usize) -> BigUint {
+        let mut limbs = vec![0u32; bits / 32];
+        limbs.extend(&self.0);
+        BigUint(limbs).shl_bits((bits % 32) as u32)
+    }
+
+    fn digits(&self) -> usize {
+        self.to_string().len()
+    }
+
+    fn to_radix(&self, radix: u32) -> String {
+        let (mut digits, mut rest) = (Vec::new(), self.clone());
+        while !rest.is_zero() {
+            let (quotient, digit) = rest.divmod_small(radix);
+            digits.push(char::from_digit(digit, radix).unwrap());
+            rest = quotient;
+        }
+        match digits.is_empty() {
+            true => String::from("0"),
+            false => digits.iter().rev().collect(),
+        }
+    }
+
+    fn bits(&self) -> u64 {
+        match self.0.last() {
+            Some(top) => self.0.len() as u64 * 32 - top.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+}
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.len().cmp(&other.0.len()).then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (mut chunks, mut rest) = (Vec::new(), self.clone());
+        while !rest.is_zero() {
+            let (quotient, chunk) = rest.divmod_small(1_000_000_000);
+            chunks.push(chunk);
+            rest = quotient;
+        }
+        match chunks.pop() {
+            None => write!(f, "0"),
+            Some(top) => {
+                write!(f, "{}", top)?;
+                chunks.iter().rev().try_for_each(|chunk| write!(f, "{:09}", chunk))
+            },
+        }
+    }
+}
+
+
+// A sign and magnitude, where zero is never negative.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: BigUint,
+}
+impl BigInt {
+    fn new(negative: bool, magnitude: BigUint) -> Self {
+        BigInt { negative: negative && !magnitude.is_zero(), magnitude }
+    }
+
+    fn from_i128(n: i128) -> Self {
+        BigInt::new(n < 0, BigUint::from_u128(n.unsigned_abs()))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.magnitude.is_zero()
+    }
+
+    fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap()
+    }
+
+    fn to_i128(&self) -> Option<i128> {
+        let magnitude = i128::try_from(self.magnitude.to_u128()?).ok()?;
+        Some(if self.negative { -magnitude } else { magnitude })
+    }
+
+    fn neg(&self) -> BigInt {
+        BigInt::new(!self.negative, self.magnitude.clone())
+    }
+
+    fn add(&self, other: &BigInt) -> BigInt {
+        match (self.negative == other.negative, self.magnitude >= other.magnitude) {
+            (true, _) => BigInt::new(self.negative, self.magnitude.add(&other.magnitude)),
+            (false, true) => BigInt::new(self.negative, self.magnitude.sub(&other.magnitude)),
+            (false, false) => BigInt::new(other.negative, other.magnitude.sub(&self.magnitude)),
+        }
+    }
+
+    fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    fn mul(&self, other: &BigInt) -> BigInt {
+        BigInt::new(self.negative != other.negative, self.magnitude.mul(&other.magnitude))
+    }
+
+    // Floored division, so the remainder takes the sign of the divisor like `%` on floats.
+    fn divmod(&self, other: &BigInt) -> (BigInt, BigInt) {
+        let (quotient, rem) = self.magnitude.divmod(&other.magnitude);
+        let (quotient, rem) = (BigInt::new(self.negative != other.negative, quotient), BigInt::new(self.negative, rem));
+        match !rem.is_zero() && rem.negative != other.negative {
+            true => (quotient.sub(&BigInt::from_i128(1)), rem.add(other)),
+            false => (quotient, rem),
+        }
+    }
+
+    // Square-and-multiply over the bits of the exponent, highest first.
+    fn pow_mod(&self, exp: &BigUint, modulus: &BigInt) -> BigInt {
+        let mut result = BigInt::from_i128(1).divmod(modulus).1;
+        for bit in (0..exp.bits()).rev() {
+            result = result.mul(&result).divmod(modulus).1;
+            if exp.0[bit as usize / 32] >> (bit % 32) & 1 == 1 {
+                result = result.mul(self).divmod(modulus).1;
+            }
+        }
+        result
+    }
+
+}
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, false) => self.magnitude.cmp(&other.magnitude),
+            (true, true) => other.magnitude.cmp(&self.magnitude),
+            (negative, _) => match negative {
+                true => Ordering::Less,
+                false => Ordering::Greater,
+            },
+        }
+    }
+}
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", if self.negative { "-" } else { "" }, self.magnitude)
+    }
+}
+
+#[cfg(test)]
+mod bigint_tests {
+    use crate::tests::run;
+    use super::*;
+
+    #[test]
+    fn integer_results_promote() {
+        assert_eq!(run(&["2^200"]), "1606938044258990275541962092341162602522202993782792835301376");
+        assert_eq!(run(&["2^64 + 1"]), "18446744073709551617");
+        assert!(run(&["100!"]).starts_with("93326215443944152681699238856266700490715968264381621468592963895217599993229915608941463976156518286253697920827223758251185210916864"));
+        assert_eq!(run(&["9007199254740993"]), "9007199254740993");
+        assert_eq!(run(&["-9_007_199_254_740_993 * 2"]), "-18014398509481986");
+        assert_eq!(run(&[":thousands on", "9,007,199,254,740,993"]), "9007199254740993");
+        assert_eq!(run(&["0x20000000000001"]), "9007199254740993");
+        assert_eq!(run(&["-(2^63)"]), "-9223372036854775808");
+        assert_eq!(run(&["20! * 21"]), "51090942171709440000");
+        assert_eq!(run(&["2^70 // 3"]), "393530540239137101141");
+        assert_eq!(run(&["2^100 / 2^99"]), "2");
+        assert_eq!(run(&["2^100 / 3"]), "4.2255020007607644e29");
+    }
+
+    #[test]
+    fn arithmetic_is_exact() {
+        let big = BigUint::parse("123456789012345678901234567890");
+        let (quotient, rem) = big.mul(&big).add(&BigUint::from_u128(7)).divmod(&big);
+        assert_eq!((quotient.to_string(), rem.to_string()), ("123456789012345678901234567890".to_string(), "7".to_string()));
+        assert_eq!(BigUint::from_u128(1 << 100).sqrt().to_u128(), Some(1 << 50));
+        let (a, b) = (BigInt::from_i128(-7), BigInt::from_i128(2));
+        assert_eq!(a.mul(&b).add(&b).to_string(), "-12");
+        assert_eq!(a.cmp(&b), Ordering::Less);
+    }
+
+}
+
+
+// A number type to solve expressions in, through `Expr::solve_in` or `eval_in`. Every method
+// gives `None` for what the type can't do, which leaves that expression to floating point.
+pub trait Numeric: Sized {
+    // Whatever else its arithmetic depends on, such as a precision.
+    type Context: Copy;
+
+    fn from_f64(float: f64, context: Self::Context) -> Option<Self>;
+
+    // Variables and big integers, which are read through floating point unless overridden.
+    fn from_value(value: Value, context: Self::Context) -> Option<Self> {
+        Self::from_f64(value.as_num().ok()?, context)
+    }
+
+    fn call(oper: &Operator, x: Self, y: Self, context: Self::Context) -> Option<Self>;
+
+    fn call_unary(oper: &UnaryOperator, x: Self, context: Self::Context) -> Option<Self>;
+
+    fn call_func(_func: &Function, _args: Vec<Self>, _context: Self::Context) -> Option<Self> {
+        None
+    }
+}
+impl Numeric for f64 {
+    type Context = ();
+
+    fn from_f64(float: f64, _: ()) -> Option<Self> {
+        Some(float)
+    }
+
+    fn call(oper: &Operator, x: f64, y: f64, _: ()) -> Option<f64> {
+        oper.call(x, y).ok()
+    }
+
+    fn call_unary(oper: &UnaryOperator, x: f64, _: ()) -> Option<f64> {
+        oper.call(x).ok()
+    }
+
+    fn call_func(func: &Function, args: Vec<f64>, _: ()) -> Option<f64> {
+        func.call(&args).ok()
+    }
+}
+// Single precision throughout, as other hardware would compute it.
+impl Numeric for f32 {
+    type Context = Angle;
+
+    fn from_f64(float: f64, _: Angle) -> Option<Self> {
+        Some(float as f32)
+    }
+
+    fn from_value(value: Value, _: Angle) -> Option<Self> {
+        match value {
+            Value::Single(float) => Some(float),
+            value => Some(value.as_num().ok()? as f32),
+        }
+    }
+
+    fn call(oper: &Operator, x: f32, y: f32, _: Angle) -> Option<f32> {
+        let truth = |holds: bool| Some(holds as u8 as f32);
+        match oper {
+            Operator::Add => Some(x + y),
+            Operator::Sub => Some(x - y),
+            Operator::Mul => Some(x * y),
+            Operator::Div => Some(x / y),
+            Operator::FloorDiv if y != 0.0 => Some((x / y).floor()),
+            Operator::Mod => match x % y {
+                rem if rem != 0.0 && (rem < 0.0) != (y < 0.0) => Some(rem + y),
+                rem => Some(rem),
+            },
+            Operator::Pow => Some(x.powf(y)),
+            Operator::Lt => truth(x < y),
+            Operator::Le => truth(x <= y),
+            Operator::Gt => truth(x > y),
+            Operator::Ge => truth(x >= y),
+            Operator::Eq => truth(x == y),
+            Operator::Ne => truth(x != y),
+            _ => None,
+        }
+    }
+
+    fn call_unary(oper: &UnaryOperator, x: f32, _: Angle) -> Option<f32> {
+        match oper {
+            UnaryOperator::Neg => Some(-x),
+            UnaryOperator::Sqrt if x >= 0.0 => Some(x.sqrt()),
+            _ => None,
+        }
+    }
+
+    // Errors are left for floating point to report.
+    fn call_func(func: &Function, args: Vec<f32>, angle: Angle) -> Option<f32> {
+        let radians = |x: f32| match angle {
+            Angle::Radians => x,
+            Angle::Degrees => x.to_radians(),
+        };
+        let from_radians = |x: f32| match angle {
+            Angle::Radians => x,
+            Angle::Degrees => x.to_degrees(),
+        };
+        match (func, &args[..]) {
+            (Function::Min, [_, ..]) => Some(args.iter().copied().fold(f32::INFINITY, f32::min)),
+            (Function::Max, [_, ..]) => Some(args.iter().copied().fold(f32::NEG_INFINITY, f32::max)),
+            (Function::Sin, [x]) => Some(radians(*x).sin()),
+            (Function::Cos, [x]) => Some(radians(*x).cos()),
+            (Function::Tan, [x]) => Some(radians(*x).tan()),
+            (Function::Atan, [x]) => Some(from_radians(x.atan())),
+            (Function::Atan2, [y, x]) => Some(from_radians(y.atan2(*x))),
+            (Function::Sqrt, [x]) if *x >= 0.0 => Some(x.sqrt()),
+            (Function::Cbrt, [x]) => Some(x.cbrt()),
+            (Function::Hypot, [x, y]) => Some(x.hypot(*y)),
+            (Function::Ln, [x]) if *x > 0.0 => Some(x.ln()),
+            (Function::Log, [x]) if *x > 0.0 => Some(x.log10()),
+            (Function::Log2, [x]) if *x > 0.0 => Some(x.log2()),
+            (Function::Abs, [x]) => Some(x.abs()),
+            (Function::Round, [x]) => Some(x.round()),
+            (Function::Floor, [x]) => Some(x.floor()),
+            (Function::Ceil, [x]) => Some(x.ceil()),
+            (Function::Trunc, [x]) => Some(x.trunc()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod f32_tests {
+    use crate::tests::run;
+    use super::*;
+
+    #[test]
+    fn rounds_like_single_precision() {
+        assert_eq!(run(&[":f32 on", "0.1 + 0.2"]), "0.3");
+        assert_eq!(run(&[":f32 on", "16777216 + 1"]), "16777216");
+        assert_eq!(run(&[":f32 on", ":places 3", "1 / 3"]), "0.333");
+        assert_eq!(run(&[":f32 on", "2^100"]), "1.2676506e30");
+        assert_eq!(run(&[":f32 on", "2^128"]), "`2^128` gives inf (`:ieee on` allows it)");
+        assert_eq!(run(&[":f32 on", "2^200 / 2^190"]), "`2^200/2^190` gives NaN (`:ieee on` allows it)");
+        assert_eq!(run(&[":f32 on", ":ieee on", "2^128"]), "inf");
+        assert_eq!(run(&[":f32 on", "1/3"]), "0.33333334");
+        assert_eq!(run(&[":f32 on", "sqrt(2)"]), "1.4142135");
+        assert_eq!(run(&[":f32 on", ":f32 off", "16777216 + 1"]), "16777217");
+    }
+
+    #[test]
+    fn float_results_warn() {
+        for input in ["[0.1, 0.2] * 3", "gamma(0.5)", "mean(1, 2, 3)", "sum(i, 1, 3, i / 10)"] {
+            let mut env = Environment::new();
+            env.command("f32 on").unwrap();
+            env.run(input).unwrap();
+            assert!(env.take_warnings().contains(&Warning::SingleFallback), "{}", input);
+        }
+    }
+
+}
+
+
+// `value + slope * ε` with `ε² = 0`, so that arithmetic carries the derivative along exactly.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Dual {
+    value: f64,
+    slope: f64,
+}
+impl Dual {
+    // Applies `f`, given its derivative at the value, by the chain rule.
+    fn chain(self, value: f64, derivative: f64) -> Dual {
+        Dual { value, slope: derivative * self.slope }
+    }
+
+}
+// Anything not differentiable here gives `None`, leaving finite differences to cope.
+impl Numeric for Dual {
+    type Context = Angle;
+
+    fn from_f64(float: f64, _: Angle) -> Option<Self> {
+        Some(Dual { value: float, slope: 0.0 })
+    }
+
+    fn call(oper: &Operator, x: Dual, y: Dual, _: Angle) -> Option<Dual> {
+        let truth = |holds: bool| Some(Dual { value: holds as u8 as f64, slope: 0.0 });
+        match oper {
+            Operator::Add => Some(Dual { value: x.value + y.value, slope: x.slope + y.slope }),
+            Operator::Sub => Some(Dual { value: x.value - y.value, slope: x.slope - y.slope }),
+            Operator::Mul => Some(Dual { value: x.value * y.value, slope: x.slope * y.value + x.value * y.slope }),
+            Operator::Div if y.value != 0.0 => {
+                Some(Dual { value: x.value / y.value, slope: (x.slope * y.value - x.value * y.slope) / (y.value * y.value) })
+            },
+            Operator::Pow if y.slope == 0.0 && y.value == 0.0 => Some(Dual { value: 1.0, slope: 0.0 }),
+            Operator::Pow if y.slope == 0.0 => Some(x.chain(x.value.powf(y.value), y.value * x.value.powf(y.value - 1.0))),
+            Operator::Pow if x.value > 0.0 => {
+                let value = x.value.powf(y.value);
+                Some(Dual { value, slope: value * (y.slope * x.value.ln() + y.value * x.slope / x.value) })
+            },
+            Operator::Lt => truth(x.value < y.value),
+            Operator::Le => truth(x.value <= y.value),
+            Operator::Gt => truth(x.value > y.value),
+            Operator::Ge => truth(x.value >= y.value),
+            Operator::Eq => truth(x.value == y.value),
+            Operator::Ne => truth(x.value != y.value),
+            _ => None,
+        }
+    }
+
+    fn call_unary(oper: &UnaryOperator, x: Dual, _: Angle) -> Option<Dual> {
+        match oper {
+            UnaryOperator::Neg => Some(Dual { value: -x.value, slope: -x.slope }),
+            UnaryOperator::Sqrt if x.value > 0.0 => Some(x.chain(x.value.sqrt(), 0.5 / x.value.sqrt())),
+            _ => None,
+        }
+    }
+
+    fn call_func(func: &Function, args: Vec<Dual>, angle: Angle) -> Option<Dual> {
+        // Radians per unit of the angle mode.
+        let scale = angle.radians_from(1.0);
+        match (func, &args[..]) {
+            (Function::Sin, [x]) => Some(x.chain((scale * x.value).sin(), scale * (scale * x.value).cos())),
+            (Function::Cos, [x]) => Some(x.chain((scale * x.value).cos(), -scale * (scale * x.value).sin())),
+            (Function::Tan, [x]) => {
+                let cos = (scale * x.value).cos();
+                Some(x.chain((scale * x.value).tan(), scale / (cos * cos)))
+            },
+            (Function::Atan, [x]) => Some(x.chain(x.value.atan() / scale, 1.0 / (scale * (1.0 + x.value * x.value)))),
+            (Function::Sqrt, [x]) if x.value > 0.0 => Some(x.chain(x.value.sqrt(), 0.5 / x.value.sqrt())),
+            (Function::Cbrt, [x]) if x.value != 0.0 => Some(x.chain(x.value.cbrt(), 1.0 / (3.0 * x.value.cbrt().powi(2)))),
+            (Function::Ln, [x]) if x.value > 0.0 => Some(x.chain(x.value.ln(), 1.0 / x.value)),
+            (Function::Log, [x]) if x.value > 0.0 => Some(x.chain(x.value.log10(), 1.0 / (x.value * consts::LN_10))),
+            (Function::Log2, [x]) if x.value > 0.0 => Some(x.chain(x.value.log2(), 1.0 / (x.value * consts::LN_2))),
+            (Function::Abs, [x]) if x.value != 0.0 => Some(x.chain(x.value.abs(), x.value.signum())),
+            (Function::Hypot, [x, y]) if x.value != 0.0 || y.value != 0.0 => {
+                let value = x.value.hypot(y.value);
+                Some(Dual { value, slope: (x.value * x.slope + y.value * y.slope) / value })
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod dual_tests {
+    use crate::tests::run;
+
+    #[test]
+    fn derivatives_are_exact() {
+        assert_eq!(run(&[":dual on", "deriv(x^3, x, 2)"]), "12");
+        assert_eq!(run(&[":dual on", "deriv(sin(x), x, 0)"]), "1");
+        assert_eq!(run(&[":dual on", "deriv(e^(2*x), x, 0)"]), "2");
+        assert_eq!(run(&[":dual on", "deriv(ln(x), x, 2)"]), "0.5");
+        assert_eq!(run(&[":dual on", "deriv(sqrt(x), x, 4)"]), "0.25");
+        assert_eq!(run(&[":dual on", "deriv(1/x, x, 2)"]), "-0.25");
+        assert_eq!(run(&[":dual on", "deriv(abs(x), x, -2)"]), "-1");
+        assert_eq!(run(&[":dual on", "deriv(x^x, x, 1)"]), "1");
+        assert_eq!(run(&[":dual on", "deriv(5, x, 1)"]), "0");
+        assert_eq!(run(&[":dual on", ":dual off", "deriv(x^3, x, 2)"]), "11.99999999999927");
+    }
+
+}
+
+
+// `mantissa * 10^exponent`, kept to a number of significant digits by the operations that
+// can produce more.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decimal {
+    mantissa: BigInt,
+    exponent: i64,
+}
+impl Decimal {
+    fn from_int(n: i128) -> Self {
+        Decimal { mantissa: BigInt::from_i128(n), exponent: 0 }
+    }
+
+    // Takes the shortest decimal that reads back as `x`, which is what the user typed for any
+    // literal with up to 17 significant digits.
+    fn from_f64(x: f64) -> Option<Self> {
+        if !x.is_finite() {
+            return None;
+        }
+        let repr = format!("{:e}", x);
+        let (mantissa, exponent) = repr.split_once('e').unwrap();
+        let (negative, mantissa) = match mantissa.strip_prefix('-') {
+            Some(mantissa) => (true, mantissa),
+            None => (false, mantissa),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let magnitude = BigUint::parse(&format!("{}{}", int, frac));
+        Some(Decimal { mantissa: BigInt::new(negative, magnitude), exponent: exponent.parse::<i64>().unwrap() - frac.len() as i64 }.normalize())
+    }
+
+    fn to_f64(&self) -> f64 {
+        format!("{}e{}", self.mantissa, self.exponent).parse().unwrap()
+    }
+
+    fn is_zero(&self) -> bool {
+        self.mantissa.is_zero()
+    }
+
+    fn is_integer(&self) -> bool {
+        self.exponent >= 0
+    }
+
+    fn normalize(mut self) -> Self {
+        if self.is_zero() {
+            return Decimal { mantissa: self.mantissa, exponent: 0 };
+        }
+        loop {
+            let (quotient, rem) = self.mantissa.magnitude.divmod_small(10);
+            if rem != 0 {
+                return self;
+            }
+            self.mantissa = BigInt::new(self.mantissa.negative, quotient);
+            self.exponent += 1;
+        }
+    }
+
+    // Rounds half away from zero to at most `digits` significant digits.
+    fn round(self, digits: usize) -> Self {
+        let len = self.mantissa.magnitude.digits();
+        if len <= digits {
+            return self.normalize();
+        }
+        let scale = BigUint::from_u128(10).pow((len - digits) as u64);
+        let (quotient, rem) = self.mantissa.magnitude.divmod(&scale);
+        let quotient = match rem.mul_small(2) >= scale {
+            true => quotient.add(&BigUint::from_u128(1)),
+            false => quotient,
+        };
+        let mantissa = BigInt::new(self.mantissa.negative, quotient);
+        Decimal { mantissa, exponent: self.exponent + (len - digits) as i64 }.normalize()
+    }
+
+    // Both mantissas over the smaller exponent.
+    fn align(&self, other: &Decimal) -> (BigInt, BigInt, i64) {
+        let exponent = self.exponent.min(other.exponent);
+        let scale = |x: &Decimal| {
+            let factor = BigUint::from_u128(10).pow((x.exponent - exponent) as u64);
+            BigInt::new(x.mantissa.negative, x.mantissa.magnitude.mul(&factor))
+        };
+        (scale(self), scale(other), exponent)
+    }
+
+    fn add(&self, other: &Decimal) -> Decimal {
+        let (x, y, exponent) = self.align(other);
+        Decimal { mantissa: x.add(&y), exponent }
+    }
+
+    fn sub(&self, other: &Decimal) -> Decimal {
+        self.add(&other.neg())
+    }
+
+    fn neg(&self) -> Decimal {
+        Decimal { mantissa: self.mantissa.neg(), exponent: self.exponent }
+    }
+
+    fn mul(&self, other: &Decimal) -> Decimal {
+        Decimal { mantissa: self.mantissa.mul(&other.mantissa), exponent: self.exponent + other.exponent }
+    }
+
+    fn div(&self, other: &Decimal, digits: usize) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+        // Enough extra digits in the dividend that the quotient has one more than needed.
+        let shift = (digits + 1 + other.mantissa.magnitude.digits()).saturating_sub(self.mantissa.magnitude.digits());
+        let dividend = self.mantissa.magnitude.mul(&BigUint::from_u128(10).pow(shift as u64));
+        let (quotient, _) = dividend.divmod(&other.mantissa.magnitude);
+        let mantissa = BigInt::new(self.mantissa.negative != other.mantissa.negative, quotient);
+        Some(Decimal { mantissa, exponent: self.exponent - other.exponent - shift as i64 }.round(digits))
+    }
+
+    fn powi(&self, exp: i64, digits: usize) -> Option<Decimal> {
+        let (mut base, mut result) = (self.clone(), Decimal::from_int(1));
+        let mut rest = exp.unsigned_abs();
+        while rest > 0 {
+            if rest & 1 == 1 {
+                result = result.mul(&base).round(digits + 2);
+            }
+            rest >>= 1;
+            if rest > 0 {
+                base = base.mul(&base).round(digits + 2);
+            }
+        }
+        match exp < 0 {
+            true => Decimal::from_int(1).div(&result, digits),
+            false => Some(result.round(digits)),
+        }
+    }
+
+    fn sqrt(&self, digits: usize) -> Option<Decimal> {
+        if self.mantissa.negative {
+            return None;
+        }
+        // An even exponent, and enough digits under the root for the precision wanted.
+        let len = self.mantissa.magnitude.digits() as i64;
+        let mut shift = (2 * (digits as i64 + 1) - len).max(0);
+        if (self.exponent - shift) % 2 != 0 {
+            shift += 1;
+        }
+        let radicand = self.mantissa.magnitude.mul(&BigUint::from_u128(10).pow(shift as u64));
+        let mantissa = BigInt::new(false, radicand.sqrt());
+        Some(Decimal { mantissa, exponent: (self.exponent - shift) / 2 }.round(digits))
+    }
+
+}
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let (x, y, _) = self.align(other);
+        Some(x.cmp(&y))
+    }
+}
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.mantissa.negative { "-" } else { "" };
+        let digits = self.mantissa.magnitude.to_string();
+        let point = digits.len() as i64 + self.exponent;
+        match point {
+            // Scientific notation rather than long runs of padding zeros.
+            point if point > digits.len().max(21) as i64 || point < -20 => {
+                let (first, rest) = digits.split_at(1);
+                let rest = if rest.is_empty() { String::new() } else { format!(".{}", rest) };
+                write!(f, "{}{}{}e{}", sign, first, rest, point - 1)
+            },
+            point if point <= 0 => write!(f, "{}0.{}{}", sign, "0".repeat(-point as usize), digits),
+            point if point >= digits.len() as i64 => write!(f, "{}{}{}", sign, digits, "0".repeat((point - digits.len() as i64) as usize)),
+            point => write!(f, "{}{}.{}", sign, &digits[..point as usize], &digits[point as usize..]),
+        }
+    }
+}
+impl Numeric for Decimal {
+    // Significant digits.
+    type Context = usize;
+
+    // As for exact fractions, a float past 15 significant digits is more likely a constant such
+    // as `pi` than what was typed, and known only to double precision.
+    fn from_f64(float: f64, _: usize) -> Option<Self> {
+        Decimal::from_f64(float).filter(|decimal| decimal.mantissa.magnitude.digits() <= 15)
+    }
+
+    fn from_value(value: Value, digits: usize) -> Option<Self> {
+        match value {
+            Value::Decimal(decimal) => Some(decimal),
+            Value::BigInt(int) => Some(Decimal { mantissa: int, exponent: 0 }),
+            value => <Decimal as Numeric>::from_f64(value.as_num().ok()?, digits),
+        }
+    }
+
+    fn call(oper: &Operator, x: Decimal, y: Decimal, digits: usize) -> Option<Decimal> {
+        let truth = |holds: bool| Some(Decimal::from_int(holds as i128));
+        match oper {
+            Operator::Add => Some(x.add(&y).round(digits)),
+            Operator::Sub => Some(x.sub(&y).round(digits)),
+            Operator::Mul => Some(x.mul(&y).round(digits)),
+            Operator::Div => x.div(&y, digits),
+            // Only integer powers stay exact; others go through floating point.
+            Operator::Pow if y.is_integer() && y.mantissa.magnitude.digits() <= 6 => {
+                let exp = y.mantissa.magnitude.to_u128()? as i64 * 10i64.pow(y.exponent as u32);
+                x.powi(if y.mantissa.negative { -exp } else { exp }, digits)
+            },
+            Operator::Lt => truth(x < y),
+            Operator::Le => truth(x <= y),
+            Operator::Gt => truth(x > y),
+            Operator::Ge => truth(x >= y),
+            Operator::Eq => truth(x == y),
+            Operator::Ne => truth(x != y),
+            _ => None,
+        }
+    }
+
+    fn call_unary(oper: &UnaryOperator, x: Decimal, digits: usize) -> Option<Decimal> {
+        match oper {
+            UnaryOperator::Neg => Some(x.neg()),
+            UnaryOperator::Sqrt => x.sqrt(digits),
+            _ => None,
+        }
+    }
+
+    fn call_func(func: &Function, mut args: Vec<Decimal>, digits: usize) -> Option<Decimal> {
+        match (func, args.len()) {
+            (Function::Sqrt, 1) => args.pop()?.sqrt(digits),
+            (Function::Abs, 1) => {
+                let x = args.pop()?;
+                Some(if x.mantissa.negative { x.neg() } else { x })
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod decimal_tests {
+    use crate::tests::run;
+    use super::*;
+
+    #[test]
+    fn precision_gives_many_digits() {
+        assert_eq!(run(&[":precision 50", "1/3"]), "0.33333333333333333333333333333333333333333333333333");
+        assert_eq!(run(&[":precision 50", "0.1 + 0.2"]), "0.3");
+        assert_eq!(run(&[":precision 30", "sqrt(2)"]), "1.41421356237309504880168872421");
+        assert_eq!(run(&[":precision 20", "1/7"]), "0.14285714285714285714");
+        assert_eq!(run(&[":precision 5", "2/3"]), "0.66667");
+        assert_eq!(run(&[":precision 50", "1/0"]), "Division by zero: `1 / 0` (`:ieee on` allows it)");
+        assert_eq!(run(&[":precision 0"]), "Unknown setting or value: `:precision 0`");
+    }
+
+    #[test]
+    fn unsupported_operations_warn() {
+        for input in ["2^0.5", "sin(1)", "pi"] {
+            let mut env = Environment::new();
+            env.command("precision 50").unwrap();
+            env.run(input).unwrap();
+            assert_eq!(env.take_warnings(), vec![Warning::FloatFallback(50)], "{}", input);
+        }
+    }
+
+    #[test]
+    fn arithmetic_is_exact() {
+        let (a, b) = (Decimal::from_f64(0.1).unwrap(), Decimal::from_f64(0.2).unwrap());
+        assert_eq!(a.add(&b).to_string(), "0.3");
+        assert_eq!(Decimal::from_int(2).powi(-2, 10).unwrap().to_string(), "0.25");
+        assert_eq!(Decimal::from_int(1).div(&Decimal::from_int(0), 10), None);
+    }
+
+}
+
+
+// A decimal with a fixed number of places, as a count of their smallest unit. Sums and
+// differences are exact, and other results round half to even, as is usual with money.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Fixed(i128);
+impl Fixed {
+    fn from_int(n: i128) -> Self {
+        Fixed(n * 10i128.pow(FIXED_PLACES as u32))
+    }
+
+    fn from_decimal(decimal: &Decimal) -> Option<Self> {
+        let magnitude = &decimal.mantissa.magnitude;
+        let scaled = match decimal.exponent + FIXED_PLACES {
+            places if places >= 0 => magnitude.mul(&BigUint::from_u128(10).pow(places as u64)),
+            // Digits beyond the last place can't be kept exactly.
+            places => match magnitude.divmod(&BigUint::from_u128(10).pow(-places as u64)) {
+                (quotient, rem) if rem.is_zero() => quotient,
+                _ => return None,
+            },
+        };
+        Fixed::from_scaled(decimal.mantissa.negative, scaled)
+    }
+
+    fn from_scaled(negative: bool, magnitude: BigUint) -> Option<Self> {
+        let magnitude = i128::try_from(magnitude.to_u128()?).ok()?;
+        Some(Fixed(if negative { -magnitude } else { magnitude }))
+    }
+
+    fn to_decimal(self) -> Decimal {
+        Decimal { mantissa: BigInt::from_i128(self.0), exponent: -FIXED_PLACES }.normalize()
+    }
+
+    fn divide_rounded(dividend: &BigUint, divisor: &BigUint) -> BigUint {
+        let (quotient, rem) = dividend.divmod(divisor);
+        let odd = quotient.0.first().is_some_and(|limb| limb & 1 == 1);
+        match rem.mul_small(2).cmp(divisor) {
+            Ordering::Greater => quotient.add(&BigUint::from_u128(1)),
+            Ordering::Equal if odd => quotient.add(&BigUint::from_u128(1)),
+            _ => quotient,
+        }
+    }
+
+    fn mul(self, other: Fixed) -> Option<Fixed> {
+        let product = BigUint::from_u128(self.0.unsigned_abs()).mul(&BigUint::from_u128(other.0.unsigned_abs()));
+        let scaled = Fixed::divide_rounded(&product, &BigUint::from_u128(10).pow(FIXED_PLACES as u64));
+        Fixed::from_scaled((self.0 < 0) != (other.0 < 0), scaled)
+    }
+
+    fn div(self, other: Fixed) -> Option<Fixed> {
+        if other.0 == 0 {
+            return None;
+        }
+        let dividend = BigUint::from_u128(self.0.unsigned_abs()).mul(&BigUint::from_u128(10).pow(FIXED_PLACES as u64));
+        let scaled = Fixed::divide_rounded(&dividend, &BigUint::from_u128(other.0.unsigned_abs()));
+        Fixed::from_scaled((self.0 < 0) != (other.0 < 0), scaled)
+    }
+
+    // The value as an integer, if it is one.
+    fn whole(self) -> Option<i128> {
+        let unit = 10i128.pow(FIXED_PLACES as u32);
+        match self.0 % unit {
+            0 => Some(self.0 / unit),
+            _ => None,
+        }
+    }
+
+    // Rounds to a number of decimal places the way `func` does.
+    fn round(self, func: Function, places: i64) -> Option<Fixed> {
+        if places >= FIXED_PLACES {
+            return Some(self);
+        }
+        let unit = 10i128.checked_pow(u32::try_from(FIXED_PLACES - places).ok()?)?;
+        let (quotient, rem) = (self.0.div_euclid(unit), self.0.rem_euclid(unit));
+        let quotient = match func {
+            Function::Floor => quotient,
+            Function::Ceil => quotient + (rem != 0) as i128,
+            Function::Trunc => quotient + (rem != 0 && self.0 < 0) as i128,
+            _ => match (2 * rem).cmp(&unit) {
+                Ordering::Greater => quotient + 1,
+                Ordering::Equal => quotient + (quotient % 2 != 0) as i128,
+                Ordering::Less => quotient,
+            },
+        };
+        quotient.checked_mul(unit).map(Fixed)
+    }
+
+}
+impl Numeric for Fixed {
+    type Context = ();
+
+    fn from_f64(float: f64, _: ()) -> Option<Self> {
+        Fixed::from_decimal(&Decimal::from_f64(float)?)
+    }
+
+    fn from_value(value: Value, _: ()) -> Option<Self> {
+        match value {
+            Value::Decimal(decimal) => Fixed::from_decimal(&decimal),
+            Value::BigInt(int) => Fixed::from_decimal(&Decimal { mantissa: int, exponent: 0 }),
+            value => Fixed::from_f64(value.as_num().ok()?, ()),
+        }
+    }
+
+    fn call(oper: &Operator, x: Fixed, y: Fixed, _: ()) -> Option<Fixed> {
+        let truth = |holds: bool| Some(Fixed::from_int(holds as i128));
+        match oper {
+            Operator::Add => x.0.checked_add(y.0).map(Fixed),
+            Operator::Sub => x.0.checked_sub(y.0).map(Fixed),
+            Operator::Mul => x.mul(y),
+            Operator::Div => x.div(y),
+            Operator::FloorDiv if y.0 != 0 => x.0.div_euclid(y.0).checked_sub((x.0.rem_euclid(y.0) != 0 && y.0 < 0) as i128)?.checked_mul(10i128.pow(FIXED_PLACES as u32)).map(Fixed),
+            // Floored, like `%` on floats.
+            Operator::Mod if y.0 != 0 => match x.0 % y.0 {
+                rem if rem != 0 && (rem < 0) != (y.0 < 0) => Some(Fixed(rem + y.0)),
+                rem => Some(Fixed(rem)),
+            },
+            Operator::Pow => {
+                let exp = y.whole()?;
+                let power = (0..exp.unsigned_abs().min(u32::MAX as u128)).try_fold(Fixed::from_int(1), |power, _| power.mul(x))?;
+                match exp < 0 {
+                    true => Fixed::from_int(1).div(power),
+                    false => Some(power),
+                }
+            },
+            Operator::Lt => truth(x < y),
+            Operator::Le => truth(x <= y),
+            Operator::Gt => truth(x > y),
+            Operator::Ge => truth(x >= y),
+            Operator::Eq => truth(x == y),
+            Operator::Ne => truth(x != y),
+            _ => None,
+        }
+    }
+
+    fn call_unary(oper: &UnaryOperator, x: Fixed, _: ()) -> Option<Fixed> {
+        match oper {
+            UnaryOperator::Neg => x.0.checked_neg().map(Fixed),
+            _ => None,
+        }
+    }
+
+    fn call_func(func: &Function, args: Vec<Fixed>, _: ()) -> Option<Fixed> {
+        match (func, &args[..]) {
+            (Function::Abs, [x]) => x.0.checked_abs().map(Fixed),
+            (Function::Round | Function::Floor | Function::Ceil | Function::Trunc, [x]) => x.round(*func, 0),
+            (Function::Round | Function::Floor | Function::Ceil | Function::Trunc, [x, places]) => {
+                x.round(*func, i64::try_from(places.whole()?).ok()?)
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod fixed_tests {
+    use crate::tests::run;
+    use super::*;
+
+    #[test]
+    fn cents_never_drift() {
+        assert_eq!(run(&[":decimal on", "0.1 + 0.2"]), "0.3");
+        assert_eq!(run(&[":decimal on", "0.1 + 0.2 == 0.3"]), "1");
+        assert_eq!(run(&[":decimal on", "19.99 * 3"]), "59.97");
+        assert_eq!(run(&[":decimal on", "100.10 - 0.1"]), "100");
+        assert_eq!(run(&[":decimal on", "1.005 * 1000"]), "1005");
+        assert_eq!(run(&[":decimal on", "1/3"]), "0.333333333333333333");
+        assert_eq!(run(&[":decimal on", "1/0"]), "Division by zero: `1 / 0` (`:ieee on` allows it)");
+    }
+
+    #[test]
+    fn float_results_warn() {
+        for input in ["sqrt(4) + 0.1 + 0.2", "max(0.1, 0.2) + 0.1", "10^19*100 + 0.1 + 0.2"] {
+            let mut env = Environment::new();
+            env.command("decimal on").unwrap();
+            env.run(input).unwrap();
+            assert!(env.take_warnings().contains(&Warning::DecimalFallback), "{}", input);
+        }
+        let mut env = Environment::new();
+        env.command("decimal on").unwrap();
+        env.run("0.1 + 0.2").unwrap();
+        assert!(env.take_warnings().is_empty());
+    }
+
+}
+
+
+// A signed binary fixed-point format with `int_bits` and `frac_bits` besides the sign bit.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QFormat {
+    int_bits: u32,
+    frac_bits: u32,
+}
+impl QFormat {
+    fn parse(name: &str) -> Option<Self> {
+        let (int_bits, frac_bits) = name.strip_prefix(&['Q', 'q'][..])?.split_once('.')?;
+        let format = QFormat { int_bits: int_bits.parse().ok()?, frac_bits: frac_bits.parse().ok()? };
+        match format.int_bits + format.frac_bits {
+            0..=63 => Some(format),
+            _ => None,
+        }
+    }
+
+    // Clamps a raw value to the range of the format, noting whether it had to.
+    fn saturate(self, raw: i128, overflowed: bool) -> QNum {
+        let max = (1i128 << (self.int_bits + self.frac_bits)) - 1;
+        let clamped = raw.clamp(-max - 1, max);
+        QNum { raw: clamped as i64, format: self, overflowed: overflowed || clamped != raw }
+    }
+
+    fn quantize(self, float: f64) -> QNum {
+        let scaled = (float * (self.frac_bits as f64).exp2()).round();
+        match scaled.is_nan() {
+            true => self.saturate(0, true),
+            // Saturating casts keep anything too big past the range of the format.
+            false => self.saturate(scaled as i128, false),
+        }
+    }
+
+}
+impl fmt::Display for QFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Q{}.{}", self.int_bits, self.frac_bits)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QNum {
+    raw: i64,
+    format: QFormat,
+    overflowed: bool,
+}
+impl QNum {
+    fn to_f64(self) -> f64 {
+        self.raw as f64 / (self.format.frac_bits as f64).exp2()
+    }
+
+}
+impl Numeric for QNum {
+    type Context = QFormat;
+
+    fn from_f64(float: f64, format: QFormat) -> Option<Self> {
+        Some(format.quantize(float))
+    }
+
+    fn from_value(value: Value, format: QFormat) -> Option<Self> {
+        match value {
+            Value::Q(q) if q.format == format => Some(q),
+            value => Some(format.quantize(value.as_num().ok()?)),
+        }
+    }
+
+    // Products keep the bits that fit and quotients truncate, as integer code would.
+    fn call(oper: &Operator, x: QNum, y: QNum, format: QFormat) -> Option<QNum> {
+        let overflowed = x.overflowed || y.overflowed;
+        let (a, b) = (x.raw as i128, y.raw as i128);
+        match oper {
+            Operator::Add => Some(format.saturate(a + b, overflowed)),
+            Operator::Sub => Some(format.saturate(a - b, overflowed)),
+            Operator::Mul => Some(format.saturate((a * b) >> format.frac_bits, overflowed)),
+            Operator::Div if b != 0 => Some(format.saturate((a << format.frac_bits) / b, overflowed)),
+            Operator::Mod if b != 0 => Some(format.saturate(a.rem_euclid(b), overflowed)),
+            _ => None,
+        }
+    }
+
+    fn call_unary(oper: &UnaryOperator, x: QNum, format: QFormat) -> Option<QNum> {
+        match oper {
+            UnaryOperator::Neg => Some(format.saturate(-(x.raw as i128), x.overflowed)),
+            _ => None,
+        }
+    }
+
+    fn call_func(func: &Function, args: Vec<QNum>, format: QFormat) -> Option<QNum> {
+        match (func, &args[..]) {
+            (Function::Abs, [x]) => Some(format.saturate((x.raw as i128).abs(), x.overflowed)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod qnum_tests {
+    use super::*;
+
+    #[test]
+    fn shows_raw_representation() {
+        assert_eq!(crate::tests::run(&[":fixed q8.8", "1.5"]), "1.5 (Q8.8 raw 0x00180 = 384)");
+        assert_eq!(crate::tests::run(&[":fixed q4.4", "0.1"]), "0.125 (Q4.4 raw 0x002 = 2)");
+        assert_eq!(crate::tests::run(&[":fixed q4.4", "-1"]), "-1 (Q4.4 raw 0x1f0 = -16)");
+        assert_eq!(crate::tests::run(&[":fixed q1.15", "0.5"]), "0.5 (Q1.15 raw 0x04000 = 16384)");
+        assert_eq!(crate::tests::run(&[":fixed q8.8", "1 / 3"]), "0.33203125 (Q8.8 raw 0x00055 = 85)");
+        assert_eq!(crate::tests::run(&[":fixed q8.8", ":fixed off", "1.5"]), "1.5");
+    }
+
+    #[test]
+    fn overflow_saturates_with_a_warning() {
+        let mut env = Environment::new();
+        env.command("fixed q8.8").unwrap();
+        assert_eq!(env.run("200 * 2").unwrap().unwrap(), "255.99609375 (Q8.8 raw 0x0ffff = 65535)");
+        assert!(matches!(env.take_warnings()[..], [Warning::Saturated(_)]));
+    }
+
+    #[test]
+    fn float_results_are_quantized_with_a_warning() {
+        let mut env = Environment::new();
+        env.command("fixed q8.8").unwrap();
+        assert_eq!(env.run("sqrt(0.25)").unwrap().unwrap(), "0.5 (Q8.8 raw 0x00080 = 128)");
+        assert!(matches!(env.take_warnings()[..], [Warning::QFallback(_)]));
+        assert_eq!(env.run("sqrt(2)").unwrap().unwrap(), "1.4140625 (Q8.8 raw 0x0016a = 362)");
+        assert_eq!(env.run("sqrt(100000)").unwrap().unwrap(), "255.99609375 (Q8.8 raw 0x0ffff = 65535)");
+        assert!(matches!(env.take_warnings()[..], [Warning::QFallback(_), Warning::Saturated(_)]));
+    }
+
+}
+
+
+pub struct Environment {
+    vars: HashMap<String, Value>,
+    funcs: HashMap<String, Lambda>,
+    consts: HashMap<String, f64>,
+    rates: HashMap<String, f64>,
+    settings: Settings,
+    // For the input being evaluated, apart from its result.
+    warnings: RefCell<Vec<Warning>>,
+}
+impl Environment {
+    pub fn new() -> Self {
+        Environment {
+            vars: HashMap::new(),
+            funcs: HashMap::new(),
+            consts: CONSTANTS.iter().map(|(name, value)| (name.to_string(), *value)).collect(),
+            rates: HashMap::new(),
+            settings: Settings::new(),
+            warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn warn(&self, warning: Warning) {
+        let mut warnings = self.warnings.borrow_mut();
+        if warnings.len() < MAX_WARNINGS && !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    }
+
+    pub fn take_warnings(&self) -> Vec<Warning> {
+        self.warnings.take()
+    }
+
+    fn get_const(&self, name: &str) -> Option<f64> {
+        self.consts.get(name).copied()
+    }
+
+    // Reads `name = value` lines, skipping blank lines, comments and `[table]` headers. Values
+    // are evaluated, so later constants may refer to earlier ones.
+    pub fn load_constants(&mut self, source: &str) -> Result<(), MathError> {
+        for (line_no, line) in source.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+                continue;
+            }
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim().trim_matches('"'), value),
+                None => return Err(BadConstant(line_no, Box::new(BadEntry(format!("expected `name = value`, got `{}`", line))))),
+            };
+            let mut chars = name.chars().peekable();
+            if !matches!(chars.peek(), Some('a'..='z' | 'A'..='Z' | '_')) || Tokens::parse_ident(&mut chars) != name {
+                return Err(BadConstant(line_no, Box::new(BadEntry(format!("invalid constant name `{}`", name)))));
+            }
+            if CONSTANTS.iter().any(|(builtin, _)| *builtin == name) {
+                return Err(BadConstant(line_no, Box::new(ReadOnly(name.to_string()))));
+            }
+            match Tokens::eval(value, self).and_then(|value| value.map(|value| value.as_num()).transpose()) {
+                Ok(Some(value)) => {
+                    self.consts.insert(name.to_string(), value);
+                },
+                Ok(None) => return Err(BadConstant(line_no, Box::new(BadEntry(format!("`{}` has no value", name))))),
+                Err(error) => return Err(BadConstant(line_no, Box::new(error))),
+            }
+        }
+        Ok(())
+    }
+
+    // Reads `CODE = rate` or `CODE,rate` lines giving the value of one unit of each currency
+    // in a common base currency; a CSV header line is skipped.
+    pub fn load_rates(&mut self, source: &str) -> Result<(), MathError> {
+        for (line_no, line) in source.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+                continue;
+            }
+            let (name, rate) = match line.split_once('=').or_else(|| line.split_once(',')) {
+                Some((name, rate)) => (name.trim().trim_matches('"'), rate.trim()),
+                None => return Err(BadRate(line_no, Box::new(BadEntry(format!("expected `CODE = rate`, got `{}`", line))))),
+            };
+            let rate = match rate.parse::<f64>() {
+                Ok(rate) if rate > 0.0 && rate.is_finite() => rate,
+                Err(_) if line_no == 1 && line.contains(',') => continue,
+                Err(error) => return Err(BadRate(line_no, Box::new(ParseNum(rate.to_string(), Some(error))))),
+                _ => return Err(BadRate(line_no, Box::new(BadEntry(format!("invalid rate `{}`", rate))))),
+            };
+            if !name.chars().all(|chr| chr.is_ascii_alphabetic()) || name.is_empty() || UnitDef::builtin(name).is_some() {
+                return Err(BadRate(line_no, Box::new(BadEntry(format!("invalid currency code `{}`", name)))));
+            }
+            self.rates.insert(name.to_string(), rate);
+        }
+        Ok(())
+    }
+
+    // Runs a line of input, giving its result as shown with the current settings, if it has one.
+    pub fn run(&mut self, input: &str) -> Result<Option<String>, MathError> {
+        let value = on_eval_stack(|| Tokens::eval(input, self))?;
+        Ok(value.map(|value| value.shown(&self.settings).to_string()))
+    }
+
+    // Evaluates a single expression in another number type, reading the environment's variables
+    // and functions through it.
+    pub fn eval_in<N: Numeric + Clone>(&self, input: &str, context: N::Context) -> Result<N, MathError> {
+        let tree = Tokens::parse(input, self)?.lower_chains().shunting()?;
+        tree.solve_in(self, context).ok_or(Unsupported(tree))
+    }
+
+    pub fn command(&mut self, command: &str) -> Result<(), MathError> {
+        match command.trim().split_once(char::is_whitespace) {
+            Some(("rates", path)) => match fs::read_to_string(path.trim()) {
+                Ok(source) => self.load_rates(&source),
+                Err(error) => Err(BadFile(path.trim().to_string(), error)),
+            },
+            _ => {
+                self.settings.apply(command)?;
+                self.refold();
+                Ok(())
+            },
+        }
+    }
+
+    fn refold(&mut self) {
+        let bodies: Vec<(String, Expr)> = self.funcs.iter().map(|(name, lambda)| (name.clone(), lambda.source.clone().fold_constants(self))).collect();
+        for (name, body) in bodies {
+            self.funcs.get_mut(&name).unwrap().body = body;
+        }
+    }
+
+    fn get_unit(&self, name: &str) -> Option<UnitDef> {
+        UnitDef::builtin(name).or_else(|| {
+            self.rates.get(name).map(|rate| UnitDef { symbol: name.to_string(), scale: *rate, dims: CURRENCY })
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<Value> {
+        self.vars.get(name).cloned()
+    }
+
+    fn set(&mut self, name: &str, value: Value) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    fn get_func(&self, name: &str) -> Option<&Lambda> {
+        self.funcs.get(name)
+    }
+
+    fn define(&mut self, name: &str, lambda: Lambda) {
+        self.funcs.insert(name.to_string(), lambda);
+    }
+
+}
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+struct Lambda {
+    params: Vec<String>,
+    // The body before folding constants, to fold again under new settings.
+    source: Expr,
+    body: Expr,
+}
+
+
+struct Settings {
+    thousands: bool,
+    ieee: bool,
+    tolerance: f64,
+    angle: Angle,
+    // Significant digits for decimal evaluation, if on.
+    precision: Option<usize>,
+    exact: bool,
+    interval: bool,
+    // Fixed-point decimal evaluation, for money.
+    decimal: bool,
+    // Binary fixed-point evaluation, if on.
+    qformat: Option<QFormat>,
+    // Single-precision evaluation.
+    f32: bool,
+    // Integer-strict typing of literals.
+    integer: bool,
+    // Reduces integer arithmetic modulo this, if on.
+    modulus: Option<u64>,
+    // Compensated summation.
+    kahan: bool,
+    notation: Notation,
+    // Shows results as fractions where some small one reads back as the same float.
+    fractions: bool,
+    // Decimal comma, with points grouping thousands.
+    comma: bool,
+    // Exact derivatives by dual numbers, where the expression allows.
+    dual: bool,
+    // Groups the thousands of results, if on.
+    grouping: bool,
+    // What groups them, if not the opposite of the decimal mark.
+    separator: Option<char>,
+    // Shows integer results in this base, if on.
+    outbase: Option<u32>,
+}
+impl Settings {
+    fn new() -> Self {
+        Settings { thousands: false, ieee: false, tolerance: 1e-9, angle: Angle::Radians, precision: None, exact: false, interval: false, decimal: false, qformat: None, f32: false, integer: false, modulus: None, kahan: false, notation: Notation::Shortest, fractions: false, comma: false, dual: false, grouping: false, separator: None, outbase: None }
+    }
+
+    fn apply(&mut self, command: &str) -> Result<(), MathError> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words[..] {
+            ["thousands", flag] => self.thousands = Settings::parse_flag(flag, command)?,
+            ["ieee", flag] => self.ieee = Settings::parse_flag(flag, command)?,
+            ["exact", flag] => self.exact = Settings::parse_flag(flag, command)?,
+            ["interval", flag] => self.interval = Settings::parse_flag(flag, command)?,
+            ["decimal", flag] => self.decimal = Settings::parse_flag(flag, command)?,
+            ["f32", flag] => self.f32 = Settings::parse_flag(flag, command)?,
+            ["integer", flag] => self.integer = Settings::parse_flag(flag, command)?,
+            ["kahan", flag] => self.kahan = Settings::parse_flag(flag, command)?,
+            ["eng", flag] => self.notation = match Settings::parse_flag(flag, command)? {
+                true => Notation::Engineering,
+                false => Settings::new().notation,
+            },
+            ["fractions", flag] => self.fractions = Settings::parse_flag(flag, command)?,
+            ["comma", flag] => self.comma = Settings::parse_flag(flag, command)?,
+            ["dual", flag] => self.dual = Settings::parse_flag(flag, command)?,
+            ["group", "on"] => (self.grouping, self.separator) = (true, None),
+            ["group", "off"] => self.grouping = false,
+            ["outbase", "off" | "dec"] => self.outbase = None,
+            ["outbase", base] => match Settings::parse_base(base) {
+                Some(radix) => self.outbase = Some(radix),
+                None => return Err(BadCommand(command.to_string())),
+            },
+            ["group", separator @ ("_" | "," | "." | "'")] => (self.grouping, self.separator) = (true, separator.chars().next()),
+            ["tolerance", value] => self.tolerance = Settings::parse_positive(value, command)?,
+            ["angle", "rad" | "radians"] => self.angle = Angle::Radians,
+            ["angle", "deg" | "degrees"] => self.angle = Angle::Degrees,
+            ["fixed", "off"] => self.qformat = None,
+            ["fixed", format] => match QFormat::parse(format) {
+                Some(format) => self.qformat = Some(format),
+                None => return Err(BadCommand(command.to_string())),
+            },
+            ["mod", "off"] => self.modulus = None,
+            ["mod", modulus] => match modulus.parse::<u64>() {
+                Ok(modulus) if modulus >= 2 => self.modulus = Some(modulus),
+                _ => return Err(BadCommand(command.to_string())),
+            },
+            ["places", "off"] => self.notation = Settings::new().notation,
+            ["places", places] => match places.parse::<usize>() {
+                Ok(places) if places <= MAX_PLACES => self.notation = Notation::Places(places),
+                _ => return Err(BadCommand(command.to_string())),
+            },
+            ["sigfigs", "off"] => self.notation = Settings::new().notation,
+            ["sigfigs", digits] => match digits.parse::<usize>() {
+                Ok(digits) if (1..=MAX_PLACES).contains(&digits) => self.notation = Notation::Significant(digits),
+                _ => return Err(BadCommand(command.to_string())),
+            },
+            ["precision", "off"] => self.precision = None,
+            ["precision", digits] => match digits.parse::<usize>() {
+                Ok(digits) if (1..=MAX_PRECISION).contains(&digits) => self.precision = Some(digits),
+                _ => return Err(BadCommand(command.to_string())),
+            },
+            _ => return Err(BadCommand(command.to_string())),
+        }
+        Ok(())
+    }
+
+    // Whether arithmetic is on plain floats, as folding constants assumes.
+    fn plain_arithmetic(&self) -> bool {
+        !(self.exact || self.interval || self.decimal || self.f32 || self.integer || self.kahan || self.dual)
+            && self.modulus.is_none() && self.precision.is_none() && self.qformat.is_none()
+    }
+
+    fn format_num(&self, float: f64) -> String {
+        let fraction = match self.fractions && float.is_finite() {
+            true => best_ratio(float, 0.0).filter(|&(_, denom)| 1 < denom && denom <= MAX_SHOWN_DENOMINATOR),
+            false => None,
+        };
+        match fraction {
+            Some((numer, denom)) => format!("{}/{}", numer, denom),
+            None => self.localize(self.notation.format(float)),
+        }
+    }
+
+    fn localize(&self, digits: String) -> String {
+        let start = digits.find(|chr: char| chr.is_ascii_digit()).unwrap_or(0);
+        let end = digits[start..].find(|chr: char| !chr.is_ascii_digit()).map_or(digits.len(), |len| start + len);
+        let (sign, int, rest) = (&digits[..start], &digits[start..end], &digits[end..]);
+        let rest = match self.comma {
+            true => rest.replace('.', ","),
+            false => rest.to_string(),
+        };
+        let separator = self.separator.unwrap_or(if self.comma { '.' } else { ',' });
+        match self.grouping {
+            true if int.len() > 3 => {
+                let mut grouped = String::from(sign);
+                for (i, digit) in int.chars().enumerate() {
+                    if i > 0 && (int.len() - i) % 3 == 0 {
+                        grouped.push(separator);
+                    }
+                    grouped.push(digit);
+                }
+                grouped + &rest
+            },
+            _ => format!("{}{}{}", sign, int, rest),
+        }
+    }
+
+    fn parse_flag(flag: &str, command: &str) -> Result<bool, MathError> {
+        match flag {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(BadCommand(command.to_string())),
+        }
+    }
+
+    fn parse_base(base: &str) -> Option<u32> {
+        match base {
+            "hex" => Some(16),
+            "dec" => Some(10),
+            "oct" => Some(8),
+            "bin" => Some(2),
+            _ => None,
+        }
+    }
+
+    fn parse_positive(value: &str, command: &str) -> Result<f64, MathError> {
+        match value.parse::<f64>() {
+            Ok(float) if float > 0.0 && float.is_finite() => Ok(float),
+            _ => Err(BadCommand(command.to_string())),
+        }
+    }
+
+}
+
+
+// How numbers are written out.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Notation {
+    // The fewest digits that read back as the same float.
+    Shortest,
+    // Digits after the point.
+    Places(usize),
+    Significant(usize),
+    // Shortest digits, with the exponent a multiple of three.
+    Engineering,
+}
+impl Notation {
+    fn format(self, float: f64) -> String {
+        match self {
+            _ if !float.is_finite() => float.to_string(),
+            Notation::Shortest => match float != 0.0 && (float.abs() < 1e-5 || float.abs() >= 1e16) {
+                true => format!("{:e}", float),
+                false => float.to_string(),
+            },
+            Notation::Places(places) => format!("{:.*}", places, float),
+            // Like `%g`, but keeping trailing zeros, which are significant too, and
+            // going scientific for anything that would read as zero to three places.
+            Notation::Significant(digits) => {
+                let scientific = format!("{:.*e}", digits - 1, float);
+                let exp: i32 = scientific[scientific.find('e').unwrap() + 1..].parse().unwrap();
+                match exp < -3 || exp >= digits as i32 {
+                    true => scientific,
+                    false => format!("{:.*}", (digits as i32 - 1 - exp) as usize, float),
+                }
+            },
+            Notation::Engineering => {
+                let scientific = format!("{:e}", float.abs());
+                let (mantissa, exp) = scientific.split_at(scientific.find('e').unwrap());
+                let exp: i32 = exp[1..].parse().unwrap();
+                let mut digits: String = mantissa.chars().filter(|&c| c != '.').collect();
+                let point = 1 + exp.rem_euclid(3) as usize;
+                while digits.len() < point {
+                    digits.push('0');
+                }
+                let sign = if float < 0.0 { "-" } else { "" };
+                let mantissa = match digits.split_at(point) {
+                    (whole, "") => format!("{}{}", sign, whole),
+                    (whole, fraction) => format!("{}{}.{}", sign, whole, fraction),
+                };
+                match exp - exp.rem_euclid(3) {
+                    0 => mantissa,
+                    exp => format!("{}e{}", mantissa, exp),
+                }
+            },
+        }
+    }
+
+}
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Angle {
+    Radians,
+    Degrees,
+}
+impl Angle {
+    fn radians_from(self, x: f64) -> f64 {
+        match self {
+            Angle::Radians => x,
+            Angle::Degrees => x.to_radians(),
+        }
+    }
+
+    fn radians_to(self, x: f64) -> f64 {
+        match self {
+            Angle::Radians => x,
+            Angle::Degrees => x.to_degrees(),
+        }
+    }
+
+}
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Token {
+    Num(f64),
+    // A literal written as an integer, in integer-strict mode.
+    Int(i64),
+    // An integer literal too large for a float to hold exactly.
+    BigInt(BigInt),
+    Var(String),
+    Oper(Operator),
+    Unary(UnaryOperator),
+    Postfix(PostfixOperator),
+    Func(Function, usize),
+    ParenOpen(Paren),
+    ParenClose(Paren),
+    Comma,
+    TernaryIf,
+    TernaryElse,
+    Assign(Option<Operator>),
+    Semicolon,
+    Let(String),
+    In,
+    Call(String, usize),
+    Unit(UnitDef, i32),
+    Duration(f64),
+    Date(i64),
+    Arrow,
+    // `=` between the sides of an equation, in functions that take one.
+    Equation,
+}
+impl Token {
+    fn from_char(chr: char) -> Self {
+        match chr {
+            '+' | '-' | '−' | '*' | '×' | '/' | '÷' | '%' | '^' | '±' => Oper(Operator::from_char(chr)),
+            '(' => ParenOpen(Paren::Round),
+            ')' => ParenClose(Paren::Round),
+            '[' => ParenOpen(Paren::Square),
+            ']' => ParenClose(Paren::Square),
+            '{' => ParenOpen(Paren::Curly),
+            '}' => ParenClose(Paren::Curly),
+            ',' => Comma,
+            '?' => TernaryIf,
+            ':' => TernaryElse,
+            ';' => Semicolon,
+            _ => unreachable!()
+        }
+    }
+
+    fn is_lower(&self, token: &Token) -> bool {
+        if let Oper(oper) = self {
+            let other = match token {
+                Oper(other) => other.precedence(),
+                Unary(other) => other.precedence(),
+                _ => return false,
+            };
+            return match oper.is_right_assoc() {
+                true => oper.precedence() < other,
+                false => oper.precedence() <= other,
+            };
+        }
+        unreachable!()
+    }
+
+    fn is_chain_boundary(&self) -> bool {
+        match self {
+            Oper(oper) => oper.precedence() < Operator::Lt.precedence(),
+            Comma | TernaryIf | TernaryElse | Assign(_) | Let(_) | In => true,
+            _ => false,
+        }
+    }
+
+    fn precedes_operand(&self) -> bool {
+        matches!(self, Oper(_) | Unary(_) | ParenOpen(_) | Comma | TernaryIf | TernaryElse | Assign(_) | Let(_) | In | Arrow)
+    }
+
+    pub fn is_num(&self) -> bool {
+        matches!(self, Num(_))
+    }
+
+    pub fn is_oper(&self) -> bool {
+        matches!(self, Oper(_))
+    }
+
+    pub fn is_paren(&self) -> bool {
+        matches!(self, ParenOpen(_) | ParenClose(_))
+    }
+
+}
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Num(float) => write!(f, "Num({})", float),
+            Token::Int(int) => write!(f, "Int({})", int),
+            Token::BigInt(int) => write!(f, "BigInt({})", int),
+            Token::Var(name) => write!(f, "Var({})", name),
+            Token::Oper(oper) => write!(f, "Oper({})", oper),
+            Token::Unary(oper) => write!(f, "Unary({})", oper),
+            Token::Postfix(oper) => write!(f, "Postfix({})", oper),
+            Token::Func(func, argc) => write!(f, "Func({}/{})", func, argc),
+            Token::ParenOpen(paren) => write!(f, "ParenOpen({})", paren.open_char()),
+            Token::ParenClose(paren) => write!(f, "ParenClose({})", paren.close_char()),
+            Token::Comma => write!(f, "Comma"),
+            Token::TernaryIf => write!(f, "TernaryIf"),
+            Token::TernaryElse => write!(f, "TernaryElse"),
+            Token::Assign(None) => write!(f, "Assign"),
+            Token::Assign(Some(oper)) => write!(f, "Assign({}=)", oper),
+            Token::Semicolon => write!(f, "Semicolon"),
+            Token::Let(name) => write!(f, "Let({})", name),
+            Token::In => write!(f, "In"),
+            Token::Call(name, argc) => write!(f, "Call({}/{})", name, argc),
+            Token::Unit(unit, power) => write!(f, "Unit({}^{})", unit.symbol, power),
+            Token::Duration(seconds) => write!(f, "Duration({}s)", seconds),
+            Token::Date(days) => write!(f, "Date({})", Value::Date(*days)),
+            Token::Arrow => write!(f, "Arrow"),
+            Token::Equation => write!(f, "Equation"),
+        }
+    }
+}
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Paren {
+    Round, Square, Curly, Bar
+}
+impl Paren {
+    fn open_char(&self) -> char {
+        match self {
+            Paren::Round => '(',
+            Paren::Square => '[',
+            Paren::Curly => '{',
+            Paren::Bar => '|',
+        }
+    }
+
+    fn close_char(&self) -> char {
+        match self {
+            Paren::Round => ')',
+            Paren::Square => ']',
+            Paren::Curly => '}',
+            Paren::Bar => '|',
+        }
+    }
+
+}
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Operator {
+    Add, Sub, Mul, Div, FloorDiv, Mod, Pow,
+    Lt, Le, Gt, Ge, Eq, Ne,
+    And, Or,
+    BitAnd, BitOr, BitXor, Shl, Shr,
+    PlusMinus,
+}
+impl Operator {
+    fn from_char(chr: char) -> Self {
+        match chr {
+            '+' => Operator::Add,
+            '-' | '−' => Operator::Sub,
+            '*' | '×' => Operator::Mul,
+            '/' | '÷' => Operator::Div,
+            '%' => Operator::Mod,
+            '^' => Operator::Pow,
+            '±' => Operator::PlusMinus,
+            _ => unreachable!("Invalid char: `{}`", chr)
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::BitOr => 3,
+            Operator::BitXor => 4,
+            Operator::BitAnd => 5,
+            Operator::Eq | Operator::Ne => 6,
+            Operator::Lt | Operator::Le | Operator::Gt | Operator::Ge => 7,
+            Operator::Shl | Operator::Shr => 8,
+            Operator::Add | Operator::Sub => 9,
+            Operator::Mul | Operator::Div | Operator::FloorDiv | Operator::Mod => 10,
+            Operator::Pow => 12,
+            // Binds tighter than anything, as `5±0.1` is as good as a literal.
+            Operator::PlusMinus => 13,
+        }
+    }
+
+    fn is_relational(&self) -> bool {
+        matches!(self, Operator::Lt | Operator::Le | Operator::Gt | Operator::Ge)
+    }
+
+    fn is_right_assoc(&self) -> bool {
+        matches!(self, Operator::Pow)
+    }
+
+    // Applies the operator in the current mode of arithmetic, warning when floats lose precision.
+    fn evaluate(&self, x: Value, y: Value, env: &Environment) -> Result<Value, MathError> {
+        if env.settings.integer && self.takes_integers() {
+            if let Some(operand) = [&x, &y].iter().find(|operand| !matches!(operand, Value::Int(_) | Value::BigInt(_))) {
+                return Err(ExpectedInteger(*self, (*operand).clone()));
+            }
+        }
+        let modular = match (env.settings.modulus, x.as_bigint(), y.as_bigint()) {
+            (Some(modulus), Some(a), Some(b)) => self.call_modular(&a, &b, modulus)?,
+            _ => None,
+        };
+        let operands = (x.as_num().ok(), y.as_num().ok());
+        let floats = matches!((&x, &y), (Value::Num(_), Value::Num(_)));
+        let value = match modular {
+            Some(value) => value,
+            None => self.apply(x, y, &env.settings)?,
+        };
+        if let ((Some(x), Some(y)), Value::Num(result), true) = (operands, &value, floats) {
+            if let Some(warning) = self.precision_loss(x, y, *result) {
+                env.warn(warning);
+            }
+        }
+        value.finite(&env.settings, || match operands {
+            (Some(x), Some(y)) => format!("{:?} {} {:?}", x, self, y),
+            _ => self.to_string(),
+        })
+    }
+
+    // `*` between matrices and vectors is the matrix product, and a matrix to an integer power
+    // is repeated multiplication; everything else is elementwise.
+    fn apply(&self, x: Value, y: Value, settings: &Settings) -> Result<Value, MathError> {
+        match (self, x, y) {
+            (Operator::PlusMinus, x, y) => Ok(Value::Uncertain(x.as_num()?, y.as_num()?.abs())),
+            (Operator::Mul, Value::Matrix(a), Value::Matrix(b)) => Ok(Value::Matrix(mat_mul(&a, &b)?)),
+            (Operator::Mul, Value::Matrix(a), Value::List(v)) => {
+                let column = v.into_iter().map(|x| vec![x]).collect::<Vec<_>>();
+                Ok(Value::List(mat_mul(&a, &column)?.concat()))
+            },
+            (Operator::Mul, Value::List(v), Value::Matrix(b)) => Ok(Value::List(mat_mul(&[v], &b)?.concat())),
+            (Operator::Pow, Value::Matrix(a), Value::Num(n)) => Ok(Value::Matrix(mat_pow(a, as_integer(n)?)?)),
+            (Operator::Add, Value::Date(date), days) | (Operator::Add, days, Value::Date(date)) if !matches!(days, Value::Date(_)) => {
+                date.checked_add(whole_days(days)?).filter(|days| days.abs() <= MAX_DAYS).map(Value::Date).ok_or(Overflow(Function::Date))
+            },
+            (Operator::Sub, Value::Date(x), Value::Date(y)) => Ok(Value::Num((x - y) as f64)),
+            (Operator::Sub, Value::Date(date), days) => {
+                date.checked_sub(whole_days(days)?).filter(|days| days.abs() <= MAX_DAYS).map(Value::Date).ok_or(Overflow(Function::Date))
+            },
+            (_, Value::Date(x), Value::Date(y)) if self.is_relational() || matches!(self, Operator::Eq | Operator::Ne) => {
+                Ok(Value::Num(self.call(x as f64, y as f64)?))
+            },
+            (_, date @ Value::Date(_), _) | (_, _, date @ Value::Date(_)) => Err(ExpectedNumber(date)),
+            (_, x @ Value::Interval(..), y) | (_, x, y @ Value::Interval(..)) => {
+                let interval = |value: Value| if matches!(value, Value::List(_) | Value::Matrix(_)) { Err(ExpectedNumber(value)) } else { value.as_interval() };
+                self.call_interval(interval(x)?, interval(y)?)
+            },
+            (_, x @ Value::Uncertain(..), y) | (_, x, y @ Value::Uncertain(..)) => self.call_uncertain(x.as_uncertain()?, y.as_uncertain()?),
+            (Operator::Add | Operator::Sub | Operator::Mod, Value::Duration(x), Value::Duration(y)) => Ok(Value::Duration(self.call_with(x, y, settings)?)),
+            (Operator::Mul, Value::Duration(x), Value::Num(y)) | (Operator::Mul, Value::Num(y), Value::Duration(x))
+                | (Operator::Div, Value::Duration(x), Value::Num(y)) => Ok(Value::Duration(self.call_with(x, y, settings)?)),
+            (_, x @ (Value::Quantity(_) | Value::Duration(_)), y) | (_, x, y @ (Value::Quantity(_) | Value::Duration(_))) => {
+                self.apply_units(x, y, settings)
+            },
+            (_, Value::Int(a), Value::Int(b)) => self.call_int(a, b, settings),
+            (_, x @ Value::Ratio(..), y) | (_, x, y @ Value::Ratio(..)) if x.as_ratio().is_some() && y.as_ratio().is_some() => {
+                match self.call_ratio(x.as_ratio().unwrap(), y.as_ratio().unwrap()) {
+                    Some(value) => Ok(value),
+                    None => self.apply_numbers(x, y, settings),
+                }
+            },
+            (_, x, y) => self.apply_numbers(x, y, settings),
+        }
+    }
+
+    fn apply_numbers(&self, x: Value, y: Value, settings: &Settings) -> Result<Value, MathError> {
+        match (x.as_bigint(), y.as_bigint()) {
+            (Some(a), Some(b)) => match self.call_bigint(&a, &b) {
+                Some(value) => Ok(value),
+                None => x.zip(y, |x, y| self.call_with(x, y, settings)),
+            },
+            _ => x.zip(y, |x, y| self.call_with(x, y, settings)),
+        }
+    }
+
+    // Bounds of the result over all values within the operands' bounds. Comparisons that
+    // could go either way give the interval `[0, 1]`.
+    fn call_interval(&self, (a, b): (f64, f64), (c, d): (f64, f64)) -> Result<Value, MathError> {
+        let hull = |values: &[f64]| {
+            let lo = values.iter().copied().fold(f64::INFINITY, f64::min);
+            Value::Interval(lo, values.iter().copied().fold(f64::NEG_INFINITY, f64::max))
+        };
+        let certain = |always: bool, never: bool| match (always, never) {
+            (true, _) => Value::Num(1.0),
+            (_, true) => Value::Num(0.0),
+            _ => Value::Interval(0.0, 1.0),
+        };
+        Ok(match self {
+            Operator::Add => Value::Interval(a + c, b + d),
+            Operator::Sub => Value::Interval(a - d, b - c),
+            Operator::Mul => hull(&[a * c, a * d, b * c, b * d]),
+            Operator::Div if c <= 0.0 && d >= 0.0 => return Err(IntervalDivision(c, d)),
+            Operator::Div => hull(&[a / c, a / d, b / c, b / d]),
+            // Even powers of intervals around zero bottom out at zero rather than at an end.
+            Operator::Pow if c == d && c.fract() == 0.0 => match (c as i64 % 2 == 0, a < 0.0 && b > 0.0) {
+                _ if c < 0.0 && a <= 0.0 && b >= 0.0 => return Err(IntervalDivision(a, b)),
+                (true, true) => Value::Interval(0.0, a.powf(c).max(b.powf(c))),
+                _ => hull(&[a.powf(c), b.powf(c)]),
+            },
+            Operator::Pow if a < 0.0 => return Err(DomainError(Function::NthRoot, a)),
+            Operator::Pow => hull(&[a.powf(c), a.powf(d), b.powf(c), b.powf(d)]),
+            Operator::Lt => certain(b < c, a >= d),
+            Operator::Le => certain(b <= c, a > d),
+            Operator::Gt => certain(a > d, b <= c),
+            Operator::Ge => certain(a >= d, b < c),
+            Operator::Eq => certain(a == b && c == d && a == c, b < c || a > d),
+            Operator::Ne => certain(b < c || a > d, a == b && c == d && a == c),
+            _ => return Err(ExpectedNumber(Value::Interval(a, b))),
+        })
+    }
+
+    // Comparisons are of the central values.
+    fn call_uncertain(&self, x: (f64, f64), y: (f64, f64)) -> Result<Value, MathError> {
+        let value = self.call(x.0, y.0)?;
+        match self.is_relational() || matches!(self, Operator::Eq | Operator::Ne) {
+            true => Ok(Value::Num(value)),
+            false => propagate(value, &[x, y], |args| self.expand(&args[0], &args[1])),
+        }
+    }
+
+    // Exact fraction arithmetic, giving `None` where the result overflows or is irrational.
+    fn call_ratio(&self, (a, b): (i64, i64), (c, d): (i64, i64)) -> Option<Value> {
+        let (a, b, c, d) = (a as i128, b as i128, c as i128, d as i128);
+        let ratio = |numer: i128, denom: i128| reduced(numer, denom).map(|(numer, denom)| Value::Ratio(numer, denom));
+        let truth = |holds: bool| Some(Value::Ratio(holds as i64, 1));
+        // Floor of the quotient, with both denominators positive.
+        let floor = || match b * c {
+            0 => None,
+            divisor => Some((a * d).div_euclid(divisor) - ((a * d).rem_euclid(divisor) != 0 && divisor < 0) as i128),
+        };
+        match self {
+            Operator::Add => ratio(a * d + c * b, b * d),
+            Operator::Sub => ratio(a * d - c * b, b * d),
+            Operator::Mul => ratio(a * c, b * d),
+            Operator::Div => ratio(a * d, b * c),
+            Operator::Pow if d == 1 => {
+                let exp = u32::try_from(c.unsigned_abs()).ok()?;
+                let (numer, denom) = (a.checked_pow(exp)?, b.checked_pow(exp)?);
+                match c < 0 {
+                    true => ratio(denom, numer),
+                    false => ratio(numer, denom),
+                }
+            },
+            Operator::FloorDiv => ratio(floor()?, 1),
+            Operator::Mod => {
+                let quotient = floor()?;
+                ratio(a * d - quotient * c * b, b * d)
+            },
+            Operator::Lt => truth(a * d < c * b),
+            Operator::Le => truth(a * d <= c * b),
+            Operator::Gt => truth(a * d > c * b),
+            Operator::Ge => truth(a * d >= c * b),
+            Operator::Eq => truth(a * d == c * b),
+            Operator::Ne => truth(a * d != c * b),
+            _ => None,
+        }
+    }
+
+    // Exact integer arithmetic, for results that would lose precision as floats. Anything
+    // that can't stay an integer is left to floating point.
+    // Integers stay integers, continuing as big integers past `i64`, except that a quotient has
+    // to be asked for as a floored one or of floats. Negative powers give floats.
+    fn call_int(&self, a: i64, b: i64, settings: &Settings) -> Result<Value, MathError> {
+        let truth = |holds: bool| Some(holds as i64);
+        let result = match self {
+            Operator::Add => a.checked_add(b),
+            Operator::Sub => a.checked_sub(b),
+            Operator::Mul => a.checked_mul(b),
+            Operator::Div => return Err(IntegerDivision(a, b)),
+            Operator::FloorDiv if b == 0 => return Err(FloorDivByZero(a as f64)),
+            Operator::Mod if b == 0 => return Ok(Value::Num(self.call_with(a as f64, 0.0, settings)?)),
+            Operator::FloorDiv => a.checked_div(b).map(|quotient| quotient - (a % b != 0 && (a < 0) != (b < 0)) as i64),
+            Operator::Mod => a.checked_rem(b).map(|rem| if rem != 0 && (rem < 0) != (b < 0) { rem + b } else { rem }),
+            Operator::Pow if b < 0 => return Ok(Value::Num(self.call(a as f64, b as f64)?)),
+            Operator::Pow => u32::try_from(b).ok().and_then(|exp| a.checked_pow(exp)),
+            Operator::Lt => truth(a < b),
+            Operator::Le => truth(a <= b),
+            Operator::Gt => truth(a > b),
+            Operator::Ge => truth(a >= b),
+            Operator::Eq => truth(a == b),
+            Operator::Ne => truth(a != b),
+            Operator::And => truth(a != 0 && b != 0),
+            Operator::Or => truth(a != 0 || b != 0),
+            Operator::BitAnd => Some(a & b),
+            Operator::BitOr => Some(a | b),
+            Operator::BitXor => Some(a ^ b),
+            Operator::Shl | Operator::Shr if !(0..64).contains(&b) => return Err(BadShift(b)),
+            Operator::Shl => Some(a << b),
+            Operator::Shr => Some(a >> b),
+            Operator::PlusMinus => unreachable!(),
+        };
+        match result {
+            Some(int) => Ok(Value::Int(int)),
+            None => self.apply_numbers(Value::BigInt(BigInt::from_i128(a as i128)), Value::BigInt(BigInt::from_i128(b as i128)), settings),
+        }
+    }
+
+    // Arithmetic on residues, where a quotient multiplies by an inverse. `None` leaves the rest
+    // to ordinary arithmetic.
+    fn call_modular(&self, a: &BigInt, b: &BigInt, modulus: u64) -> Result<Option<Value>, MathError> {
+        let residue = |int: &BigInt| int.divmod(&BigInt::from_i128(modulus as i128)).1.magnitude.to_u128().unwrap() as u64;
+        let inverse = |x: u64| mod_inverse(x, modulus).ok_or(NotInvertible(x, modulus));
+        let (x, y, p) = (residue(a), residue(b), modulus as u128);
+        let result = match self {
+            Operator::Add => (x as u128 + y as u128) % p,
+            Operator::Sub => (x as u128 + p - y as u128) % p,
+            Operator::Mul => x as u128 * y as u128 % p,
+            Operator::Div => x as u128 * inverse(y)? as u128 % p,
+            Operator::Pow => match b.magnitude.to_u128().and_then(|exp| u64::try_from(exp).ok()) {
+                Some(exp) if b.negative => mod_pow(inverse(x)? as u128, exp as u128, p),
+                Some(exp) => mod_pow(x as u128, exp as u128, p),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(Value::from_bigint(BigInt::from_i128(result as i128))))
+    }
+
+    // Whether a floating-point result has lost much of the precision of its operands: a term
+    // too small to change a sum, a difference that leaves mostly rounding error, or a product
+    // too small for a float. Differences of integers are exact, however close.
+    fn precision_loss(&self, x: f64, y: f64, result: f64) -> Option<Warning> {
+        let integers = x.fract() == 0.0 && y.fract() == 0.0 && x.abs().max(y.abs()) <= EXACT_INTEGER_LIMIT;
+        match self {
+            _ if !(x.is_finite() && y.is_finite() && result.is_finite()) => None,
+            Operator::Add | Operator::Sub if x == 0.0 || y == 0.0 => None,
+            Operator::Add | Operator::Sub if result == x || result == if *self == Operator::Add { y } else { -y } => {
+                Some(Warning::Absorbed(x, *self, y))
+            },
+            Operator::Add | Operator::Sub if result != 0.0 && !integers => {
+                let digits = (x.abs().max(y.abs()) / result.abs()).log10().floor();
+                Some(Warning::Cancelled(x, *self, y, digits as u32)).filter(|_| digits >= CANCELLED_DIGITS)
+            },
+            Operator::Mul | Operator::Div if result == 0.0 && x != 0.0 && y != 0.0 => Some(Warning::Underflow(x, *self, y)),
+            _ => None,
+        }
+    }
+
+    // Integer-strict mode only lets these have integer operands.
+    fn takes_integers(&self) -> bool {
+        matches!(self, Operator::Mod | Operator::BitAnd | Operator::BitOr | Operator::BitXor | Operator::Shl | Operator::Shr)
+    }
+
+    fn call_bigint(&self, a: &BigInt, b: &BigInt) -> Option<Value> {
+        let truth = |holds: bool| Some(Value::Num(holds as u8 as f64));
+        match self {
+            Operator::Add => Some(Value::from_bigint(a.add(b))),
+            Operator::Sub => Some(Value::from_bigint(a.sub(b))),
+            Operator::Mul => Some(Value::from_bigint(a.mul(b))),
+            Operator::Pow if b.negative => None,
+            Operator::Pow => {
+                let exp = b.magnitude.to_u128().filter(|&exp| exp <= MAX_BIGINT_BITS as u128)? as u64;
+                if a.magnitude.bits().saturating_sub(1) * exp > MAX_BIGINT_BITS {
+                    return None;
+                }
+                Some(Value::from_bigint(BigInt::new(a.negative && exp % 2 == 1, a.magnitude.pow(exp))))
+            },
+            Operator::FloorDiv | Operator::Mod if b.is_zero() => None,
+            Operator::FloorDiv => Some(Value::from_bigint(a.divmod(b).0)),
+            Operator::Mod => Some(Value::from_bigint(a.divmod(b).1)),
+            Operator::Lt => truth(a < b),
+            Operator::Le => truth(a <= b),
+            Operator::Gt => truth(a > b),
+            Operator::Ge => truth(a >= b),
+            Operator::Eq => truth(a == b),
+            Operator::Ne => truth(a != b),
+            _ => None,
+        }
+    }
+
+    // Quantities can only be added to or compared with quantities of the same dimensions,
+    // which keep the unit on the left, while products and quotients combine their units.
+    fn apply_units(&self, x: Value, y: Value, settings: &Settings) -> Result<Value, MathError> {
+        let (x, y) = (Quantity::from_value(x)?, Quantity::from_value(y)?);
+        match self {
+            Operator::Mul => Ok(x.combine(y, 1).checked()?.into_value()),
+            Operator::Div if y.value == 0.0 && !settings.ieee => Err(DivisionByZero(*self, x.value.to_string())),
+            Operator::Div => Ok(x.combine(y, -1).checked()?.into_value()),
+            Operator::Pow if !y.unit.is_empty() => Err(ExpectedNumber(Value::Quantity(y))),
+            Operator::Pow => Ok(x.pow(y.value)?.into_value()),
+            _ if x.dims() != y.dims() => Err(IncompatibleUnits(x.unit_name(), y.unit_name())),
+            Operator::Add | Operator::Sub | Operator::Mod => {
+                Ok(Value::Quantity(Quantity { value: self.call_with(x.value, y.value, settings)?, unit: x.unit }))
+            },
+            Operator::FloorDiv => Ok(Value::Num(self.call_with(x.value, y.value, settings)?)),
+            _ if self.is_relational() || matches!(self, Operator::Eq | Operator::Ne) => Ok(Value::Num(self.call(x.value, y.value)?)),
+            _ => Err(ExpectedNumber(Value::Quantity(x))),
+        }
+    }
+
+    // Zero divisors are errors here, unless IEEE semantics are on.
+    fn call_with(&self, x: f64, y: f64, settings: &Settings) -> Result<f64, MathError> {
+        match self {
+            Operator::Div if y == 0.0 && settings.ieee => Ok(x / y),
+            Operator::FloorDiv if y == 0.0 && settings.ieee => Ok((x / y).floor()),
+            Operator::Mod if y == 0.0 && settings.ieee => Ok(f64::NAN),
+            _ => self.call(x, y),
+        }
+    }
+
+    fn call(&self, x: f64, y: f64) -> Result<f64, MathError> {
+        Ok(match self {
+            Operator::Add => x + y,
+            Operator::Sub => x - y,
+            Operator::Mul => x * y,
+            Operator::Div if y == 0.0 => return Err(DivisionByZero(*self, x.to_string())),
+            Operator::Div => x / y,
+            Operator::FloorDiv if y == 0.0 => return Err(FloorDivByZero(x)),
+            Operator::FloorDiv => (x / y).floor(),
+            Operator::Mod if y == 0.0 => return Err(DivisionByZero(*self, x.to_string())),
+            // Floored modulo: the result takes the sign of the divisor.
+            Operator::Mod => match x % y {
+                rem if rem != 0.0 && (rem < 0.0) != (y < 0.0) => rem + y,
+                rem => rem,
+            },
+            Operator::Pow => x.powf(y),
+            Operator::Lt => (x < y) as u8 as f64,
+            Operator::Le => (x <= y) as u8 as f64,
+            Operator::Gt => (x > y) as u8 as f64,
+            Operator::Ge => (x >= y) as u8 as f64,
+            Operator::Eq => (x == y) as u8 as f64,
+            Operator::Ne => (x != y) as u8 as f64,
+            Operator::And => (x != 0.0 && y != 0.0) as u8 as f64,
+            Operator::Or => (x != 0.0 || y != 0.0) as u8 as f64,
+            Operator::BitAnd => (as_integer(x)? & as_integer(y)?) as f64,
+            Operator::BitOr => (as_integer(x)? | as_integer(y)?) as f64,
+            Operator::BitXor => (as_integer(x)? ^ as_integer(y)?) as f64,
+            Operator::Shl | Operator::Shr => {
+                let (x, y) = (as_integer(x)?, as_integer(y)?);
+                if !(0..64).contains(&y) {
+                    return Err(BadShift(y));
+                }
+                match self {
+                    Operator::Shl => (x << y) as f64,
+                    _ => (x >> y) as f64,
+                }
+            },
+            _ => panic!("`{}.call(x, y)`: Not implemented!", self)
+        })
+    }
+
+    fn expand(&self, x: &Series, y: &Series) -> Result<Series, MathError> {
+        match self {
+            Operator::Add => Ok(x.add(y, 1.0)),
+            Operator::Sub => Ok(x.add(y, -1.0)),
+            Operator::Mul => Ok(x.mul(y)),
+            Operator::Div => x.div(y).ok_or_else(|| NoSeries(self.to_string())),
+            Operator::Pow => x.pow(y).ok_or_else(|| NoSeries(self.to_string())),
+            Operator::PlusMinus => Err(NoSeries(self.to_string())),
+            _ if x.is_constant() && y.is_constant() => Ok(Series::constant(self.call(x.0[0], y.0[0])?, x.0.len())),
+            _ => Err(NoSeries(self.to_string())),
+        }
+    }
+
+}
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::FloorDiv => "//",
+            Operator::Mod => "%",
+            Operator::Pow => "^",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::BitAnd => "&",
+            Operator::BitOr => "|",
+            Operator::BitXor => "xor",
+            Operator::Shl => "<<",
+            Operator::Shr => ">>",
+            Operator::PlusMinus => "±",
+        })
+    }
+}
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnaryOperator {
+    Neg, Not, Sqrt
+}
+impl UnaryOperator {
+    // Binds tighter than `*` but looser than `^`, so `-2^2` is `-(2^2)`.
+    fn precedence(&self) -> u8 {
+        match self {
+            UnaryOperator::Neg | UnaryOperator::Not | UnaryOperator::Sqrt => 11,
+        }
+    }
+
+    fn apply(&self, x: Value) -> Result<Value, MathError> {
+        match (self, x) {
+            (UnaryOperator::Neg, Value::Quantity(mut quantity)) => {
+                quantity.value = -quantity.value;
+                Ok(Value::Quantity(quantity))
+            },
+            (UnaryOperator::Neg, Value::Duration(seconds)) => Ok(Value::Duration(-seconds)),
+            (UnaryOperator::Neg, Value::BigInt(int)) => Ok(Value::BigInt(int.neg())),
+            (UnaryOperator::Neg, Value::Int(int)) => match int.checked_neg() {
+                Some(int) => Ok(Value::Int(int)),
+                None => Ok(Value::BigInt(BigInt::from_i128(int as i128).neg())),
+            },
+            (UnaryOperator::Neg, Value::Ratio(numer, denom)) if numer != i64::MIN => Ok(Value::Ratio(-numer, denom)),
+            (UnaryOperator::Neg, Value::Interval(lo, hi)) => Ok(Value::Interval(-hi, -lo)),
+            (UnaryOperator::Neg, Value::Uncertain(value, sigma)) => Ok(Value::Uncertain(-value, sigma)),
+            (UnaryOperator::Sqrt, x @ Value::Uncertain(..)) => Function::Sqrt.apply_uncertain(&[x], &Settings::new()),
+            (UnaryOperator::Sqrt, Value::Interval(lo, hi)) => Function::Sqrt.call_interval(lo, hi, Angle::Radians),
+            (UnaryOperator::Sqrt, Value::Quantity(quantity)) => Ok(quantity.pow(0.5)?.into_value()),
+            (_, x) => x.map(|x| self.call(x)),
+        }
+    }
+
+    fn call(&self, x: f64) -> Result<f64, MathError> {
+        Ok(match self {
+            UnaryOperator::Neg => -x,
+            UnaryOperator::Not => (x == 0.0) as u8 as f64,
+            UnaryOperator::Sqrt => Function::Sqrt.call(&[x])?,
+        })
+    }
+
+}
+impl fmt::Display for UnaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            UnaryOperator::Neg => '-',
+            UnaryOperator::Not => '!',
+            UnaryOperator::Sqrt => '√',
+        })
+    }
+}
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PostfixOperator {
+    Factorial
+}
+impl PostfixOperator {
+    // Factorials past 18! are computed exactly.
+    fn apply(&self, x: Value) -> Result<Value, MathError> {
+        match x.as_bigint().and_then(|int| int.magnitude.to_u128()) {
+            Some(n) if !x.as_bigint().unwrap().negative && (19..=MAX_BIG_FACTORIAL as u128).contains(&n) => {
+                let product = (2..=n).fold(BigUint::from_u128(1), |product, k| product.mul_small(k as u32));
+                Ok(Value::from_bigint(BigInt::new(false, product)))
+            },
+            _ => x.map(|x| self.call(x)),
+        }
+    }
+
+    fn call(&self, x: f64) -> Result<f64, MathError> {
+        match self {
+            PostfixOperator::Factorial => {
+                if x < 0.0 && x.fract() == 0.0 {
+                    return Err(BadFactorial(x));
+                }
+                if x.fract() != 0.0 {
+                    return Ok(gamma(x + 1.0));
+                }
+                // Anything past 170! overflows f64 anyway.
+                if x > 170.0 {
+                    return Ok(f64::INFINITY);
+                }
+                Ok((2..=x as u64).fold(1.0, |acc, n| acc * n as f64))
+            },
+        }
+    }
+
+}
+impl fmt::Display for PostfixOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            PostfixOperator::Factorial => '!',
+        })
+    }
+}
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Function {
+    Min, Max,
+    Sin, Cos, Tan, Atan, Atan2,
+    Sqrt, Cbrt, NthRoot,
+    Ln, Log, Log2,
+    Abs, Sign,
+    Round, Floor, Ceil, Trunc,
+    Gcd, Lcm,
+    Ncr, Npr,
+    Gamma, Erf,
+    Hypot, Clamp, Lerp,
+    Mean, Median, Stdev, Variance, Count,
+    NormPdf, NormCdf, NormInv, BinomPdf,
+    Det, Inv, Transpose,
+    Dot, Cross,
+    QuadRoots, CubicRoots,
+    IsPrime, NextPrime, FactorInt,
+    Fib, Catalan, Triangular,
+    Hex, Bin, Oct, Float,
+    Popcount, Bit, SetBit, ClearBit,
+    Deg, Rad,
+    PowMod, Ratio, Cfrac,
+    Range, Linspace,
+    CToF, FToC, CToK, KToC,
+    Date, Today, DaysBetween,
+    If, Sum, Prod,
+    Integrate, Deriv, Solve, Taylor, Diff, SolveFor, Expand, Factor,
+}
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "sin" => Some(Function::Sin),
+            "cos" => Some(Function::Cos),
+            "tan" => Some(Function::Tan),
+            "atan" => Some(Function::Atan),
+            "atan2" => Some(Function::Atan2),
+            "sqrt" => Some(Function::Sqrt),
+            "cbrt" => Some(Function::Cbrt),
+            "nthroot" => Some(Function::NthRoot),
+            "ln" => Some(Function::Ln),
+            "log" => Some(Function::Log),
+            "log2" => Some(Function::Log2),
+            "abs" => Some(Function::Abs),
+            "sign" => Some(Function::Sign),
+            "round" => Some(Function::Round),
+            "floor" => Some(Function::Floor),
+            "ceil" => Some(Function::Ceil),
+            "trunc" => Some(Function::Trunc),
+            "gcd" => Some(Function::Gcd),
+            "lcm" => Some(Function::Lcm),
+            "ncr" | "binom" => Some(Function::Ncr),
+            "npr" => Some(Function::Npr),
+            "gamma" => Some(Function::Gamma),
+            "erf" => Some(Function::Erf),
+            "normpdf" => Some(Function::NormPdf),
+            "normcdf" => Some(Function::NormCdf),
+            "norminv" => Some(Function::NormInv),
+            "binompdf" => Some(Function::BinomPdf),
+            "hypot" => Some(Function::Hypot),
+            "clamp" => Some(Function::Clamp),
+            "lerp" => Some(Function::Lerp),
+            "mean" => Some(Function::Mean),
+            "median" => Some(Function::Median),
+            "stdev" => Some(Function::Stdev),
+            "var" => Some(Function::Variance),
+            "count" => Some(Function::Count),
+            "det" => Some(Function::Det),
+            "inv" => Some(Function::Inv),
+            "transpose" => Some(Function::Transpose),
+            "dot" => Some(Function::Dot),
+            "cross" => Some(Function::Cross),
+            "quadroots" => Some(Function::QuadRoots),
+            "cubicroots" => Some(Function::CubicRoots),
+            "isprime" => Some(Function::IsPrime),
+            "nextprime" => Some(Function::NextPrime),
+            "factorint" => Some(Function::FactorInt),
+            "fib" => Some(Function::Fib),
+            "catalan" => Some(Function::Catalan),
+            "triangular" => Some(Function::Triangular),
+            "hex" => Some(Function::Hex),
+            "bin" => Some(Function::Bin),
+            "oct" => Some(Function::Oct),
+            "float" => Some(Function::Float),
+            "popcount" => Some(Function::Popcount),
+            "bit" => Some(Function::Bit),
+            "setbit" => Some(Function::SetBit),
+            "clearbit" => Some(Function::ClearBit),
+            "deg" => Some(Function::Deg),
+            "rad" => Some(Function::Rad),
+            "powmod" => Some(Function::PowMod),
+            "c_to_f" => Some(Function::CToF),
+            "f_to_c" => Some(Function::FToC),
+            "c_to_k" => Some(Function::CToK),
+            "ratio" => Some(Function::Ratio),
+            "cfrac" => Some(Function::Cfrac),
+            "range" => Some(Function::Range),
+            "linspace" => Some(Function::Linspace),
+            "k_to_c" => Some(Function::KToC),
+            "date" => Some(Function::Date),
+            "today" => Some(Function::Today),
+            "days_between" => Some(Function::DaysBetween),
+            "if" => Some(Function::If),
+            "sum" => Some(Function::Sum),
+            "prod" => Some(Function::Prod),
+            "integrate" => Some(Function::Integrate),
+            "deriv" => Some(Function::Deriv),
+            "solve" => Some(Function::Solve),
+            "taylor" => Some(Function::Taylor),
+            "diff" => Some(Function::Diff),
+            "solvefor" => Some(Function::SolveFor),
+            "expand" => Some(Function::Expand),
+            "factor" => Some(Function::Factor),
+            _ => None,
+        }
+    }
+
+    fn arity(&self) -> RangeInclusive<usize> {
+        match self {
+            Function::Min | Function::Max => 1..=usize::MAX,
+            Function::Mean | Function::Median | Function::Stdev | Function::Variance => 1..=usize::MAX,
+            Function::Count | Function::Sum => 1..=usize::MAX,
+            Function::NormPdf | Function::NormCdf | Function::NormInv => 1..=3,
+            Function::BinomPdf => 3..=3,
+            Function::Det | Function::Inv | Function::Transpose => 1..=1,
+            Function::Dot | Function::Cross => 2..=2,
+            Function::QuadRoots => 3..=3,
+            Function::CubicRoots => 4..=4,
+            Function::IsPrime | Function::NextPrime | Function::FactorInt => 1..=1,
+            Function::Fib | Function::Catalan | Function::Triangular => 1..=1,
+            Function::Hex | Function::Bin | Function::Oct | Function::Float => 1..=1,
+            Function::Popcount => 1..=1,
+            Function::Deg | Function::Rad => 1..=1,
+            Function::PowMod => 3..=3,
+            Function::Ratio => 1..=2,
+            Function::Cfrac => 2..=2,
+            Function::Range => 2..=3,
+            Function::Linspace => 3..=3,
+            Function::CToF | Function::FToC | Function::CToK | Function::KToC => 1..=1,
+            Function::Date => 3..=3,
+            Function::Today => 0..=0,
+            Function::DaysBetween => 2..=2,
+            Function::Bit | Function::SetBit | Function::ClearBit => 2..=2,
+            Function::Sin | Function::Cos | Function::Tan | Function::Atan => 1..=1,
+            Function::Sqrt | Function::Cbrt => 1..=1,
+            Function::Ln | Function::Log2 => 1..=1,
+            Function::Log => 1..=2,
+            Function::Abs | Function::Sign => 1..=1,
+            Function::Floor | Function::Ceil | Function::Trunc => 1..=1,
+            Function::Round => 1..=2,
+            Function::Gcd | Function::Lcm => 2..=2,
+            Function::Ncr | Function::Npr => 2..=2,
+            Function::Gamma | Function::Erf => 1..=1,
+            Function::Hypot => 2..=2,
+            Function::Clamp | Function::Lerp => 3..=3,
+            Function::If => 3..=3,
+            Function::Prod | Function::Integrate => 4..=4,
+            Function::Deriv | Function::Solve => 3..=3,
+            Function::Taylor => 4..=4,
+            Function::Diff | Function::SolveFor => 2..=2,
+            Function::Expand | Function::Factor => 1..=1,
+            Function::Atan2 | Function::NthRoot => 2..=2,
+        }
+    }
+
+    // Lazy functions receive their arguments unevaluated, as trees.
+    fn is_lazy(&self) -> bool {
+        matches!(self, Function::If | Function::Sum | Function::Prod | Function::Integrate | Function::Deriv | Function::Solve | Function::Taylor | Function::Diff | Function::SolveFor
+            | Function::Expand | Function::Factor)
+    }
+
+    // Whether the function evaluates its arguments at many points along the way, where
+    // precision warnings say more about the method than the result.
+    fn iterates(&self) -> bool {
+        matches!(self, Function::Sum | Function::Prod | Function::Integrate | Function::Deriv | Function::Solve)
+    }
+
+    fn takes_equation(&self) -> bool {
+        matches!(self, Function::SolveFor)
+    }
+
+    // Whether constant arguments always give the same result, whatever the settings.
+    fn is_constant(&self) -> bool {
+        !matches!(self, Function::Sin | Function::Cos | Function::Tan | Function::Atan | Function::Atan2 | Function::Today)
+    }
+
+    fn call_lazy(&self, args: &[Expr], env: &Environment, scopes: &[(String, Value)], depth: usize) -> Result<Value, MathError> {
+        let solve = |arg: &Expr| arg.solve_scoped(env, scopes.to_vec(), depth);
+        let number = |arg: &Expr| solve(arg)?.as_num();
+        let solve_at = |arg: &Expr, name: &str, value: f64| {
+            let mut scopes = scopes.to_vec();
+            scopes.push((name.to_string(), Value::Num(value)));
+            arg.solve_scoped(env, scopes, depth)?.as_num()
+        };
+        let bound = |arg: &Expr| match arg.bare() {
+            Expr::Var(name) => Ok(name.clone()),
+            _ => Err(NotAVariable(*self, arg.clone())),
+        };
+        match self {
+            Function::If => match number(&args[0])? != 0.0 {
+                true => solve(&args[1]),
+                false => solve(&args[2]),
+            },
+            // Four arguments led by a name with no value is a summation, anything else a plain
+            // total.
+            Function::Sum if args.len() == 4 && bound(&args[0]).is_ok_and(|name| {
+                !scopes.iter().any(|(bound, _)| *bound == name) && env.get(&name).is_none()
+            }) => {
+                let name = bound(&args[0])?;
+                let (from, to) = (as_integer(number(&args[1])?)?, as_integer(number(&args[2])?)?);
+                if to - from >= MAX_LIST_LEN as i64 {
+                    return Err(TooManyTerms(*self, to - from + 1));
+                }
+                let (mut total, mut error) = (0.0, 0.0);
+                for i in from..=to {
+                    let term = solve_at(&args[3], &name, i as f64)?;
+                    match env.settings.kahan {
+                        true => {
+                            let (sum, lost) = two_sum(total, term);
+                            total = sum;
+                            error += lost;
+                        },
+                        false => total += term,
+                    }
+                }
+                Ok(Value::Num(total + error))
+            },
+            Function::Prod => {
+                let name = bound(&args[0])?;
+                let (from, to) = (as_integer(number(&args[1])?)?, as_integer(number(&args[2])?)?);
+                if to - from >= MAX_LIST_LEN as i64 {
+                    return Err(TooManyTerms(*self, to - from + 1));
+                }
+                // Exact as long as every term is an integer, like the factorials it generalizes.
+                let (mut exact, mut total) = (Some(BigInt::from_i128(1)), 1.0);
+                for i in from..=to {
+                    let term = solve_at(&args[3], &name, i as f64)?;
+                    exact = exact.filter(|_| term.fract() == 0.0 && term.abs() <= EXACT_INTEGER_LIMIT)
+                        .map(|product| product.mul(&BigInt::from_i128(term as i128)));
+                    total *= term;
+                    match &exact {
+                        Some(product) if product.magnitude.bits() > MAX_BIGINT_BITS => return Err(Overflow(*self)),
+                        None if total.is_infinite() => return Err(Overflow(*self)),
+                        _ => (),
+                    }
+                }
+                Ok(exact.map_or(Value::Num(total), Value::from_bigint))
+            },
+            Function::Integrate => {
+                let name = bound(&args[1])?;
+                let (a, b) = (number(&args[2])?, number(&args[3])?);
+                integrate(&|x| solve_at(&args[0], &name, x), a, b, env.settings.tolerance).map(Value::Num)
+            },
+            Function::Deriv => {
+                let name = bound(&args[1])?;
+                let x = number(&args[2])?;
+                if env.settings.dual {
+                    let angle = env.settings.angle;
+                    let mut bindings: Vec<(&str, Dual)> = scopes.iter()
+                        .filter_map(|(name, value)| Some((name.as_str(), Dual::from_value(value.clone(), angle)?)))
+                        .collect();
+                    bindings.push((&name, Dual { value: x, slope: 1.0 }));
+                    if let Some(dual) = args[0].solve_bound::<Dual>(env, &bindings, angle).filter(|dual| dual.slope.is_finite()) {
+                        return Ok(Value::Num(dual.slope));
+                    }
+                }
+                let slope = derivative(&|x| solve_at(&args[0], &name, x), x)?;
+                match slope.is_finite() {
+                    true => Ok(Value::Num(slope)),
+                    false => Err(DomainError(*self, x)),
+                }
+            },
+            Function::Solve => {
+                let name = bound(&args[1])?;
+                let guess = number(&args[2])?;
+                find_root(&|x| solve_at(&args[0], &name, x), guess, env.settings.tolerance).map(Value::Num)
+            },
+            Function::Taylor => {
+                let name = bound(&args[1])?;
+                let (around, order) = (number(&args[2])?, as_integer(number(&args[3])?)?);
+                if !(0..=MAX_SERIES_ORDER as i64).contains(&order) {
+                    return Err(DomainError(*self, order as f64));
+                }
+                let len = order as usize + 1;
+                let mut var = Series::constant(around, len);
+                if len > 1 {
+                    var.0[1] = 1.0;
+                }
+                let series = args[0].series(env, &mut vec![(name.clone(), var)], scopes, len, depth)?;
+                Ok(Value::Polynomial(name, around, series.0))
+            },
+            Function::Diff => {
+                let name = bound(&args[1])?;
+                let expr = args[0].symbolic(env, &[&name]).ok_or_else(|| NotSymbolic(args[0].clone()))?;
+                let derivative = expr.simplify(&env.settings)?.derivative(&name, env.settings.angle)?;
+                Ok(derivative.simplify(&env.settings)?.into_value())
+            },
+            // Equations `a*x + b = 0` in the variable, where `a` is the derivative.
+            Function::SolveFor => {
+                let name = bound(&args[1])?;
+                let expr = args[0].symbolic(env, &[&name]).ok_or_else(|| NotSymbolic(args[0].clone()))?.simplify(&env.settings)?;
+                let slope = expr.derivative(&name, env.settings.angle)?.simplify(&env.settings)?;
+                if slope.contains(&name) {
+                    return Err(NotLinear(name));
+                }
+                if slope == Expr::Num(0.0) {
+                    return Err(NoUniqueSolution(name));
+                }
+                let offset = expr.substitute(&name, &Expr::Num(0.0));
+                let root = Expr::BinaryOp(Operator::Div, Box::new(Expr::UnaryOp(UnaryOperator::Neg, Box::new(offset))), Box::new(slope));
+                Ok(root.simplify(&env.settings)?.into_value())
+            },
+            Function::Expand | Function::Factor => {
+                let expr = args[0].symbolic(env, &[]).ok_or_else(|| NotSymbolic(args[0].clone()))?.simplify(&env.settings)?;
+                match self {
+                    Function::Expand => Ok(expr.expand(&env.settings)?.into_value()),
+                    _ => Ok(expr.factor(&env.settings)?.into_value()),
+                }
+            },
+            Function::Sum => {
+                let values = args.iter().map(solve).collect::<Result<Vec<_>, _>>()?;
+                self.apply(&values, &env.settings)
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // Variadic functions take the elements of list arguments as arguments of their own, and
+    // single-argument functions are applied to each element of a list or matrix.
+    fn apply(&self, args: &[Value], settings: &Settings) -> Result<Value, MathError> {
+        let arity = self.arity();
+        let args = match args {
+            [Value::Quantity(quantity)] if matches!(self, Function::Sqrt | Function::Cbrt | Function::Abs) => {
+                return match self {
+                    Function::Sqrt => Ok(quantity.clone().pow(0.5)?.into_value()),
+                    Function::Cbrt => Ok(quantity.clone().pow(1.0 / 3.0)?.into_value()),
+                    _ => Ok(Value::Quantity(Quantity { value: quantity.value.abs(), unit: quantity.unit.clone() })),
+                };
+            },
+            [value] if self.is_temperature() => return self.call_temperature(value),
+            _ if self.is_date() => return self.call_date(args),
+            [Value::Interval(lo, hi)] => return self.call_interval(*lo, *hi, settings.angle),
+            _ if args.iter().any(|arg| matches!(arg, Value::Uncertain(..))) => return self.apply_uncertain(args, settings),
+            [x] | [x, _] if *self == Function::Ratio => {
+                let (x, tolerance) = (x.as_num()?, args.get(1).map_or(Ok(RATIO_TOLERANCE), Value::as_num)?);
+                if !x.is_finite() {
+                    return Err(DomainError(*self, x));
+                }
+                if tolerance.is_nan() || tolerance <= 0.0 {
+                    return Err(DomainError(*self, tolerance));
+                }
+                return best_ratio(x, tolerance).map(|(numer, denom)| Value::Ratio(numer, denom)).ok_or(Overflow(*self));
+            },
+            [value] if *self == Function::Float => return Ok(Value::Num(value.as_num()?)),
+            [base, exp, modulus] if *self == Function::PowMod && args.iter().all(|arg| arg.as_bigint().is_some()) => {
+                let (base, exp, modulus) = (base.as_bigint().unwrap(), exp.as_bigint().unwrap(), modulus.as_bigint().unwrap());
+                if exp.negative || modulus.negative || modulus.is_zero() {
+                    return Err(DomainError(*self, if exp.negative { exp.to_f64() } else { modulus.to_f64() }));
+                }
+                return Ok(Value::from_bigint(base.pow_mod(&exp.magnitude, &modulus)));
+            },
+            [value @ (Value::BigInt(_) | Value::Int(_))] if *self == Function::IsPrime => {
+                let n = value.as_bigint().unwrap();
+                return match n.to_i128().and_then(|n| u64::try_from(n).ok()) {
+                    _ if n.negative => Ok(Value::Num(0.0)),
+                    Some(n) => Ok(Value::Num(is_prime(n as u128) as u8 as f64)),
+                    None => Err(DomainError(*self, n.to_f64())),
+                };
+            },
+            [value @ (Value::BigInt(_) | Value::Int(_))] if *self == Function::FactorInt => {
+                let n = value.as_bigint().unwrap();
+                return match n.to_i128().and_then(|n| u64::try_from(n).ok()) {
+                    Some(n) if n >= 1 => {
+                        let factors = prime_factors(n).into_iter().map(|p| exact_float(p as u128).ok_or(InexactInteger(p as f64)));
+                        Ok(Value::List(factors.collect::<Result<_, _>>()?))
+                    },
+                    _ => Err(DomainError(*self, n.to_f64())),
+                };
+            },
+            [value] if *self == Function::NextPrime && value.as_bigint().is_some() => {
+                let n = value.as_bigint().unwrap();
+                let mut n = match n.to_i128().and_then(|n| u64::try_from(n).ok()) {
+                    _ if n.negative => 2,
+                    Some(n) => n.max(1) as u128 + 1,
+                    None => return Err(DomainError(*self, n.to_f64())),
+                };
+                while !is_prime(n) {
+                    n += 1;
+                }
+                return Ok(Value::from_bigint(BigInt::from_i128(n as i128)));
+            },
+            [n, i] if matches!(self, Function::Bit | Function::SetBit | Function::ClearBit) && n.as_bigint().is_some() && i.as_bigint().is_some() => {
+                let (n, i) = (n.as_bigint().unwrap(), as_integer(i.as_num()?)?);
+                if !(0..64).contains(&i) {
+                    return Err(BadBit(i));
+                }
+                let n = n.to_i128().ok_or_else(|| DomainError(*self, n.to_f64()))?;
+                return Ok(Value::from_bigint(BigInt::from_i128(self.change_bit(n, i))));
+            },
+            [value] if self.radix().is_some() => return Ok(Value::Radix(value.as_exact_int()?, self.radix().unwrap())),
+            [Value::Matrix(rows)] if self.takes_matrix() => return self.call_matrix(rows),
+            [other] if self.takes_matrix() => return Err(ExpectedMatrix(*self, other.clone())),
+            [Value::List(a), Value::List(b)] if self.takes_vectors() => return self.call_vectors(a, b),
+            [Value::List(_), other] | [other, _] if self.takes_vectors() => return Err(ExpectedVector(*self, other.clone())),
+            [value @ (Value::List(_) | Value::Matrix(_))] if *arity.end() == 1 && !self.returns_list() => {
+                return value.clone().map(|x| self.call_angled(&[x], settings.angle));
+            },
+            _ if *arity.end() == usize::MAX => args.iter().map(Value::elements).collect::<Result<Vec<_>, _>>()?.concat(),
+            _ => args.iter().map(Value::as_num).collect::<Result<Vec<_>, _>>()?,
+        };
+        if !arity.contains(&args.len()) {
+            return Err(BadArity(self.to_string(), args.len()));
+        }
+        match self.returns_list() {
+            true => Ok(Value::List(self.call_list(&args)?)),
+            false if settings.kahan => Ok(Value::Num(self.call_compensated(&args)?)),
+            false => Ok(Value::Num(self.call_angled(&args, settings.angle)?)),
+        }
+    }
+
+    fn takes_matrix(&self) -> bool {
+        matches!(self, Function::Det | Function::Inv | Function::Transpose)
+    }
+
+    fn call_matrix(&self, rows: &[Vec<f64>]) -> Result<Value, MathError> {
+        match self {
+            Function::Transpose => Ok(Value::Matrix(transpose(rows))),
+            _ if rows.len() != rows[0].len() => Err(NotSquare(rows.len(), rows[0].len())),
+            Function::Det => Ok(Value::Num(determinant(rows.to_vec()))),
+            Function::Inv => Ok(Value::Matrix(invert(rows.to_vec())?)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn takes_vectors(&self) -> bool {
+        matches!(self, Function::Dot | Function::Cross)
+    }
+
+    fn call_vectors(&self, a: &[f64], b: &[f64]) -> Result<Value, MathError> {
+        match self {
+            Function::Dot if a.len() != b.len() => Err(ShapeMismatch(format!("[{}]", a.len()), format!("[{}]", b.len()))),
+            Function::Dot => Ok(Value::Num(a.iter().zip(b).map(|(x, y)| x * y).sum())),
+            Function::Cross => match (a, b) {
+                ([a1, a2, a3], [b1, b2, b3]) => Ok(Value::List(vec![a2 * b3 - a3 * b2, a3 * b1 - a1 * b3, a1 * b2 - a2 * b1])),
+                ([_, _, _], _) => Err(ShapeMismatch(String::from("[3]"), format!("[{}]", b.len()))),
+                _ => Err(ShapeMismatch(String::from("[3]"), format!("[{}]", a.len()))),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn is_temperature(&self) -> bool {
+        matches!(self, Function::CToF | Function::FToC | Function::CToK | Function::KToC)
+    }
+
+    // Temperature scales are offset from each other, so they are converted here rather than
+    // as units; kelvin alone is a unit, and `c_to_k` and `k_to_c` convert to and from it.
+    fn call_temperature(&self, value: &Value) -> Result<Value, MathError> {
+        const ZERO_CELSIUS: f64 = 273.15;
+        let kelvin = match (self, value) {
+            (Function::KToC, Value::Quantity(quantity)) if quantity.dims() == UnitDef::builtin("K").unwrap().dims => quantity.value,
+            (Function::KToC, Value::Quantity(quantity)) => return Err(IncompatibleUnits(quantity.unit_name(), String::from("K"))),
+            (Function::KToC, value) => value.as_num()?,
+            (Function::FToC, value) => (value.as_num()? - 32.0) * 5.0 / 9.0 + ZERO_CELSIUS,
+            (_, value) => value.as_num()? + ZERO_CELSIUS,
+        };
+        if kelvin < 0.0 {
+            return Err(DomainError(*self, value.as_num().unwrap_or(kelvin)));
+        }
+        match self {
+            Function::CToF => Ok(Value::Num((kelvin - ZERO_CELSIUS) * 9.0 / 5.0 + 32.0)),
+            Function::CToK => Ok(Value::Quantity(Quantity::new(kelvin, vec![(UnitDef::builtin("K").unwrap(), 1)]))),
+            _ => Ok(Value::Num(kelvin - ZERO_CELSIUS)),
+        }
+    }
+
+    fn is_date(&self) -> bool {
+        matches!(self, Function::Date | Function::Today | Function::DaysBetween)
+    }
+
+    fn call_date(&self, args: &[Value]) -> Result<Value, MathError> {
+        match (self, args) {
+            (Function::Date, [year, month, day]) => {
+                let (year, month, day) = (as_integer(year.as_num()?)?, as_integer(month.as_num()?)?, as_integer(day.as_num()?)?);
+                Ok(Value::Date(date_to_days(year, month, day)?))
+            },
+            (Function::Today, []) => {
+                let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+                Ok(Value::Date((elapsed / 86400) as i64))
+            },
+            (Function::DaysBetween, [Value::Date(from), Value::Date(to)]) => Ok(Value::Num((to - from) as f64)),
+            (_, [Value::Date(_), other] | [other, _]) => Err(ExpectedDate(*self, other.clone())),
+            _ => unreachable!(),
+        }
+    }
+
+    fn radix(&self) -> Option<u32> {
+        match self {
+            Function::Hex => Some(16),
+            Function::Bin => Some(2),
+            Function::Oct => Some(8),
+            _ => None,
+        }
+    }
+
+    fn returns_list(&self) -> bool {
+        matches!(self, Function::QuadRoots | Function::CubicRoots | Function::FactorInt | Function::Cfrac | Function::Range | Function::Linspace)
+    }
+
+    // Whether a float result is really an integer, for integer-strict mode: rounding always
+    // gives one, and the rest only of integers.
+    fn gives_integer(&self, args: &[Value]) -> bool {
+        match self {
+            Function::Round => args.len() == 1,
+            Function::Floor | Function::Ceil | Function::Trunc => true,
+            Function::Min | Function::Max | Function::Abs | Function::Sign | Function::Gcd | Function::Lcm
+                | Function::Ncr | Function::Npr | Function::IsPrime | Function::NextPrime | Function::Fib
+                | Function::Catalan | Function::Triangular | Function::Popcount | Function::Bit
+                | Function::SetBit | Function::ClearBit | Function::PowMod => {
+                args.iter().all(|arg| matches!(arg, Value::Int(_)))
+            },
+            _ => false,
+        }
+    }
+
+    // Bits of the two's complement, in 128 bits so that setting bit 63 doesn't flip the sign.
+    fn change_bit(&self, n: i128, i: i64) -> i128 {
+        match self {
+            Function::Bit => n >> i & 1,
+            Function::SetBit => n | 1 << i,
+            _ => n & !(1 << i),
+        }
+    }
+
+    fn call_list(&self, args: &[f64]) -> Result<Vec<f64>, MathError> {
+        match self {
+            Function::QuadRoots | Function::CubicRoots if args.iter().all(|&coef| coef == 0.0) => Err(DomainError(*self, 0.0)),
+            Function::QuadRoots | Function::CubicRoots => Ok(real_roots(args)),
+            Function::FactorInt => match as_integer(args[0])? {
+                n if n < 1 => Err(DomainError(*self, args[0])),
+                n => Ok(prime_factors(n as u64).into_iter().map(|p| p as f64).collect()),
+            },
+            Function::Cfrac if !args[0].is_finite() => Err(DomainError(*self, args[0])),
+            Function::Cfrac => match as_integer(args[1])? {
+                n if n < 1 => Err(DomainError(*self, args[1])),
+                n => Ok(continued_fraction(args[0], n as usize)),
+            },
+            _ if !args.iter().all(|x| x.is_finite()) => Err(DomainError(*self, args.iter().copied().find(|x| !x.is_finite()).unwrap())),
+            // Like Python's `range`, the end is excluded, but with some slack for rounding error.
+            Function::Range => {
+                let (from, to, step) = (args[0], args[1], args.get(2).copied().unwrap_or(1.0));
+                if step == 0.0 {
+                    return Err(DomainError(*self, step));
+                }
+                let len = ((to - from) / step - 1e-9).ceil().max(0.0);
+                if len > MAX_LIST_LEN as f64 {
+                    return Err(DomainError(*self, step));
+                }
+                Ok((0..len as usize).map(|i| from + i as f64 * step).collect())
+            },
+            Function::Linspace => match as_integer(args[2])? {
+                n if n < 1 || n > MAX_LIST_LEN as i64 => Err(DomainError(*self, args[2])),
+                1 => Ok(vec![args[0]]),
+                n => Ok((0..n).map(|i| match i == n - 1 {
+                    true => args[1],
+                    false => args[0] + (args[1] - args[0]) * i as f64 / (n - 1) as f64,
+                }).collect()),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn apply_uncertain(&self, args: &[Value], settings: &Settings) -> Result<Value, MathError> {
+        let args = args.iter().map(Value::as_uncertain).collect::<Result<Vec<_>, _>>()?;
+        let central: Vec<Value> = args.iter().map(|&(x, _)| Value::Num(x)).collect();
+        let value = self.apply(&central, settings)?.as_num()?;
+        propagate(value, &args, |series| self.expand(series, settings, 2))
+    }
+
+    // Monotonic functions map the bounds, and the rest check for turning points in between.
+    fn call_interval(&self, lo: f64, hi: f64, angle: Angle) -> Result<Value, MathError> {
+        // The first of `point + k * period` for integers `k` that lies within `[lo, hi]`.
+        let turning = |lo: f64, hi: f64, point: f64, period: f64| Some(point + ((lo - point) / period).ceil() * period).filter(|&x| x <= hi);
+        match self {
+            Function::Sqrt | Function::Cbrt | Function::Ln | Function::Log | Function::Log2 | Function::Atan
+                | Function::Floor | Function::Ceil | Function::Trunc | Function::Round | Function::Erf
+                | Function::Sign | Function::Deg | Function::Rad => {
+                Ok(Value::Interval(self.call_angled(&[lo], angle)?, self.call_angled(&[hi], angle)?))
+            },
+            Function::Abs if lo < 0.0 && hi > 0.0 => Ok(Value::Interval(0.0, hi.max(-lo))),
+            Function::Abs => Ok(Value::Interval(lo.abs().min(hi.abs()), lo.abs().max(hi.abs()))),
+            Function::Sin | Function::Cos => {
+                let (lo, hi) = (angle.radians_from(lo), angle.radians_from(hi));
+                // Cosine is a sine shifted a quarter turn.
+                let shift = if *self == Function::Cos { consts::FRAC_PI_2 } else { 0.0 };
+                let (lo, hi) = (lo + shift, hi + shift);
+                let (min, max) = (lo.sin().min(hi.sin()), lo.sin().max(hi.sin()));
+                Ok(Value::Interval(
+                    if turning(lo, hi, -consts::FRAC_PI_2, consts::TAU).is_some() { -1.0 } else { min },
+                    if turning(lo, hi, consts::FRAC_PI_2, consts::TAU).is_some() { 1.0 } else { max },
+                ))
+            },
+            Function::Tan => match turning(lo, hi, angle.radians_to(consts::FRAC_PI_2), angle.radians_to(consts::PI)) {
+                Some(pole) => Err(DomainError(*self, pole)),
+                None => Ok(Value::Interval(self.call_angled(&[lo], angle)?, self.call_angled(&[hi], angle)?)),
+            },
+            _ => Err(ExpectedNumber(Value::Interval(lo, hi))),
+        }
+    }
+
+    // Trig built-ins work in radians, so the angle mode converts on the way in or out.
+    // Totals without the rounding errors of summing term by term.
+    fn call_compensated(&self, args: &[f64]) -> Result<f64, MathError> {
+        match self {
+            Function::Sum => Ok(compensated_sum(args.iter().copied())),
+            Function::Mean => Ok(compensated_sum(args.iter().copied()) / args.len() as f64),
+            Function::Variance if args.len() >= 2 => {
+                let mean = Function::Mean.call_compensated(args)?;
+                Ok(compensated_sum(args.iter().map(|x| (x - mean).powi(2))) / (args.len() - 1) as f64)
+            },
+            Function::Stdev if args.len() >= 2 => Ok(Function::Variance.call_compensated(args)?.sqrt()),
+            _ => self.call(args),
+        }
+    }
+
+    fn call_angled(&self, args: &[f64], angle: Angle) -> Result<f64, MathError> {
+        match self {
+            Function::Sin | Function::Cos | Function::Tan => self.call(&[angle.radians_from(args[0])]),
+            Function::Atan | Function::Atan2 => Ok(angle.radians_to(self.call(args)?)),
+            _ => self.call(args),
+        }
+    }
+
+    // Power series of the built-ins that have one; the rest only take constant arguments.
+    fn expand(&self, args: &[Series], settings: &Settings, len: usize) -> Result<Series, MathError> {
+        let none = || NoSeries(self.to_string());
+        match (self, args) {
+            (Function::Sin | Function::Cos | Function::Tan, [x]) => {
+                let (sin, cos) = x.scale(settings.angle.radians_from(1.0)).sin_cos();
+                match self {
+                    Function::Sin => Ok(sin),
+                    Function::Cos => Ok(cos),
+                    _ => sin.div(&cos).ok_or_else(none),
+                }
+            },
+            (Function::Atan, [x]) => Ok(x.atan().ok_or_else(none)?.scale(settings.angle.radians_to(1.0))),
+            (Function::Sqrt, [x]) => x.powf(0.5).ok_or_else(none),
+            (Function::Cbrt, [x]) if x.0[0] < 0.0 => Ok(x.scale(-1.0).powf(1.0 / 3.0).ok_or_else(none)?.scale(-1.0)),
+            (Function::Cbrt, [x]) => x.powf(1.0 / 3.0).ok_or_else(none),
+            (Function::NthRoot, [x, n]) if n.is_constant() => x.powf(1.0 / n.0[0]).ok_or_else(none),
+            (Function::Ln, [x]) => x.ln().ok_or_else(none),
+            (Function::Log, [x]) => Ok(x.ln().ok_or_else(none)?.scale(1.0 / consts::LN_10)),
+            (Function::Log, [x, base]) if base.is_constant() => Ok(x.ln().ok_or_else(none)?.scale(1.0 / base.0[0].ln())),
+            (Function::Log2, [x]) => Ok(x.ln().ok_or_else(none)?.scale(1.0 / consts::LN_2)),
+            (Function::Abs, [x]) if x.0[0] != 0.0 => Ok(x.scale(x.0[0].signum())),
+            (Function::Hypot, [x, y]) => x.mul(x).add(&y.mul(y), 1.0).powf(0.5).ok_or_else(none),
+            _ if args.iter().all(Series::is_constant) => {
+                let args = args.iter().map(|x| Value::Num(x.0[0])).collect::<Vec<_>>();
+                Ok(Series::constant(self.apply(&args, settings)?.as_num()?, len))
+            },
+            _ => Err(none()),
+        }
+    }
+
+    fn call(&self, args: &[f64]) -> Result<f64, MathError> {
+        Ok(match self {
+            Function::Min => args.iter().copied().fold(f64::INFINITY, f64::min),
+            Function::Max => args.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Function::Sin => args[0].sin(),
+            Function::Cos => args[0].cos(),
+            Function::Tan => args[0].tan(),
+            Function::Atan => args[0].atan(),
+            Function::Atan2 => args[0].atan2(args[1]),
+            Function::Sqrt if args[0] < 0.0 => return Err(DomainError(*self, args[0])),
+            Function::Sqrt => args[0].sqrt(),
+            Function::Cbrt => args[0].cbrt(),
+            Function::NthRoot => {
+                let (x, n) = (args[0], args[1]);
+                match x < 0.0 {
+                    // Only odd integer roots of negatives stay real.
+                    true if n.fract() == 0.0 && n % 2.0 != 0.0 => -(-x).powf(1.0 / n),
+                    true => return Err(DomainError(*self, x)),
+                    false if n == 0.0 => return Err(DomainError(*self, n)),
+                    false => x.powf(1.0 / n),
+                }
+            },
+            Function::Ln | Function::Log | Function::Log2 if args[0] <= 0.0 => return Err(DomainError(*self, args[0])),
+            Function::Ln => args[0].ln(),
+            Function::Log2 => args[0].log2(),
+            Function::Log => match args.get(1) {
+                None => args[0].log10(),
+                Some(&base) if base <= 0.0 || base == 1.0 => return Err(DomainError(*self, base)),
+                Some(&base) => args[0].log(base),
+            },
+            Function::Abs => args[0].abs(),
+            Function::Sign => match args[0] {
+                x if x > 0.0 => 1.0,
+                x if x < 0.0 => -1.0,
+                x => x,
+            },
+            Function::Round => match args.get(1) {
+                None => args[0].round(),
+                Some(&digits) => {
+                    let scale = 10f64.powi(as_integer(digits)? as i32);
+                    (args[0] * scale).round() / scale
+                },
+            },
+            Function::Floor => args[0].floor(),
+            Function::Ceil => args[0].ceil(),
+            Function::Trunc => args[0].trunc(),
+            Function::Gcd => gcd(as_integer(args[0])?.unsigned_abs() as u128, as_integer(args[1])?.unsigned_abs() as u128) as f64,
+            Function::Lcm => {
+                let (a, b) = (as_integer(args[0])?.unsigned_abs() as u128, as_integer(args[1])?.unsigned_abs() as u128);
+                match gcd(a, b) {
+                    0 => 0.0,
+                    divisor => (a / divisor) as f64 * b as f64,
+                }
+            },
+            Function::Ncr | Function::Npr => {
+                let (n, k) = (as_integer(args[0])?, as_integer(args[1])?);
+                if n < 0 || k < 0 {
+                    return Err(DomainError(*self, n.min(k) as f64));
+                }
+                if k > n {
+                    return Ok(0.0);
+                }
+                let (n, k) = (n as u128, k as u128);
+                let mut result: u128 = 1;
+                for i in 1..=if *self == Function::Ncr { k.min(n - k) } else { k } {
+                    result = match result.checked_mul(n + 1 - i) {
+                        Some(product) if *self == Function::Ncr => product / i,
+                        Some(product) => product,
+                        None => return Err(Overflow(*self)),
+                    };
+                }
+                exact_float(result).ok_or(Overflow(*self))?
+            },
+            Function::Gamma if args[0] <= 0.0 && args[0].fract() == 0.0 => return Err(DomainError(*self, args[0])),
+            Function::Gamma => gamma(args[0]),
+            Function::Erf => erf(args[0]),
+            // Mean and standard deviation default to the standard normal distribution.
+            Function::NormPdf | Function::NormCdf | Function::NormInv => {
+                let (mean, sigma) = (args.get(1).copied().unwrap_or(0.0), args.get(2).copied().unwrap_or(1.0));
+                if sigma.is_nan() || sigma <= 0.0 {
+                    return Err(DomainError(*self, sigma));
+                }
+                let z = (args[0] - mean) / sigma;
+                match self {
+                    Function::NormPdf => (-z * z / 2.0).exp() / (sigma * (2.0 * consts::PI).sqrt()),
+                    Function::NormCdf => erfc(-z / consts::SQRT_2) / 2.0,
+                    _ if !(args[0] > 0.0 && args[0] < 1.0) => return Err(DomainError(*self, args[0])),
+                    _ => mean + sigma * norm_quantile(args[0]),
+                }
+            },
+            Function::BinomPdf => {
+                let (n, p, k) = (as_integer(args[0])?, args[1], as_integer(args[2])?);
+                if n < 0 {
+                    return Err(DomainError(*self, n as f64));
+                }
+                if !(0.0..=1.0).contains(&p) {
+                    return Err(DomainError(*self, p));
+                }
+                if k < 0 || k > n {
+                    return Ok(0.0);
+                }
+                // Summing logarithms keeps large `n` from overflowing the binomial coefficient.
+                let ln_choose: f64 = (1..=k.min(n - k)).map(|i| ((n - i + 1) as f64 / i as f64).ln()).sum();
+                let ln_power = |base: f64, exp: i64| if exp == 0 { 0.0 } else { exp as f64 * base.ln() };
+                (ln_choose + ln_power(p, k) + ln_power(1.0 - p, n - k)).exp()
+            },
+            Function::Hypot => args[0].hypot(args[1]),
+            // Written out rather than `f64::clamp`, which panics on inverted or NaN bounds.
+            Function::Clamp if args[1].is_nan() || args[2].is_nan() || args[1] > args[2] => return Err(DomainError(*self, args[1])),
+            Function::Clamp => args[0].max(args[1]).min(args[2]),
+            // Exact at both ends, unlike `a + (b - a) * t`.
+            Function::Lerp => (1.0 - args[2]) * args[0] + args[2] * args[1],
+            Function::Sum => args.iter().sum(),
+            Function::Count => args.len() as f64,
+            Function::Mean => args.iter().sum::<f64>() / args.len() as f64,
+            Function::Median => {
+                let mut sorted = args.to_vec();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                let mid = sorted.len() / 2;
+                match sorted.len() % 2 {
+                    0 => (sorted[mid - 1] + sorted[mid]) / 2.0,
+                    _ => sorted[mid],
+                }
+            },
+            Function::Stdev | Function::Variance if args.len() < 2 => return Err(DomainError(*self, args[0])),
+            Function::Stdev => Function::Variance.call(args)?.sqrt(),
+            // Sample variance, with Bessel's correction.
+            Function::Variance => {
+                let mean = Function::Mean.call(args)?;
+                args.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (args.len() - 1) as f64
+            },
+            Function::Det | Function::Inv | Function::Transpose => unreachable!(),
+            Function::Dot | Function::Cross => unreachable!(),
+            Function::IsPrime => match as_integer(args[0])? {
+                n if n < 2 => 0.0,
+                n => is_prime(n as u128) as u8 as f64,
+            },
+            Function::NextPrime => {
+                let mut n = as_integer(args[0])?.max(1) as u128 + 1;
+                while !is_prime(n) {
+                    n += 1;
+                }
+                exact_float(n).ok_or(Overflow(*self))?
+            },
+            Function::Fib | Function::Catalan | Function::Triangular => {
+                let n = match as_integer(args[0])? {
+                    n if n < 0 => return Err(DomainError(*self, args[0])),
+                    n => n as u128,
+                };
+                let result = match self {
+                    Function::Fib => (0..n).try_fold((0u128, 1u128), |(a, b), _| Some((b, a.checked_add(b)?))).map(|(a, _)| a),
+                    // C(k + 1) = C(k) * 2(2k + 1) / (k + 2), which always divides exactly.
+                    Function::Catalan => (0..n).try_fold(1u128, |c, k| Some(c.checked_mul(2 * (2 * k + 1))? / (k + 2))),
+                    _ => n.checked_mul(n + 1).map(|product| product / 2),
+                };
+                result.and_then(exact_float).ok_or(Overflow(*self))?
+            },
+            Function::Popcount => as_integer(args[0])?.count_ones() as f64,
+            Function::Deg => args[0].to_degrees(),
+            Function::Rad => args[0].to_radians(),
+            Function::PowMod => {
+                let (base, exp, modulus) = (as_integer(args[0])?, as_integer(args[1])?, as_integer(args[2])?);
+                if exp < 0 || modulus < 1 {
+                    return Err(DomainError(*self, if exp < 0 { args[1] } else { args[2] }));
+                }
+                mod_pow(base.rem_euclid(modulus) as u128, exp as u128, modulus as u128) as f64
+            },
+            Function::Bit | Function::SetBit | Function::ClearBit => {
+                let (n, i) = (as_integer(args[0])?, as_integer(args[1])?);
+                if !(0..64).contains(&i) {
+                    return Err(BadBit(i));
+                }
+                let result = self.change_bit(n as i128, i);
+                exact_float(result.unsigned_abs()).ok_or(Overflow(*self))?.copysign(result as f64)
+            },
+            Function::QuadRoots | Function::CubicRoots | Function::FactorInt => unreachable!(),
+            Function::Float => args[0],
+            Function::Hex | Function::Bin | Function::Oct => unreachable!(),
+            Function::CToF | Function::FToC | Function::CToK | Function::KToC => unreachable!(),
+            Function::Date | Function::Today | Function::DaysBetween => unreachable!(),
+            Function::Ratio | Function::Cfrac | Function::Range | Function::Linspace => unreachable!(),
+            Function::If | Function::Prod | Function::Integrate | Function::Deriv | Function::Solve | Function::Taylor | Function::Diff | Function::SolveFor => unreachable!(),
+            Function::Expand | Function::Factor => unreachable!(),
+        })
+    }
+
+}
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Sin => "sin",
+            Function::Cos => "cos",
+            Function::Tan => "tan",
+            Function::Atan => "atan",
+            Function::Atan2 => "atan2",
+            Function::Sqrt => "sqrt",
+            Function::Cbrt => "cbrt",
+            Function::NthRoot => "nthroot",
+            Function::Ln => "ln",
+            Function::Log => "log",
+            Function::Log2 => "log2",
+            Function::Abs => "abs",
+            Function::Sign => "sign",
+            Function::Round => "round",
+            Function::Floor => "floor",
+            Function::Ceil => "ceil",
+            Function::Trunc => "trunc",
+            Function::Gcd => "gcd",
+            Function::Lcm => "lcm",
+            Function::Ncr => "ncr",
+            Function::Npr => "npr",
+            Function::Gamma => "gamma",
+            Function::Erf => "erf",
+            Function::NormPdf => "normpdf",
+            Function::NormCdf => "normcdf",
+            Function::NormInv => "norminv",
+            Function::BinomPdf => "binompdf",
+            Function::Hypot => "hypot",
+            Function::Clamp => "clamp",
+            Function::Lerp => "lerp",
+            Function::Mean => "mean",
+            Function::Median => "median",
+            Function::Stdev => "stdev",
+            Function::Variance => "var",
+            Function::Count => "count",
+            Function::Det => "det",
+            Function::Inv => "inv",
+            Function::Transpose => "transpose",
+            Function::Dot => "dot",
+            Function::Cross => "cross",
+            Function::QuadRoots => "quadroots",
+            Function::CubicRoots => "cubicroots",
+            Function::IsPrime => "isprime",
+            Function::NextPrime => "nextprime",
+            Function::FactorInt => "factorint",
+            Function::Fib => "fib",
+            Function::Catalan => "catalan",
+            Function::Triangular => "triangular",
+            Function::Hex => "hex",
+            Function::Bin => "bin",
+            Function::Oct => "oct",
+            Function::Float => "float",
+            Function::Popcount => "popcount",
+            Function::Bit => "bit",
+            Function::SetBit => "setbit",
+            Function::ClearBit => "clearbit",
+            Function::Deg => "deg",
+            Function::Rad => "rad",
+            Function::PowMod => "powmod",
+            Function::CToF => "c_to_f",
+            Function::FToC => "f_to_c",
+            Function::CToK => "c_to_k",
+            Function::Ratio => "ratio",
+            Function::Cfrac => "cfrac",
+            Function::Range => "range",
+            Function::Linspace => "linspace",
+            Function::KToC => "k_to_c",
+            Function::Date => "date",
+            Function::Today => "today",
+            Function::DaysBetween => "days_between",
+            Function::If => "if",
+            Function::Sum => "sum",
+            Function::Prod => "prod",
+            Function::Integrate => "integrate",
+            Function::Deriv => "deriv",
+            Function::Solve => "solve",
+            Function::Taylor => "taylor",
+            Function::Diff => "diff",
+            Function::SolveFor => "solvefor",
+            Function::Expand => "expand",
+            Function::Factor => "factor",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum MathError {
+    Generic(String),
+    ParseNum(String, Option<ParseFloatError>),
+    ParseRadix(String, u32, ParseIntError),
+    BadRadix(String),
+    BadDigit(char, u32),
+    BadSeparator(String),
+    BadGrouping(String),
+    BadCommand(String),
+    NeedsIeee(String),
+    BadLet,
+    RecursionLimit(String),
+    ReadOnly(String),
+    BadConstant(usize, Box<MathError>),
+    BadRate(usize, Box<MathError>),
+    // A line of a constants or rates file not of the expected form.
+    BadEntry(String),
+    BadFile(String, io::Error),
+    BadChar(char),
+    BadFactorial(f64),
+    FloorDivByZero(f64),
+    DivisionByZero(Operator, String),
+    NonFinite(String, f64),
+    UnknownIdent(String),
+    UnknownFunction(String),
+    BadArity(String, usize),
+    NotAVariable(Function, Expr),
+    ExpectedNumber(Value),
+    ExpectedMatrix(Function, Value),
+    ExpectedVector(Function, Value),
+    ExpectedDate(Function, Value),
+    NoSeries(String),
+    NotSymbolic(Expr),
+    Unsupported(Expr),
+    NoDerivative(String),
+    NotLinear(String),
+    NotPolynomial(String),
+    NoUniqueSolution(String),
+    BadInterval(f64, f64),
+    IntervalDivision(f64, f64),
+    IntegerDivision(i64, i64),
+    ExpectedInteger(Operator, Value),
+    NotInvertible(u64, u64),
+    BadDate(i64, i64, i64),
+    ShapeMismatch(String, String),
+    IncompatibleUnits(String, String),
+    BadUnitPower(String, f64),
+    PowerTooLarge(f64),
+    NotSquare(usize, usize),
+    SingularMatrix,
+    DomainError(Function, f64),
+    NoConvergence(Function),
+    TooManyTerms(Function, i64),
+    Overflow(Function),
+    NotAnInteger(f64),
+    InexactInteger(f64),
+    BadShift(i64),
+    BadBit(i64),
+    MismatchedParens(Paren, Paren),
+    UnclosedParens(Tokens),
+    UnmatchedParens(Tokens),
+    NotEnoughTokens(Tokens),
+    MisplacedComma(Tokens),
+    UnmatchedTernary(Tokens),
+    MisplacedAssign(Tokens),
+    UnmatchedLet(Tokens),
+    MisplacedArrow(Tokens),
+    UnexpectedToken(Token),
+    // Where in the input the error is, when that is known.
+    At(Span, Box<MathError>),
+}
+impl MathError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            At(span, _) => Some(*span),
+            _ => None,
+        }
+    }
+
+    // The error itself, whether or not it was located.
+    pub fn inner(&self) -> &MathError {
+        match self {
+            At(_, error) => error,
+            error => error,
+        }
+    }
+
+    fn at(self, span: Span) -> MathError {
+        match self {
+            At(..) => self,
+            error => At(span, Box::new(error)),
+        }
+    }
+
+}
+impl fmt::Display for MathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Generic(string) => write!(f, "Error: `{}`", string),
+            ParseNum(string, _) => write!(f, "Cannot parse literal: `{}`", string),
+            ParseRadix(string, radix, _) => write!(f, "Cannot parse base-{} literal: `{}`", radix, string),
+            BadRadix(string) => write!(f, "Base must be between 2 and 36: `{}`", string),
+            BadDigit(chr, radix) => write!(f, "Digit `{}` is not valid in base {}", chr, radix),
+            BadSeparator(string) => write!(f, "Misplaced digit separator in literal: `{}`", string),
+            BadGrouping(string) => write!(f, "Thousands groups must have three digits: `{}`", string),
+            BadCommand(string) => write!(f, "Unknown setting or value: `:{}`", string),
+            NeedsIeee(string) => write!(f, "`{}` is only available in IEEE mode (`:ieee on`)", string),
+            BadLet => write!(f, "Expected `let name = value in body`"),
+            RecursionLimit(name) => write!(f, "Maximum call depth exceeded in `{}`", name),
+            ReadOnly(name) => write!(f, "`{}` is a constant and cannot be redefined", name),
+            BadConstant(line, _) => write!(f, "{} line {}", CONSTANTS_FILE, line),
+            BadRate(line, _) => write!(f, "Exchange rates line {}", line),
+            BadEntry(string) => write!(f, "{}", string),
+            BadFile(path, _) => write!(f, "Cannot read `{}`", path),
+            BadChar(chr) => write!(f, "Character not supported: `{}`", chr),
+            BadFactorial(float) => write!(f, "Factorial is not defined for negative integers: `{}`", float),
+            FloorDivByZero(float) => write!(f, "Floor division by zero: `{} // 0`", float),
+            DivisionByZero(oper, numer) => write!(f, "Division by zero: `{} {} 0` (`:ieee on` allows it)", numer, oper),
+            NonFinite(operation, float) => write!(f, "`{}` gives {} (`:ieee on` allows it)", operation, float),
+            UnknownIdent(ident) => write!(f, "Unknown identifier: `{}`", ident),
+            UnknownFunction(ident) => write!(f, "Unknown function: `{}`", ident),
+            BadArity(func, argc) => write!(f, "Function `{}` cannot take {} argument(s)", func, argc),
+            ExpectedNumber(value) => write!(f, "Expected a number, got: {}", value),
+            ExpectedMatrix(func, value) => write!(f, "Function `{}` expects a matrix, got: {}", func, value),
+            ExpectedVector(func, value) => write!(f, "Function `{}` expects a vector, got: {}", func, value),
+            ExpectedDate(func, value) => write!(f, "Function `{}` expects a date, got: {}", func, value),
+            NoSeries(name) => write!(f, "`{}` has no power series expansion here", name),
+            NotSymbolic(expr) => write!(f, "Cannot work with this symbolically: `{}`", expr),
+            Unsupported(expr) => write!(f, "Cannot solve this in the chosen number type: `{}`", expr),
+            NoDerivative(name) => write!(f, "`{}` has no symbolic derivative", name),
+            NotLinear(var) => write!(f, "Equation is not linear in `{}`", var),
+            NotPolynomial(expr) => write!(f, "Not a polynomial in one variable with integer coefficients: `{}`", expr),
+            NoUniqueSolution(var) => write!(f, "Equation does not determine `{}`", var),
+            BadInterval(lo, hi) => write!(f, "Interval bounds are out of order: [{}, {}]", lo, hi),
+            IntervalDivision(lo, hi) => write!(f, "Cannot divide by an interval containing zero: [{}, {}]", lo, hi),
+            IntegerDivision(a, b) => write!(f, "Cannot divide integers: use `{} // {}` for the floored quotient, or `float({}) / {}`", a, b, a, b),
+            ExpectedInteger(oper, value) => write!(f, "Operator `{}` expects integers, got: {}", oper, value),
+            NotInvertible(x, modulus) => write!(f, "`{}` has no inverse modulo {}", x, modulus),
+            BadDate(year, month, day) => write!(f, "Invalid date: {}-{:02}-{:02}", year, month, day),
+            ShapeMismatch(left, right) => write!(f, "Cannot combine values of shape {} and {}", left, right),
+            IncompatibleUnits(left, right) => write!(f, "Incompatible units: `{}` and `{}`", left, right),
+            BadUnitPower(unit, exp) => write!(f, "Cannot raise `{}` to the power `{}`", unit, exp),
+            PowerTooLarge(exp) => write!(f, "Cannot multiply out a power above {} of a sum: `{}`", MAX_SERIES_ORDER, exp),
+            NotSquare(rows, cols) => write!(f, "Expected a square matrix, got a {}x{} matrix", rows, cols),
+            SingularMatrix => write!(f, "Matrix is singular"),
+            NotAVariable(func, expr) => write!(f, "Function `{}` expects a variable name, found: `{}`", func, expr),
+            DomainError(func, float) => write!(f, "`{}` is not defined for `{}`", func, float),
+            NoConvergence(func) => write!(f, "`{}` failed to converge; try `:tolerance` with a larger value", func),
+            TooManyTerms(func, count) => write!(f, "`{}` over {} terms is more than the limit of {}", func, count, MAX_LIST_LEN),
+            Overflow(func) => write!(f, "Result of `{}` is too large to represent exactly", func),
+            NotAnInteger(float) => write!(f, "Expected an integer, got: `{}`", float),
+            InexactInteger(float) => write!(f, "Integer is too large to be exact as a float: `{}`", float),
+            BadShift(int) => write!(f, "Shift amount must be between 0 and 63: `{}`", int),
+            BadBit(int) => write!(f, "Bit index must be between 0 and 63: `{}`", int),
+            MismatchedParens(open, close) => write!(f, "Mismatched brackets: `{}` closed by `{}`", open.open_char(), close.close_char()),
+            UnclosedParens(tokens) => write!(f, "Opened parentheses were not closed: {}", tokens),
+            UnmatchedParens(tokens) => write!(f, "Unmatched closed parentheses: {}", tokens),
+            NotEnoughTokens(tokens) => write!(f, "Unmatched numbers and operators: {}", tokens),
+            MisplacedComma(tokens) => write!(f, "Comma outside of a function call: {}", tokens),
+            UnmatchedTernary(tokens) => write!(f, "Unmatched `?` and `:` in conditional: {}", tokens),
+            MisplacedAssign(tokens) => write!(f, "Assignment must be of the form `name = expression`: {}", tokens),
+            UnmatchedLet(tokens) => write!(f, "Unmatched `let` and `in`: {}", tokens),
+            MisplacedArrow(tokens) => write!(f, "Functions must be assigned as `name = (params) -> body`: {}", tokens),
+            UnexpectedToken(token) => write!(f, "Unexpected token: {}", token),
+            At(_, error) => write!(f, "{}", error),
+        }
+    }
+}
+impl Error for MathError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseNum(_, Some(error)) => Some(error),
+            ParseRadix(_, _, error) => Some(error),
+            BadFile(_, error) => Some(error),
+            BadConstant(_, error) | BadRate(_, error) => Some(error.as_ref()),
+            At(_, error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+// Problems that don't stop evaluation, but make its result suspect.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    Absorbed(f64, Operator, f64),
+    Cancelled(f64, Operator, f64, u32),
+    Underflow(f64, Operator, f64),
+    Saturated(QFormat),
+    // Something the decimal type lacks, at the given precision.
+    FloatFallback(usize),
+    // Something the fixed decimal type of `:decimal` mode lacks, or a result out of its range.
+    DecimalFallback,
+    // Something single-precision evaluation lacks.
+    SingleFallback,
+    // Something fixed-point evaluation lacks, in the given format.
+    QFallback(QFormat),
+}
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::Absorbed(x, oper, y) => write!(f, "`{:?} {} {:?}` loses the smaller operand entirely", x, oper, y),
+            Warning::Cancelled(x, oper, y, digits) => write!(f, "`{:?} {} {:?}` cancels {} of about 16 significant digits", x, oper, y, digits),
+            Warning::Underflow(x, oper, y) => write!(f, "`{:?} {} {:?}` underflows to zero", x, oper, y),
+            Warning::Saturated(format) => write!(f, "Result overflowed {}, saturated to its range", format),
+            Warning::FloatFallback(digits) => write!(f, "Computed in double precision, not to {} digits", digits),
+            Warning::DecimalFallback => write!(f, "Computed in double precision, not in exact decimal"),
+            Warning::SingleFallback => write!(f, "Computed in double precision, not in single precision"),
+            Warning::QFallback(format) => write!(f, "Computed in double precision, not in {}", format),
+        }
+    }
+}
+
+// First-order propagation of independent standard uncertainties, taking each partial
+// derivative as the linear term of the power series in that argument.
+fn propagate(value: f64, args: &[(f64, f64)], expand: impl Fn(&[Series]) -> Result<Series, MathError>) -> Result<Value, MathError> {
+    let mut variance = 0.0;
+    for (i, &(_, sigma)) in args.iter().enumerate().filter(|(_, (_, sigma))| *sigma != 0.0) {
+        let seeded: Vec<Series> = args.iter().enumerate().map(|(j, &(x, _))| Series(vec![x, if i == j { 1.0 } else { 0.0 }])).collect();
+        variance += (expand(&seeded)?.0[1] * sigma).powi(2);
+    }
+    Ok(Value::Uncertain(value, variance.sqrt()))
+}
+
+fn as_integer(x: f64) -> Result<i64, MathError> {
+    match x {
+        x if x.fract() != 0.0 => Err(NotAnInteger(x)),
+        x if x.abs() > EXACT_INTEGER_LIMIT => Err(InexactInteger(x)),
+        _ => Ok(x as i64),
+    }
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+// Positive divisors, by trial division, of integers small enough for that.
+fn divisors(n: i128) -> Vec<i128> {
+    let n = n.abs();
+    if n > 1_000_000_000_000 {
+        return Vec::new();
+    }
+    let mut divisors = Vec::new();
+    let mut i = 1;
+    while i * i <= n {
+        if n % i == 0 {
+            divisors.push(i);
+            if i * i != n {
+                divisors.push(n / i);
+            }
+        }
+        i += 1;
+    }
+    divisors.sort_unstable();
+    divisors
+}
+
+// Divides a polynomial, as ascending integer coefficients, by `q*x - p`, if that leaves no
+// remainder and nothing overflows.
+fn divide_root(coefs: &[i128], p: i128, q: i128) -> Option<Vec<i128>> {
+    let degree = coefs.len() - 1;
+    let mut quotient = vec![0; degree];
+    let mut carry = coefs[degree];
+    for i in (0..degree).rev() {
+        if carry % q != 0 {
+            return None;
+        }
+        quotient[i] = carry / q;
+        carry = coefs[i].checked_add(p.checked_mul(quotient[i])?)?;
+    }
+    match carry == 0 {
+        true => Some(quotient),
+        false => None,
+    }
+}
+
+// A fraction in lowest terms with a positive denominator, if it fits.
+fn reduced(numer: i128, denom: i128) -> Option<(i64, i64)> {
+    if denom == 0 {
+        return None;
+    }
+    let divisor = gcd(numer.unsigned_abs(), denom.unsigned_abs()) as i128;
+    let (numer, denom) = (numer / divisor * denom.signum(), denom.abs() / divisor);
+    Some((i64::try_from(numer).ok()?, i64::try_from(denom).ok()?))
+}
+
+// A term of a polynomial, with its coefficient written as an integer or small fraction if exact.
+fn polynomial_term(coef: f64, monomial: &str) -> String {
+    let exact = |x: f64| best_ratio(x, x * 1e-12).filter(|&(_, denom)| denom <= 10000);
+    let (numer, denom) = match exact(coef) {
+        Some(ratio) => ratio,
+        // Reciprocals of factorials and the like.
+        None => match exact(1.0 / coef) {
+            Some((numer, 1)) => (1, numer),
+            _ => {
+                let coef = format!("{:.12e}", coef).parse::<f64>().unwrap();
+                return match monomial.is_empty() {
+                    true => format!("{}", coef),
+                    false => format!("{}*{}", coef, monomial),
+                };
+            },
+        },
+    };
+    let numer = match (numer, monomial.is_empty()) {
+        (numer, true) => numer.to_string(),
+        (1, false) => monomial.to_string(),
+        (numer, false) => format!("{}*{}", numer, monomial),
+    };
+    match denom {
+        1 => numer,
+        denom => format!("{}/{}", numer, denom),
+    }
+}
+
+// The fraction with the smallest denominator within `tolerance` of `x`: walks the continued
+// fraction convergents, then searches for the smallest semiconvergent that is close enough.
+fn best_ratio(x: f64, tolerance: f64) -> Option<(i64, i64)> {
+    let (mut numers, mut denoms) = ((0i64, 1i64), (1i64, 0i64));
+    let mut rest = x;
+    loop {
+        let term = rest.floor();
+        if term.abs() > i64::MAX as f64 {
+            return None;
+        }
+        let term = term as i64;
+        let step = |k: i64| Some((numers.0.checked_add(k.checked_mul(numers.1)?)?, denoms.0.checked_add(k.checked_mul(denoms.1)?)?));
+        let close = |(numer, denom): (i64, i64)| (numer as f64 / denom as f64 - x).abs() <= tolerance;
+        let (numer, denom) = step(term)?;
+        if close((numer, denom)) || rest == term as f64 {
+            // Semiconvergents only approach `x` monotonically past the first term.
+            if denoms.1 == 0 {
+                return Some((numer, denom));
+            }
+            let (mut low, mut high) = (1, term);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                match close(step(mid)?) {
+                    true => high = mid,
+                    false => low = mid + 1,
+                }
+            }
+            return step(low);
+        }
+        numers = (numers.1, numer);
+        denoms = (denoms.1, denom);
+        rest = 1.0 / (rest - term as f64);
+    }
+}
+
+// Stops early once a convergent reproduces `x`, as later terms would only expand rounding error.
+fn continued_fraction(x: f64, terms: usize) -> Vec<f64> {
+    let mut expansion = Vec::new();
+    let (mut numers, mut denoms) = ((0.0, 1.0), (1.0, 0.0));
+    let mut rest = x;
+    while expansion.len() < terms {
+        let term = rest.floor();
+        expansion.push(term);
+        numers = (numers.1, numers.0 + term * numers.1);
+        denoms = (denoms.1, denoms.0 + term * denoms.1);
+        if numers.1 / denoms.1 == x || rest == term {
+            break;
+        }
+        rest = 1.0 / (rest - term);
+    }
+    expansion
+}
+
+// A number of days, or a duration or time quantity that is a whole number of days.
+fn whole_days(value: Value) -> Result<i64, MathError> {
+    let days = match value {
+        Value::Num(days) => days,
+        value => {
+            let quantity = Quantity::from_value(value)?;
+            let day = UnitDef::builtin("day").unwrap();
+            if quantity.dims() != day.dims {
+                return Err(IncompatibleUnits(quantity.unit_name(), day.symbol));
+            }
+            quantity.value / day.scale
+        },
+    };
+    as_integer(days)
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar, after Howard Hinnant's
+// `days_from_civil`; years start in March so the leap day falls at the end.
+const MAX_DAYS: i64 = 365_242_500;
+
+fn date_to_days(year: i64, month: i64, day: i64) -> Result<i64, MathError> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year.abs() > MAX_DAYS / 366 {
+        return Err(BadDate(year, month, day));
+    }
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    match days_to_date(days) == (year, month, day) {
+        true => Ok(days),
+        false => Err(BadDate(year, month, day)),
+    }
+}
+
+fn days_to_date(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+// Deterministic Miller-Rabin: these bases suffice for every integer below 3.1e23, well past 2^64.
+fn is_prime(n: u128) -> bool {
+    const BASES: [u128; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    if let Some(&base) = BASES.iter().find(|&&base| n.is_multiple_of(base)) {
+        return n == base;
+    }
+    let (mut d, mut r) = (n - 1, 0);
+    while d % 2 == 0 {
+        d /= 2;
+        r += 1;
+    }
+    BASES.iter().all(|&base| {
+        let mut x = mod_pow(base, d, n);
+        if x == 1 || x == n - 1 {
+            return true;
+        }
+        for _ in 1..r {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+fn mod_pow(mut base: u128, mut exp: u128, modulus: u128) -> u128 {
+    let mut result = 1 % modulus;
+    base %= modulus;
+    while exp > 0 {
+        if exp % 2 == 1 {
+            result = mul_mod(result, base, modulus);
+        }
+        base = mul_mod(base, base, modulus);
+        exp /= 2;
+    }
+    result
+}
+
+// Residues of a 64-bit modulus multiply within a u128; past that, by doubling and adding.
+fn mul_mod(a: u128, mut b: u128, modulus: u128) -> u128 {
+    if modulus <= u64::MAX as u128 {
+        return a % modulus * (b % modulus) % modulus;
+    }
+    let add = |x: u128, y: u128| match x.overflowing_add(y) {
+        (sum, false) if sum < modulus => sum,
+        (sum, _) => sum.wrapping_sub(modulus),
+    };
+    let (mut a, mut result) = (a % modulus, 0);
+    while b > 0 {
+        if b % 2 == 1 {
+            result = add(result, a);
+        }
+        a = add(a, a);
+        b /= 2;
+    }
+    result
+}
+
+// Neumaier's variant of Kahan summation: the sum and the rounding error it loses.
+fn two_sum(x: f64, y: f64) -> (f64, f64) {
+    let sum = x + y;
+    match x.abs() >= y.abs() {
+        true => (sum, (x - sum) + y),
+        false => (sum, (y - sum) + x),
+    }
+}
+
+fn compensated_sum(terms: impl Iterator<Item = f64>) -> f64 {
+    let (total, error) = terms.fold((0.0, 0.0), |(total, error), term| {
+        let (sum, lost) = two_sum(total, term);
+        (sum, error + lost)
+    });
+    total + error
+}
+
+// By the extended Euclidean algorithm, if `a` and `modulus` are coprime.
+fn mod_inverse(a: u64, modulus: u64) -> Option<u64> {
+    let (mut r, mut next_r) = (modulus as i128, a as i128);
+    let (mut t, mut next_t) = (0i128, 1i128);
+    while next_r != 0 {
+        let quotient = r / next_r;
+        (r, next_r) = (next_r, r - quotient * next_r);
+        (t, next_t) = (next_t, t - quotient * next_t);
+    }
+    match r {
+        1 => Some(t.rem_euclid(modulus as i128) as u64),
+        _ => None,
+    }
+}
+
+// Trial division by the small primes, then Pollard's rho splits what's left until every part is prime.
+fn prime_factors(mut n: u64) -> Vec<u64> {
+    let mut factors = Vec::new();
+    let mut p = 2;
+    while p < 1000 && p * p <= n {
+        while n.is_multiple_of(p) {
+            factors.push(p);
+            n /= p;
+        }
+        p += if p == 2 { 1 } else { 2 };
+    }
+    let mut rest = vec![n];
+    while let Some(n) = rest.pop() {
+        match n {
+            1 => {},
+            n if is_prime(n as u128) => factors.push(n),
+            n => {
+                let divisor = pollard_rho(n);
+                rest.extend([divisor, n / divisor]);
+            },
+        }
+    }
+    factors.sort_unstable();
+    factors
+}
+
+// A nontrivial divisor of a composite, found where the sequence x^2 + c cycles modulo it.
+fn pollard_rho(n: u64) -> u64 {
+    let n = n as u128;
+    (1..).find_map(|c| {
+        let step = |x: u128| (x * x + c) % n;
+        let (mut x, mut y, mut divisor) = (2, 2, 1);
+        while divisor == 1 {
+            x = step(x);
+            y = step(step(y));
+            divisor = gcd(x.abs_diff(y), n);
+        }
+        (divisor != n).then_some(divisor as u64)
+    }).unwrap()
+}
+
+// Integers above 2^53 can no longer be represented exactly by an f64.
+fn exact_float(int: u128) -> Option<f64> {
+    match int <= 1 << f64::MANTISSA_DIGITS {
+        true => Some(int as f64),
+        false => None,
+    }
+}
+
+// Lanczos approximation (g = 7, n = 9), with the reflection formula below 1/2.
+fn gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9, 676.520_368_121_885_1, -1_259.139_216_722_402_8,
+        771.323_428_777_653_1, -176.615_029_162_140_6, 12.507_343_278_686_905,
+        -0.138_571_095_265_720_12, 9.984_369_578_019_572e-6, 1.505_632_735_149_311_6e-7,
+    ];
+    // Factorials of whole arguments, the same as `(x - 1)!`, up to the largest a float holds.
+    if x.fract() == 0.0 && (1.0..=171.0).contains(&x) {
+        return (2..x as u64).fold(1.0, |acc, n| acc * n as f64);
+    }
+    if x < 0.5 {
+        return consts::PI / ((consts::PI * x).sin() * gamma(1.0 - x));
+    }
+    let x = x - 1.0;
+    let sum = COEFFICIENTS[1..].iter().enumerate()
+        .fold(COEFFICIENTS[0], |acc, (i, coef)| acc + coef / (x + i as f64 + 1.0));
+    let t = x + 7.5;
+    (2.0 * consts::PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * sum
+}
+
+// Maclaurin series near zero, continued fraction for erfc in the tails.
+fn erf(x: f64) -> f64 {
+    match x.abs() < 3.0 {
+        true => erf_series(x),
+        false => (1.0 - erfc(x.abs())).copysign(x),
+    }
+}
+
+// Computed directly in the tails, where `1 - erf(x)` would cancel to zero.
+fn erfc(x: f64) -> f64 {
+    if x < 3.0 {
+        return 1.0 - erf_series(x.max(-3.0));
+    }
+    let fraction = (1..60).rev().fold(x, |acc, k| x + k as f64 / 2.0 / acc);
+    (-x * x).exp() / (consts::PI.sqrt() * fraction)
+}
+
+fn erf_series(x: f64) -> f64 {
+    let (mut term, mut sum) = (x, x);
+    for n in 1..100 {
+        term *= -x * x / n as f64;
+        sum += term / (2 * n + 1) as f64;
+        if term.abs() < 1e-17 * sum.abs() {
+            break;
+        }
+    }
+    sum * 2.0 / consts::PI.sqrt()
+}
+
+// Acklam's rational approximation, polished with a Newton step against `erfc`.
+fn norm_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969683028665376e1, 2.209460984245205e2, -2.759285104469687e2,
+                         1.38357751867269e2, -3.066479806614716e1, 2.506628277459239];
+    const B: [f64; 5] = [-5.447609879822406e1, 1.615858368580409e2, -1.556989798598866e2,
+                         6.680131188771972e1, -1.328068155288572e1];
+    const C: [f64; 6] = [-7.784894002430293e-3, -3.223964580411365e-1, -2.400758277161838,
+                         -2.549732539343734, 4.374664141464968, 2.938163982698783];
+    const D: [f64; 4] = [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996, 3.754408661907416];
+    let horner = |coefficients: &[f64], x: f64| coefficients.iter().fold(0.0, |acc, c| acc * x + c);
+    let tail = |p: f64| {
+        let q = (-2.0 * p.ln()).sqrt();
+        horner(&C, q) / (horner(&D, q) * q + 1.0)
+    };
+    let x = match p {
+        p if p < 0.02425 => tail(p),
+        p if p > 1.0 - 0.02425 => -tail(1.0 - p),
+        p => {
+            let q = p - 0.5;
+            q * horner(&A, q * q) / (horner(&B, q * q) * q * q + 1.0)
+        },
+    };
+    let error = erfc(-x / consts::SQRT_2) / 2.0 - p;
+    x - error * (2.0 * consts::PI).sqrt() * (x * x / 2.0).exp()
+}
+
+// Adaptive Simpson's rule, halving the tolerance along with each interval.
+fn integrate(f: &dyn Fn(f64) -> Result<f64, MathError>, a: f64, b: f64, tolerance: f64) -> Result<f64, MathError> {
+    let points = (f(a)?, f((a + b) / 2.0)?, f(b)?);
+    let whole = (b - a) / 6.0 * (points.0 + 4.0 * points.1 + points.2);
+    simpson(f, (a, b), points, whole, tolerance, MAX_BISECTIONS)
+}
+
+fn simpson(f: &dyn Fn(f64) -> Result<f64, MathError>, (a, b): (f64, f64), (fa, fm, fb): (f64, f64, f64),
+           whole: f64, tolerance: f64, depth: usize) -> Result<f64, MathError> {
+    let m = (a + b) / 2.0;
+    let (lm, rm) = (f((a + m) / 2.0)?, f((m + b) / 2.0)?);
+    let left = (m - a) / 6.0 * (fa + 4.0 * lm + fm);
+    let right = (b - m) / 6.0 * (fm + 4.0 * rm + fb);
+    let delta = left + right - whole;
+    if !delta.is_finite() || (depth == 0 && delta.abs() > 15.0 * tolerance) {
+        return Err(NoConvergence(Function::Integrate));
+    }
+    if delta.abs() <= 15.0 * tolerance {
+        return Ok(left + right + delta / 15.0);
+    }
+    Ok(simpson(f, (a, m), (fa, lm, fm), left, tolerance / 2.0, depth - 1)?
+        + simpson(f, (m, b), (fm, rm, fb), right, tolerance / 2.0, depth - 1)?)
+}
+
+// Five-point central difference, with the step scaled to the point.
+fn derivative(f: &dyn Fn(f64) -> Result<f64, MathError>, x: f64) -> Result<f64, MathError> {
+    let h = 1e-3 * x.abs().max(1.0);
+    Ok((f(x - 2.0 * h)? - 8.0 * f(x - h)? + 8.0 * f(x + h)? - f(x + 2.0 * h)?) / (12.0 * h))
+}
+
+// Newton's method, falling back to bisection once a sign change has been bracketed. It has
+// converged once a step moves by no more than the tolerance, relative to the size of the root:
+// a small residual alone says little when the function itself is small.
+fn find_root(f: &dyn Fn(f64) -> Result<f64, MathError>, guess: f64, tolerance: f64) -> Result<f64, MathError> {
+    let (mut x, mut fx) = (guess, f(guess)?);
+    let mut bracket: Option<(f64, f64, f64)> = None;
+    for _ in 0..MAX_ITERATIONS {
+        if fx == 0.0 {
+            return Ok(x);
+        }
+        let mut next = x - fx / derivative(f, x)?;
+        if let Some((a, _, b)) = bracket {
+            if !(next > a.min(b) && next < a.max(b)) {
+                next = (a + b) / 2.0;
+            }
+        }
+        if !next.is_finite() {
+            break;
+        }
+        // Without a bracket, backtrack along the step until the residual shrinks.
+        let (mut f_next, mut halvings) = (f(next)?, 0);
+        while bracket.is_none() && f_next.abs().partial_cmp(&fx.abs()) != Some(Ordering::Less) && halvings < MAX_ITERATIONS {
+            next = (x + next) / 2.0;
+            f_next = f(next)?;
+            halvings += 1;
+        }
+        if !f_next.is_finite() {
+            break;
+        }
+        bracket = match bracket {
+            Some((_, fa, b)) if f_next.signum() == fa.signum() => Some((next, f_next, b)),
+            Some((a, fa, _)) => Some((a, fa, next)),
+            None if f_next.signum() != fx.signum() => Some((x, fx, next)),
+            None => None,
+        };
+        let step = (next - x).abs();
+        let converged = step <= tolerance * next.abs().max(1.0) || step <= 4.0 * f64::EPSILON * next.abs();
+        if converged && (bracket.is_some() || halvings == 0) {
+            return Ok(next);
+        }
+        x = next;
+        fx = f_next;
+    }
+    Err(NoConvergence(Function::Solve))
+}
+
+fn transpose(rows: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    (0..rows.first().map_or(0, Vec::len)).map(|j| rows.iter().map(|row| row[j]).collect()).collect()
+}
+
+fn mat_mul(a: &[Vec<f64>], b: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, MathError> {
+    if a[0].len() != b.len() {
+        return Err(ShapeMismatch(Value::Matrix(a.to_vec()).shape(), Value::Matrix(b.to_vec()).shape()));
+    }
+    let columns = transpose(b);
+    Ok(a.iter().map(|row| {
+        columns.iter().map(|column| row.iter().zip(column).map(|(x, y)| x * y).sum()).collect()
+    }).collect())
+}
+
+fn mat_pow(a: Vec<Vec<f64>>, n: i64) -> Result<Vec<Vec<f64>>, MathError> {
+    if a.len() != a[0].len() {
+        return Err(NotSquare(a.len(), a[0].len()));
+    }
+    let mut base = if n < 0 { invert(a)? } else { a };
+    let mut result = (0..base.len())
+        .map(|i| (0..base.len()).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect::<Vec<Vec<f64>>>();
+    let mut n = n.unsigned_abs();
+    while n > 0 {
+        if n % 2 == 1 {
+            result = mat_mul(&result, &base)?;
+        }
+        base = mat_mul(&base, &base)?;
+        n /= 2;
+    }
+    Ok(result)
+}
+
+// Gaussian elimination with partial pivoting.
+fn determinant(mut rows: Vec<Vec<f64>>) -> f64 {
+    let n = rows.len();
+    let mut det = 1.0;
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| rows[i][col].abs().total_cmp(&rows[j][col].abs())).unwrap();
+        if rows[pivot][col] == 0.0 {
+            return 0.0;
+        }
+        if pivot != col {
+            rows.swap(pivot, col);
+            det = -det;
+        }
+        det *= rows[col][col];
+        let pivot_row = rows[col].clone();
+        for row in &mut rows[col + 1..] {
+            let factor = row[col] / pivot_row[col];
+            for (x, pivot) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *x -= factor * pivot;
+            }
+        }
+    }
+    det
+}
+
+// Gauss-Jordan elimination on the matrix augmented with the identity.
+fn invert(mut rows: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, MathError> {
+    let n = rows.len();
+    let scale = rows.iter().flatten().fold(0.0, |max: f64, x| max.max(x.abs()));
+    for (i, row) in rows.iter_mut().enumerate() {
+        row.extend((0..n).map(|j| if i == j { 1.0 } else { 0.0 }));
+    }
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| rows[i][col].abs().total_cmp(&rows[j][col].abs())).unwrap();
+        if rows[pivot][col].abs() <= scale * 1e-12 {
+            return Err(SingularMatrix);
+        }
+        rows.swap(pivot, col);
+        let divisor = rows[col][col];
+        rows[col].iter_mut().for_each(|x| *x /= divisor);
+        let pivot_row = rows[col].clone();
+        for (_, row) in rows.iter_mut().enumerate().filter(|&(i, _)| i != col) {
+            let factor = row[col];
+            for (x, pivot) in row.iter_mut().zip(&pivot_row) {
+                *x -= factor * pivot;
+            }
+        }
+    }
+    Ok(rows.into_iter().map(|row| row[n..].to_vec()).collect())
+}
+
+// Real roots of a polynomial of degree at most three, highest coefficient first, in ascending
+// order with repeated roots listed once.
+fn real_roots(coefs: &[f64]) -> Vec<f64> {
+    let coefs = match coefs.iter().position(|&coef| coef != 0.0) {
+        Some(first) => &coefs[first..],
+        None => return Vec::new(),
+    };
+    let mut roots = match *coefs {
+        [a, b] => vec![-b / a],
+        [a, b, c] => {
+            let disc = b * b - 4.0 * a * c;
+            match disc {
+                _ if disc < 0.0 => Vec::new(),
+                _ if disc == 0.0 => vec![-b / (2.0 * a)],
+                // Avoids cancellation between `b` and the root of the discriminant.
+                _ => {
+                    let q = -(b + b.signum() * disc.sqrt()) / 2.0;
+                    vec![q / a, c / q]
+                },
+            }
+        },
+        // Reduced to the depressed cubic t^3 + pt + q, with x = t - b/3a.
+        [a, b, c, d] => {
+            let (b, c, d) = (b / a, c / a, d / a);
+            let p = c - b * b / 3.0;
+            let q = 2.0 * b.powi(3) / 27.0 - b * c / 3.0 + d;
+            let disc = (q / 2.0).powi(2) + (p / 3.0).powi(3);
+            let roots = match disc {
+                _ if disc > 0.0 => vec![(-q / 2.0 + disc.sqrt()).cbrt() + (-q / 2.0 - disc.sqrt()).cbrt()],
+                _ if p == 0.0 => vec![0.0],
+                _ => {
+                    let r = 2.0 * (-p / 3.0).sqrt();
+                    let phi = (3.0 * q / (p * r)).clamp(-1.0, 1.0).acos() / 3.0;
+                    (0..3).map(|k| r * (phi - 2.0 * consts::PI * k as f64 / 3.0).cos()).collect()
+                },
+            };
+            roots.into_iter().map(|t| t - b / 3.0).collect()
+        },
+        _ => Vec::new(),
+    };
+    // A few Newton steps clean up the rounding left by the closed forms.
+    for root in roots.iter_mut() {
+        for _ in 0..3 {
+            let (value, slope) = coefs.iter().fold((0.0, 0.0), |(value, slope), coef| (value * *root + coef, slope * *root + value));
+            if slope == 0.0 {
+                break;
+            }
+            *root -= value / slope;
+        }
+    }
+    roots.sort_by(|a, b| a.total_cmp(b));
+    roots.dedup_by(|a, b| (*a - *b).abs() <= 1e-9 * a.abs().max(1.0));
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The result of the last line as the REPL shows it, or its error, running `:command` lines
+    // as settings.
+    pub(crate) fn run(lines: &[&str]) -> String {
+        let mut env = Environment::new();
+        let mut shown = String::new();
+        for line in lines {
+            let result = match line.strip_prefix(':') {
+                Some(command) => env.command(command).map(|_| None),
+                None => env.run(line),
+            };
+            shown = match result {
+                Ok(value) => value.unwrap_or_default(),
+                Err(error) => error.to_string(),
+            };
+        }
+        shown
+    }
+
+    // Whether the result of the last line is within `1e-9` of `expected`, relatively.
+    fn close(lines: &[&str], expected: f64) -> bool {
+        let shown = run(lines);
+        shown.parse::<f64>().is_ok_and(|float| (float - expected).abs() <= 1e-9 * expected.abs().max(1.0))
+    }
+
+    #[test]
+    fn powers_are_right_associative() {
+        assert_eq!(run(&["2^3^2"]), "512");
+        assert_eq!(run(&["(2^3)^2"]), "64");
+        assert_eq!(run(&["-2^2"]), "-4");
+        assert_eq!(run(&["2^-1"]), "0.5");
+    }
+
+    #[test]
+    fn modulo_takes_the_sign_of_the_divisor() {
+        assert_eq!(run(&["17 % 5 + 1"]), "3");
+        assert_eq!(run(&["-7 % 3"]), "2");
+        assert_eq!(run(&["7 % -3"]), "-2");
+        assert_eq!(run(&["5.5 % 2"]), "1.5");
+        assert_eq!(run(&["2 * 3 % 4"]), "2");
+    }
+
+    #[test]
+    fn unary_minus_negates() {
+        assert_eq!(run(&["-5 + 3"]), "-2");
+        assert_eq!(run(&["2 * -3"]), "-6");
+        assert_eq!(run(&["-(2+3)"]), "-5");
+        assert_eq!(run(&["--3"]), "3");
+        assert_eq!(run(&["2^-2"]), "0.25");
+    }
+
+    #[test]
+    fn factorial_is_postfix() {
+        assert_eq!(run(&["5! + 1"]), "121");
+        assert_eq!(run(&["(-1)!"]), "Factorial is not defined for negative integers: `-1`");
+        assert_eq!(run(&["0!"]), "1");
+        assert_eq!(run(&["3!!"]), "720");
+    }
+
+    #[test]
+    fn floor_division_rounds_down() {
+        assert_eq!(run(&["7 // 2"]), "3");
+        assert_eq!(run(&["-7 // 2"]), "-4");
+        assert_eq!(run(&["7 // 0"]), "Floor division by zero: `7 // 0`");
+        assert_eq!(run(&["7.5 // 2"]), "3");
+    }
+
+    #[test]
+    fn functions_take_comma_separated_arguments() {
+        assert_eq!(run(&["max(1, 2*3, 4)"]), "6");
+        assert_eq!(run(&["max(1, max(2, 3))"]), "3");
+        assert_eq!(run(&["sqrt(1, 2)"]), "Function `sqrt` cannot take 2 argument(s)");
+    }
+
+    #[test]
+    fn comparisons_give_zero_or_one() {
+        assert_eq!(run(&["2*3 > 5"]), "1");
+        assert_eq!(run(&["1 <= 1"]), "1");
+        assert_eq!(run(&["2 >= 3"]), "0");
+        assert_eq!(run(&["2 != 2"]), "0");
+        assert_eq!(run(&["1 + 1 == 2"]), "1");
+    }
+
+    #[test]
+    fn logical_operators_treat_nonzero_as_true() {
+        assert_eq!(run(&["(5 > 3) && (5 < 10)"]), "1");
+        assert_eq!(run(&["0 || 2"]), "1");
+        assert_eq!(run(&["!0"]), "1");
+        assert_eq!(run(&["!3"]), "0");
+        assert_eq!(run(&["1 < 2 && 0"]), "0");
+        assert_eq!(run(&["!1 || 1"]), "1");
+    }
+
+    #[test]
+    fn ternary_picks_a_branch() {
+        assert_eq!(run(&["x = -4", "x > 0 ? x : -x"]), "4");
+        assert_eq!(run(&["0 ? 2 : 3"]), "3");
+        assert_eq!(run(&["1 ? 0 ? 5 : 6 : 7"]), "6");
+        assert_eq!(run(&["1 ? 2"]), "Unmatched `?` and `:` in conditional: {Num(1), TernaryIf, Num(2)}");
+    }
+
+    #[test]
+    fn bitwise_operators_follow_c_precedence() {
+        assert_eq!(run(&["6 & 3"]), "2");
+        assert_eq!(run(&["6 | 3"]), "7");
+        assert_eq!(run(&["6 xor 3"]), "5");
+        assert_eq!(run(&["1 << 4"]), "16");
+        assert_eq!(run(&["256 >> 2"]), "64");
+        assert_eq!(run(&["1.5 & 1"]), "Expected an integer, got: `1.5`");
+        assert_eq!(run(&["1 | 2 & 3"]), "3");
+        assert_eq!(run(&["1 << 2 + 1"]), "8");
+        assert_eq!(run(&["6 & 3 == 2"]), "0");
+        assert_eq!(run(&["1 << 64"]), "Shift amount must be between 0 and 63: `64`");
+    }
+
+    #[test]
+    fn assignment_stores_variables() {
+        assert_eq!(run(&["x = 2 + 3", "y = x * 2"]), "10");
+        assert_eq!(run(&["x = 2 + 3"]), "5");
+        assert_eq!(run(&["x = 2", "y = x * 2", "x + y"]), "6");
+        assert_eq!(run(&["2 = 3"]), "Assignment must be of the form `name = expression`: {Num(2), Assign, Num(3)}");
+    }
+
+    #[test]
+    fn compound_assignment_updates_variables() {
+        assert_eq!(run(&["x = 1", "x += 5"]), "6");
+        assert_eq!(run(&["x = 10", "x -= 3"]), "7");
+        assert_eq!(run(&["x = 2", "x *= 4"]), "8");
+        assert_eq!(run(&["x = 8", "x /= 4"]), "2");
+        assert_eq!(run(&["x = 1", "x += 5", "x"]), "6");
+        assert_eq!(run(&["x += 1"]), "Unknown identifier: `x`");
+    }
+
+    #[test]
+    fn comparisons_chain() {
+        assert_eq!(run(&["x = 5", "1 < x < 10"]), "1");
+        assert_eq!(run(&["x = 50", "1 < x < 10"]), "0");
+        assert_eq!(run(&["3 > 2 > 1"]), "1");
+        assert_eq!(run(&["1 < 3 < 2"]), "0");
+    }
+
+    #[test]
+    fn brackets_of_each_kind_group() {
+        assert_eq!(run(&["[1 + 2] * {3 + 4}"]), "21");
+        assert_eq!(run(&["[1+2)"]), "Mismatched brackets: `[` closed by `)`");
+        assert_eq!(run(&["{[1+2]*3}"]), "9");
+        assert_eq!(run(&["(1+2}"]), "Mismatched brackets: `(` closed by `}`");
+    }
+
+    #[test]
+    fn unicode_symbols_are_operators() {
+        assert_eq!(run(&["6 × 7 ÷ 2 − 1"]), "20");
+        assert_eq!(run(&["√16"]), "4");
+        assert_eq!(run(&["√(9+16)"]), "5");
+        assert_eq!(run(&["π"]), "3.141592653589793");
+    }
+
+    #[test]
+    fn scientific_notation_literals() {
+        assert_eq!(run(&["1.5e-3"]), "0.0015");
+        assert_eq!(run(&["1e"]), "Cannot parse literal: `1e`");
+        assert_eq!(run(&["6.02E23"]), "6.02e23");
+        assert_eq!(run(&["1e+3"]), "1000");
+        assert_eq!(run(&["1.5e"]), "Cannot parse literal: `1.5e`");
+    }
+
+    #[test]
+    fn prefixed_literals_give_their_value() {
+        assert_eq!(run(&["0xFF + 0b1010 + 0o755"]), "758");
+        assert_eq!(run(&["0xZZ"]), "Digit `Z` is not valid in base 16");
+        assert_eq!(run(&["0b102"]), "Digit `2` is not valid in base 2");
+        assert_eq!(run(&["0o8"]), "Digit `8` is not valid in base 8");
+        assert!(run(&["0x"]).starts_with("Cannot parse base-16 literal: `0x`"));
+    }
+
+    #[test]
+    fn digit_separators_are_skipped() {
+        assert_eq!(run(&["1_000_000.5"]), "1000000.5");
+        assert_eq!(run(&["1__0"]), "Misplaced digit separator in literal: `1__0`");
+        assert_eq!(run(&["0xff_ff"]), "65535");
+        assert_eq!(run(&["_1"]), "Misplaced digit separator in literal: `_1`");
+        assert_eq!(run(&["1_"]), "Misplaced digit separator in literal: `1_`");
+    }
+
+    #[test]
+    fn thousands_commas_are_optional() {
+        assert_eq!(run(&[":thousands on", "1,234,567.89"]), "1234567.89");
+        assert_eq!(run(&[":thousands on", "1,23"]), "Thousands groups must have three digits: `1,23`");
+        assert_eq!(run(&[":thousands on", "1234,567"]), "Thousands groups must have three digits: `1234,567`");
+        assert_eq!(run(&[":thousands on", "max(1, 234)"]), "234");
+        assert_eq!(run(&["1,234"]), "Comma outside of a function call: {Num(1), Comma, Num(234)}");
+    }
+
+    #[test]
+    fn si_suffixes_scale_literals() {
+        assert_eq!(run(&["4.7k"]), "4700");
+        assert_eq!(run(&["3u"]), "3e-6");
+        assert_eq!(run(&["10M"]), "10000000");
+        assert_eq!(run(&["1G + 1T"]), "1001000000000");
+        assert_eq!(run(&["2m"]), "0.002");
+        assert_eq!(run(&["5n + 7p"]), "5.007e-9");
+    }
+
+    #[test]
+    fn byte_suffixes_count_bytes() {
+        assert_eq!(run(&["2GiB / 4KiB"]), "524288");
+        assert_eq!(run(&["1KiB"]), "1024");
+        assert_eq!(run(&["1MB + 1GB"]), "1001000000");
+    }
+
+    #[test]
+    fn radix_literals_take_any_base() {
+        assert_eq!(run(&["16#ff + 36#z9 + 2#1011"]), "1535");
+        assert_eq!(run(&["2#102"]), "Digit `2` is not valid in base 2");
+        assert_eq!(run(&["8#17"]), "15");
+        assert_eq!(run(&["37#1"]), "Base must be between 2 and 36: `37`");
+        assert_eq!(run(&["1#0"]), "Base must be between 2 and 36: `1`");
+    }
+
+    #[test]
+    fn special_literals_need_ieee_mode() {
+        assert_eq!(run(&["inf"]), "`inf` is only available in IEEE mode (`:ieee on`)");
+        assert_eq!(run(&[":ieee on", "-inf"]), "-inf");
+        assert_eq!(run(&["nan"]), "`nan` is only available in IEEE mode (`:ieee on`)");
+        assert_eq!(run(&[":ieee on", "nan"]), "NaN");
+        assert_eq!(run(&[":ieee on", "inf - inf"]), "NaN");
+    }
+
+    #[test]
+    fn comments_run_to_the_end_of_the_line() {
+        assert_eq!(run(&["2*pi*3  # circumference"]), "18.84955592153876");
+        assert_eq!(Environment::new().run("# circumference").unwrap(), None);
+        assert_eq!(Environment::new().run("  ").unwrap(), None);
+        assert_eq!(run(&["1 + 2 # x", "4 # y"]), "4");
+    }
+
+    #[test]
+    fn let_binds_within_one_expression() {
+        assert_eq!(run(&["let x = 5 in x*x + 2*x"]), "35");
+        assert_eq!(run(&["let x = 5 in x", "x"]), "x");
+        assert_eq!(run(&["x = 1", "let x = 5 in x", "x"]), "1");
+        assert_eq!(run(&["let x = 2 in let y = 3 in x*y"]), "6");
+        assert_eq!(run(&["let = 2 in 1"]), "Expected `let name = value in body`");
+    }
+
+    #[test]
+    fn lambdas_are_callable_values() {
+        assert_eq!(run(&["f = (x) -> x^2 + 1", "f(3)"]), "10");
+        assert_eq!(run(&["f = (x, y) -> x*y", "f(2, 3)"]), "6");
+        assert_eq!(run(&["f = (x) -> x", "f(1, 2)"]), "Function `f` cannot take 2 argument(s)");
+        assert_eq!(run(&["-> 1"]), "Functions must be assigned as `name = (params) -> body`: {Arrow, Num(1)}");
+    }
+
+    #[test]
+    fn trigonometric_functions() {
+        assert_eq!(run(&["sin(pi/2)"]), "1");
+        assert_eq!(run(&["cos(pi)"]), "-1");
+        assert!(close(&["tan(pi/4)"], 1.0));
+        assert_eq!(run(&["asin(1)"]), "Unknown function: `asin`");
+    }
+
+    #[test]
+    fn atan2_keeps_the_quadrant() {
+        assert!(close(&["atan2(1, -1)"], 3.0 * consts::FRAC_PI_4));
+        assert!(close(&["atan2(-1, -1)"], -3.0 * consts::FRAC_PI_4));
+        assert_eq!(run(&["atan2(0, 0)"]), "0");
+    }
+
+    #[test]
+    fn roots_reject_even_roots_of_negatives() {
+        assert_eq!(run(&["cbrt(-27)"]), "-3");
+        assert_eq!(run(&["nthroot(16, 4)"]), "2");
+        assert_eq!(run(&["nthroot(-16, 4)"]), "`nthroot` is not defined for `-16`");
+        assert_eq!(run(&["sqrt(16)"]), "4");
+        assert_eq!(run(&["sqrt(-4)"]), "`sqrt` is not defined for `-4`");
+        assert_eq!(run(&["nthroot(-27, 3)"]), "-3");
+        assert_eq!(run(&["nthroot(2, 0)"]), "`nthroot` is not defined for `0`");
+    }
+
+    #[test]
+    fn logarithms_need_positive_arguments() {
+        assert_eq!(run(&["log(1000) + log2(8) + log(8, 2) + ln(e)"]), "10");
+        assert_eq!(run(&["log(0)"]), "`log` is not defined for `0`");
+        assert_eq!(run(&["ln(0)"]), "`ln` is not defined for `0`");
+        assert_eq!(run(&["log(-1)"]), "`log` is not defined for `-1`");
+        assert_eq!(run(&["log(8, 1)"]), "`log` is not defined for `1`");
+    }
+
+    #[test]
+    fn abs_and_sign() {
+        assert_eq!(run(&["abs(-3) + |-3| + sign(-2)"]), "5");
+        assert_eq!(run(&["||-3| - 5|"]), "2");
+        assert_eq!(run(&["2 * ||-3| - |-5||"]), "4");
+        assert_eq!(run(&["|-1| || 0"]), "1");
+        assert_eq!(run(&["abs(-2.5)"]), "2.5");
+        assert_eq!(run(&["[sign(0), sign(3)]"]), "[0, 1]");
+    }
+
+    #[test]
+    fn rounding_functions() {
+        assert_eq!(run(&["round(3.14159, 2)"]), "3.14");
+        assert_eq!(run(&["[round(2.5), floor(-2.5), ceil(2.1), trunc(-2.7)]"]), "[3, -3, 3, -2]");
+        assert_eq!(run(&["[round(-2.5), floor(2.7), ceil(-2.1), trunc(2.7)]"]), "[-3, 2, -2, 2]");
+        assert_eq!(run(&["round(1, 0.5)"]), "Expected an integer, got: `0.5`");
+    }
+
+    #[test]
+    fn min_and_max_take_any_number_of_arguments() {
+        assert_eq!(run(&["min(3, 1+1, 7/2)"]), "2");
+        assert_eq!(run(&["max(1)"]), "1");
+        assert_eq!(run(&["min(-1, -2, -3)"]), "-3");
+        assert_eq!(run(&["max()"]), "Function `max` cannot take 0 argument(s)");
+    }
+
+    #[test]
+    fn gcd_and_lcm_take_integers() {
+        assert_eq!(run(&["gcd(12, 18) + lcm(4, 6)"]), "18");
+        assert_eq!(run(&["gcd(1.5, 3)"]), "Expected an integer, got: `1.5`");
+        assert_eq!(run(&["gcd(-12, 18)"]), "6");
+        assert_eq!(run(&["[gcd(0, 5), lcm(0, 5)]"]), "[5, 0]");
+    }
+
+    #[test]
+    fn combinatorics_are_exact() {
+        assert_eq!(run(&["ncr(5, 2) + npr(5, 2) + binom(10, 3)"]), "150");
+        assert_eq!(run(&["ncr(1000, 500)"]), "Result of `ncr` is too large to represent exactly");
+        assert_eq!(run(&["ncr(2, 5)"]), "0");
+        assert_eq!(run(&["ncr(5, -1)"]), "`ncr` is not defined for `-1`");
+    }
+
+    #[test]
+    fn gamma_and_erf() {
+        assert_eq!(run(&["gamma(5)"]), "24");
+        assert_eq!(run(&["gamma(21) == 20!"]), "1");
+        assert!(close(&["gamma(0.5)^2"], consts::PI));
+        assert!(close(&["erf(1)"], 0.842_700_792_949_715));
+        assert!(close(&["2.5!"], 3.323_350_970_447_843));
+        assert_eq!(run(&["gamma(0)"]), "`gamma` is not defined for `0`");
+        assert_eq!(run(&["erf(0)"]), "0");
+    }
+
+    #[test]
+    fn hypot_clamp_and_lerp() {
+        assert!(close(&["hypot(1e300, 1e300)"], 1e300 * consts::SQRT_2));
+        assert_eq!(run(&["clamp(5, 1, 3) + lerp(0, 10, 0.25)"]), "5.5");
+        assert_eq!(run(&["hypot(3, 4)"]), "5");
+        assert_eq!(run(&["clamp(1, 3, 2)"]), "`clamp` is not defined for `3`");
+        assert_eq!(run(&["lerp(2, 4, 1.5)"]), "5");
+    }
+
+    #[test]
+    fn named_constants_are_read_only() {
+        assert_eq!(run(&["tau - 2*pi"]), "0");
+        assert_eq!(run(&["pi = 3"]), "`pi` is a constant and cannot be redefined");
+        assert_eq!(run(&["e += 1"]), "`e` is a constant and cannot be redefined");
+        assert_eq!(run(&["pi == 3"]), "0");
+        assert_eq!(run(&["phi^2 - phi - 1"]), "0");
+    }
+
+    #[test]
+    fn constants_load_from_a_file() {
+        let mut env = Environment::new();
+        assert!(matches!(env.load_constants("g = 9.81\ntau = 6"), Err(BadConstant(2, inner)) if matches!(*inner, ReadOnly(_))));
+        assert!(matches!(env.run("g = 10").unwrap_err().inner(), ReadOnly(_)));
+        env.load_constants("[physics]\n# the speed of light\nc = 299792458\nhalf_c = c / 2").unwrap();
+        assert_eq!(env.run("half_c").unwrap().unwrap(), "149896229");
+        assert!(matches!(env.load_constants("2x = 1"), Err(BadConstant(1, inner)) if matches!(*inner, BadEntry(_))));
+        assert!(matches!(env.load_constants("\nx"), Err(BadConstant(2, inner)) if matches!(*inner, BadEntry(_))));
+    }
+
+    #[test]
+    fn user_functions_are_stored() {
+        assert_eq!(run(&["f(x) = x^2 + 3*x", "f(2)"]), "10");
+        assert_eq!(run(&["g(x, y) = x*y", "h(x) = g(x, 2) + 1", "h(3)"]), "7");
+        assert_eq!(run(&["f(n) = n < 1 ? 1 : n * f(n - 1)", "f(10)"]), "3628800");
+        assert_eq!(run(&["f(x) = x + 1", "f(x) = x + 2", "f(1)"]), "3");
+        assert_eq!(run(&["f(x) = x", "f(1, 2)"]), "Function `f` cannot take 2 argument(s)");
+        assert_eq!(run(&["f(x) = y", "f(1)"]), "Unknown identifier: `y`");
+    }
+
+    #[test]
+    fn if_skips_the_branch_not_taken() {
+        assert_eq!(run(&["x = 0", "if(x != 0, 1/x, 0)"]), "0");
+        assert_eq!(run(&["x = 0", "x != 0 ? 1/x : 0"]), "0");
+        assert_eq!(run(&["if(1, 2, 1/0)"]), "2");
+        assert_eq!(run(&["if(0, 1/0, 3)"]), "3");
+        assert_eq!(run(&["if(1, 2)"]), "Function `if` cannot take 2 argument(s)");
+    }
+
+    #[test]
+    fn sum_binds_its_variable_to_each_term() {
+        assert_eq!(run(&["sum(i, 1, 100, i^2)"]), "338350");
+        assert_eq!(run(&["sum(i, 1, 1e8, 1)"]), "`sum` over 100000000 terms is more than the limit of 1048576");
+        assert_eq!(run(&["sum(i, 3, 1, i)"]), "0");
+        assert_eq!(run(&["sum(i, 1, 2.5, i)"]), "Expected an integer, got: `2.5`");
+    }
+
+    #[test]
+    fn prod_multiplies_each_term() {
+        assert_eq!(run(&["prod(k, 1, 10, k)"]), "3628800");
+        assert_eq!(run(&["prod(k, 1, 30, k)"]), "265252859812191058636308480000000");
+        assert_eq!(run(&["prod(k, 1, 30, k) == 30!"]), "1");
+        assert_eq!(run(&["prod(k, 1, 10000, k)"]), "Result of `prod` is too large to represent exactly");
+        assert_eq!(run(&["prod(k, 1, 3, k / 2)"]), "0.75");
+        assert_eq!(run(&["prod(k, 1, 1000, k + 0.5)"]), "Result of `prod` is too large to represent exactly");
+        assert_eq!(run(&["prod(k, 5, 1, k)"]), "1");
+    }
+
+    #[test]
+    fn integrate_follows_the_tolerance() {
+        assert!(close(&["integrate(sin(x)/x, x, 0.001, 10)"], 1.657_347_594_274_418));
+        assert!(close(&["integrate(x^2, x, 0, 3)"], 9.0));
+        assert!(close(&["integrate(x^2, x, 3, 0)"], -9.0));
+        assert!(run(&["integrate(sin(1/x), x, 0.0001, 1)"]).starts_with("`integrate` failed to converge"));
+        assert!(run(&[":tolerance 1e-14", "integrate(sqrt(x), x, 0, 1)"]).starts_with("`integrate` failed to converge"));
+    }
+
+    #[test]
+    fn deriv_differentiates_at_a_point() {
+        assert!(close(&["deriv(x^2, x, 3)"], 6.0));
+        assert!(close(&["deriv(sin(x), x, 0)"], 1.0));
+        assert_eq!(run(&["deriv(x^2, 2, 3)"]), "Function `deriv` expects a variable name, found: `2`");
+    }
+
+    #[test]
+    fn solve_finds_the_nearest_root() {
+        assert!(close(&["solve(x^2 - 2, x, 1)"], consts::SQRT_2));
+        assert_eq!(run(&["solve(1e-12*(x - 5), x, 0)"]), "5");
+        assert_eq!(run(&["solve(x - 1e-10, x, 0)"]), "1e-10");
+        assert!(run(&["solve(x^2 + 1, x, 0)"]).starts_with("`solve` failed to converge"));
+        assert_eq!(run(&["solve(x^2 - 4, x, -3)"]), "-2");
+        assert!(close(&["solve(cos(x) - x, x, 1)"], 0.739_085_133_215_160_6));
+    }
+
+    #[test]
+    fn statistics_take_arguments_or_lists() {
+        assert_eq!(run(&["mean(3, 7, 8, 12)"]), "7.5");
+        assert_eq!(run(&["median(3, 7, 8, 12)"]), "7.5");
+        assert!(close(&["var(3, 7, 8, 12)"], 41.0 / 3.0));
+        assert!(close(&["stdev(3, 7, 8, 12)"], (41.0f64 / 3.0).sqrt()));
+        assert_eq!(run(&["sum(3, 7, 8, 12)"]), "30");
+        assert_eq!(run(&["a = 1", "b = 2", "c = 3", "d = 4", "sum(a, b, c, d)"]), "10");
+        assert_eq!(run(&["f(n) = sum(k, 1, n, k)", "f(4)"]), "10");
+        assert_eq!(run(&["count(3, 7, 8, 12)"]), "4");
+        assert_eq!(run(&["mean([1, 2, 3, 4])"]), "2.5");
+        assert_eq!(run(&["median(5, 1, 3)"]), "3");
+        assert_eq!(run(&["count([1, 2], 3)"]), "3");
+        assert_eq!(run(&["var(1)"]), "`var` is not defined for `1`");
+    }
+
+    #[test]
+    fn lists_combine_elementwise() {
+        assert_eq!(run(&["[1, 2, 3] * 2"]), "[2, 4, 6]");
+        assert_eq!(run(&["[1,2,3] + [4,5,6]"]), "[5, 7, 9]");
+        assert_eq!(run(&["[1,2] + [1,2,3]"]), "Cannot combine values of shape [2] and [3]");
+        assert_eq!(run(&["[5] + 1"]), "6");
+        assert_eq!(run(&["[1,2] * [3,4]"]), "[3, 8]");
+        assert_eq!(run(&["-[1,2] - 1"]), "[-2, -3]");
+        assert_eq!(run(&["sin([0, pi/2])"]), "[0, 1]");
+        assert_eq!(run(&["[1, 2, 3] / 0"]), "Division by zero: `1 / 0` (`:ieee on` allows it)");
+    }
+
+    #[test]
+    fn matrices_multiply_as_matrices() {
+        assert_eq!(run(&["[[1,2],[3,4]] * [[5,6],[7,8]]"]), "[[19, 22], [43, 50]]");
+        assert_eq!(run(&["det([[1,2],[3,4]])"]), "-2");
+        assert_eq!(run(&["inv([[2,0],[0,4]])"]), "[[0.5, 0], [0, 0.25]]");
+        assert_eq!(run(&["transpose([[1,2],[3,4]])"]), "[[1, 3], [2, 4]]");
+        assert_eq!(run(&["[[1],[2],[3]]"]), "[[1], [2], [3]]");
+        assert_eq!(run(&["inv([[2]])"]), "[[0.5]]");
+        assert_eq!(run(&["inv([[0]])"]), "Matrix is singular");
+        assert_eq!(run(&["[[1,2],[3,4]] * [1,1]"]), "[3, 7]");
+        assert_eq!(run(&["[[1,2],[3,4]] + [[1,1],[1,1]]"]), "[[2, 3], [4, 5]]");
+        assert_eq!(run(&["[[1,2]] * [[1,2]]"]), "Cannot combine values of shape [1x2] and [1x2]");
+        assert_eq!(run(&["det([[1,2,3]])"]), "Expected a square matrix, got a 1x3 matrix");
+        assert_eq!(run(&["[[1,2],[3]]"]), "Cannot combine values of shape [2] and [1]");
+    }
+
+    #[test]
+    fn dot_and_cross_check_dimensions() {
+        assert_eq!(run(&["dot([1,2,3],[4,5,6])"]), "32");
+        assert_eq!(run(&["cross([1,0,0],[0,1,0])"]), "[0, 0, 1]");
+        assert_eq!(run(&["cross([1,2,3],[4,5,6])"]), "[-3, 6, -3]");
+        assert_eq!(run(&["dot([1,2],[1,2,3])"]), "Cannot combine values of shape [2] and [3]");
+        assert_eq!(run(&["cross([1,2],[3,4])"]), "Cannot combine values of shape [3] and [2]");
+        assert_eq!(run(&["dot(1, [1])"]), "Function `dot` expects a vector, got: 1");
+    }
+
+    #[test]
+    fn polynomial_roots_are_real() {
+        assert_eq!(run(&["quadroots(1, -3, 2)"]), "[1, 2]");
+        assert_eq!(run(&["quadroots(1, -2, 1)"]), "[1]");
+        assert_eq!(run(&["quadroots(1, 0, 1)"]), "[]");
+        assert_eq!(run(&["quadroots(0, 2, -4)"]), "[2]");
+        assert_eq!(run(&["cubicroots(1, 0, 0, -8)"]), "[2]");
+        assert_eq!(run(&["quadroots(0, 0, 0)"]), "`quadroots` is not defined for `0`");
+    }
+
+    #[test]
+    fn number_theory_functions() {
+        assert_eq!(run(&["[isprime(97), isprime(91), nextprime(100)]"]), "[1, 0, 101]");
+        assert_eq!(run(&["factorint(360)"]), "[2, 2, 2, 3, 3, 5]");
+        assert_eq!(run(&["isprime(2305843009213693951)"]), "1");
+        assert_eq!(run(&["isprime(2^61 - 1)"]), "1");
+        assert_eq!(run(&["isprime(2^61 + 1)"]), "0");
+        assert!(run(&["factorint(4611686018427387847)"]).starts_with("Integer is too large to be exact"));
+        assert_eq!(run(&["factorint(2^62)"]), format!("[{}]", vec!["2"; 62].join(", ")));
+        assert_eq!(run(&["factorint(9223372021822390277)"]), "[2147483647, 4294967291]");
+        assert_eq!(run(&["nextprime(18446744073709551557)"]), "18446744073709551629");
+        assert_eq!(run(&["nextprime(2^53)"]), "9007199254740997");
+        assert!(run(&["isprime(float(2^61) - 1)"]).starts_with("Integer is too large to be exact"));
+        assert_eq!(run(&["[isprime(1), isprime(-7), nextprime(-5)]"]), "[0, 0, 2]");
+        assert_eq!(run(&["factorint(1)"]), "[]");
+        assert_eq!(run(&["factorint(0)"]), "`factorint` is not defined for `0`");
+        assert_eq!(run(&["isprime(2.5)"]), "Expected an integer, got: `2.5`");
+    }
+
+    #[test]
+    fn integer_sequences_detect_overflow() {
+        assert_eq!(run(&["[fib(10), catalan(5), triangular(10)]"]), "[55, 42, 55]");
+        assert_eq!(run(&["[fib(0), catalan(0), triangular(0)]"]), "[0, 1, 0]");
+        assert_eq!(run(&["fib(90)"]), "Result of `fib` is too large to represent exactly");
+        assert_eq!(run(&["triangular(-1)"]), "`triangular` is not defined for `-1`");
+    }
+
+    #[test]
+    fn base_conversion_functions() {
+        assert_eq!(run(&["hex(255)"]), "0xff");
+        assert_eq!(run(&["bin(5)"]), "0b101");
+        assert_eq!(run(&["oct(8)"]), "0o10");
+        assert_eq!(run(&["hex(2^70)"]), "0x400000000000000000");
+        assert_eq!(run(&["bin(-(2^64))"]), format!("-0b1{}", "0".repeat(64)));
+        assert_eq!(run(&["hex(2^60 + 1)"]), "0x1000000000000001");
+        assert_eq!(run(&["hex(-255)"]), "-0xff");
+        assert_eq!(run(&["oct(0)"]), "0o0");
+        assert_eq!(run(&["hex(1.5)"]), "Expected an integer, got: `1.5`");
+    }
+
+    #[test]
+    fn bit_functions_take_integers() {
+        assert_eq!(run(&["popcount(255)"]), "8");
+        assert_eq!(run(&["bit(5, 0)"]), "1");
+        assert_eq!(run(&["setbit(0, 3)"]), "8");
+        assert_eq!(run(&["clearbit(15, 0)"]), "14");
+        assert_eq!(run(&["setbit(1, 63)"]), "9223372036854775809");
+        assert_eq!(run(&["setbit(2^62, 0)"]), "4611686018427387905");
+        assert_eq!(run(&["bit(2^63, 63)"]), "1");
+        assert_eq!(run(&["clearbit(-1, 63)"]), "-9223372036854775809");
+        assert_eq!(run(&["setbit(1, 64)"]), "Bit index must be between 0 and 63: `64`");
+        assert_eq!(run(&["bit(5, 1)"]), "0");
+        assert_eq!(run(&["popcount(-1)"]), "64");
+        assert_eq!(run(&["bit(1.5, 0)"]), "Expected an integer, got: `1.5`");
+    }
+
+    #[test]
+    fn angle_mode_reaches_every_trig_function() {
+        assert_eq!(run(&[":angle deg", "sin(90)"]), "1");
+        assert_eq!(run(&["deg(pi)"]), "180");
+        assert_eq!(run(&[":angle deg", "cos(180)"]), "-1");
+        assert_eq!(run(&[":angle deg", "atan2(1, 1)"]), "45");
+        assert!(close(&["rad(180)"], consts::PI));
+        assert_eq!(run(&[":angle grad"]), "Unknown setting or value: `:angle grad`");
+    }
+
+    #[test]
+    fn powmod_is_exact() {
+        assert_eq!(run(&["powmod(4, 13, 497)"]), "445");
+        assert_eq!(run(&["powmod(2, 100, 1000000007)"]), "976371285");
+        assert_eq!(run(&["powmod(123456789, 987654321, 2^62)"]), "2707128288486860373");
+        assert_eq!(run(&["powmod(-4, 13, 497)"]), "52");
+        assert_eq!(run(&["powmod(2^70, 2, 1000)"]), "776");
+        assert_eq!(run(&["powmod(3, 0, 1)"]), "0");
+        assert_eq!(run(&["powmod(2, -1, 5)"]), "`powmod` is not defined for `-1`");
+        assert_eq!(run(&["powmod(2, 10, 0)"]), "`powmod` is not defined for `0`");
+    }
+
+    #[test]
+    fn distributions_check_their_parameters() {
+        assert!(close(&["normcdf(1.96)"], 0.975_002_104_851_780));
+        assert!(close(&["norminv(0.975)"], 1.959_963_984_540_054));
+        assert!(close(&["binompdf(10, 0.5, 5)"], 0.246_093_75));
+        assert!(close(&["normpdf(0)"], 0.398_942_280_401_432_7));
+        assert_eq!(run(&["normcdf(1, 1, 2)"]), "0.5");
+        assert_eq!(run(&["binompdf(3, 0.5, 4)"]), "0");
+        assert_eq!(run(&["norminv(1)"]), "`norminv` is not defined for `1`");
+        assert_eq!(run(&["normpdf(1, 0, 0)"]), "`normpdf` is not defined for `0`");
+        assert_eq!(run(&["binompdf(10, 1.5, 5)"]), "`binompdf` is not defined for `1.5`");
+    }
+
+    #[test]
+    fn ratio_finds_small_fractions() {
+        assert_eq!(run(&["ratio(0.3333333)"]), "1/3");
+        assert_eq!(run(&["ratio(pi, 1e-6)"]), "355/113");
+        assert_eq!(run(&["ratio(-0.75)"]), "-3/4");
+        assert_eq!(run(&["ratio(pi)"]), "355/113");
+        assert_eq!(run(&["ratio(1.5, 0)"]), "`ratio` is not defined for `0`");
+    }
+
+    #[test]
+    fn cfrac_lists_the_terms() {
+        assert_eq!(run(&["cfrac(pi, 5)"]), "[3, 7, 15, 1, 292]");
+        assert_eq!(run(&["cfrac(sqrt(2), 5)"]), "[1, 2, 2, 2, 2]");
+        assert_eq!(run(&["cfrac(0.5, 5)"]), "[0, 2]");
+        assert_eq!(run(&["cfrac(-pi, 3)"]), "[-4, 1, 6]");
+        assert_eq!(run(&["cfrac(pi, 0)"]), "`cfrac` is not defined for `0`");
+    }
+
+    #[test]
+    fn range_and_linspace_give_lists() {
+        assert_eq!(run(&["range(0, 10, 2)"]), "[0, 2, 4, 6, 8]");
+        assert_eq!(run(&["linspace(0, 1, 5)"]), "[0, 0.25, 0.5, 0.75, 1]");
+        assert_eq!(run(&["mean(range(1, 5, 1))"]), "2.5");
+        assert_eq!(run(&["range(0, 3)"]), "[0, 1, 2]");
+        assert_eq!(run(&["range(5, 0, -2)"]), "[5, 3, 1]");
+        assert_eq!(run(&["linspace(1, 0, 3)"]), "[1, 0.5, 0]");
+        assert_eq!(run(&["range(0, 1, 0)"]), "`range` is not defined for `0`");
+        assert_eq!(run(&["linspace(0, 1, 0)"]), "`linspace` is not defined for `0`");
+    }
+
+    #[test]
+    fn exact_mode_keeps_fractions() {
+        assert_eq!(run(&[":exact on", "1/3 + 1/6"]), "1/2");
+        assert_eq!(run(&[":exact on", "sqrt(2)"]), "1.4142135623730951");
+        assert_eq!(run(&[":exact on", "0.1 + 0.2"]), "3/10");
+        assert_eq!(run(&[":exact on", "(1/3)^2"]), "1/9");
+        assert_eq!(run(&[":exact on", "1/3 * 3"]), "1");
+        assert_eq!(run(&[":exact on", "1/0"]), "Division by zero: `1 / 0` (`:ieee on` allows it)");
+    }
+
+    #[test]
+    fn intervals_propagate_bounds() {
+        assert_eq!(run(&[":interval on", "[1.9, 2.1] * 3"]), "[5.699999999999999, 6.300000000000001]");
+        assert_eq!(run(&[":interval on", "[1, 2] - [1, 2]"]), "[-1, 1]");
+        assert_eq!(run(&[":interval on", "[1, 2] * [-1, 3]"]), "[-2, 6]");
+        assert_eq!(run(&[":interval on", "sqrt([4, 9])"]), "[2, 3]");
+        assert_eq!(run(&[":interval on", "1 / [-1, 1]"]), "Cannot divide by an interval containing zero: [-1, 1]");
+        assert_eq!(run(&[":interval on", "[2, 1]"]), "Interval bounds are out of order: [2, 1]");
+    }
+
+    #[test]
+    fn integer_mode_is_strict() {
+        assert_eq!(run(&[":integer on", "7 / 2"]), "Cannot divide integers: use `7 // 2` for the floored quotient, or `float(7) / 2`");
+        assert_eq!(run(&[":integer on", "7 // 2"]), "3");
+        assert_eq!(run(&[":integer on", "-7 // 2"]), "-4");
+        assert_eq!(run(&[":integer on", "6 / 3"]), "Cannot divide integers: use `6 // 3` for the floored quotient, or `float(6) / 3`");
+        assert_eq!(run(&[":integer on", "float(7) / 2"]), "3.5");
+        assert_eq!(run(&[":integer on", "2.5 + 1"]), "3.5");
+        assert_eq!(run(&[":integer on", "1.5 & 1"]), "Operator `&` expects integers, got: 1.5");
+        assert_eq!(run(&[":integer on", ":integer off", "7 / 2"]), "3.5");
+    }
+
+    #[test]
+    fn modular_arithmetic_reduces() {
+        assert_eq!(run(&[":mod 17", "3 * 6"]), "1");
+        assert_eq!(run(&[":mod 17", "1 / 3"]), "6");
+        assert_eq!(run(&[":mod 17", "0 - 1"]), "16");
+        assert_eq!(run(&[":mod 17", "20"]), "3");
+        assert_eq!(run(&[":mod 17", "-1"]), "16");
+        assert_eq!(run(&[":mod 17", "5!"]), "1");
+        assert_eq!(run(&[":mod 17", "2^100"]), "16");
+        assert_eq!(run(&[":mod 17", "2^-1"]), "9");
+        assert_eq!(run(&[":mod 17", "0 / 5"]), "0");
+        assert_eq!(run(&[":mod 17", "1 / 17"]), "`0` has no inverse modulo 17");
+        assert_eq!(run(&[":mod 10", "1 / 2"]), "`2` has no inverse modulo 10");
+        // Only integers are reduced.
+        assert_eq!(run(&[":mod 17", "2.5 + 1"]), "3.5");
+        assert_eq!(run(&[":mod 17", ":mod off", "20"]), "20");
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        assert_eq!(run(&["5 / 0"]), "Division by zero: `5 / 0` (`:ieee on` allows it)");
+        assert_eq!(run(&["5 % 0"]), "Division by zero: `5 % 0` (`:ieee on` allows it)");
+        assert_eq!(run(&[":ieee on", "5 / 0"]), "inf");
+        assert_eq!(run(&["0 / 0"]), "Division by zero: `0 / 0` (`:ieee on` allows it)");
+        assert_eq!(run(&["5.5 % 0"]), "Division by zero: `5.5 % 0` (`:ieee on` allows it)");
+        assert_eq!(run(&["1 / (2 - 2)"]), "Division by zero: `1 / 0` (`:ieee on` allows it)");
+        assert_eq!(run(&["5 // 0"]), "Floor division by zero: `5 // 0`");
+        assert_eq!(run(&[":ieee on", "-5 / 0"]), "-inf");
+        assert_eq!(run(&[":ieee on", "0 / 0"]), "NaN");
+        assert_eq!(run(&[":ieee on", "5 % 0"]), "NaN");
+    }
+
+    #[test]
+    fn non_finite_results_are_errors() {
+        assert_eq!(run(&["1e308 * 10"]), "`1e308 * 10.0` gives inf (`:ieee on` allows it)");
+        assert_eq!(run(&["1e308 * -10"]), "`1e308 * -10.0` gives -inf (`:ieee on` allows it)");
+        assert_eq!(run(&["1e308 + 1e308"]), "`1e308 + 1e308` gives inf (`:ieee on` allows it)");
+        assert_eq!(run(&["0 * 1e308"]), "0");
+        assert_eq!(run(&[":ieee on", "1e308 * 10"]), "inf");
+    }
+
+    #[test]
+    fn kahan_summation_does_not_drift() {
+        assert_eq!(run(&[":kahan on", "sum(i, 1, 10000, 0.1)"]), "1000");
+        assert_eq!(run(&[":kahan on", "(1e16 + 1) - (1e16 - 1)"]), "2");
+        assert_eq!(run(&["sum(i, 1, 10000, 0.1)"]), "1000.0000000001588");
+        assert_eq!(run(&[":kahan on", ":kahan off", "sum(i, 1, 10000, 0.1)"]), "1000.0000000001588");
+        assert_eq!(run(&[":kahan on", "0.1 + 0.2 + 0.3"]), "0.6");
+        assert_eq!(run(&[":kahan on", "sum(i, 1, 1000, 1/i)"]), "7.485470860550345");
+        // The sum is correctly rounded, not rounded to what was typed.
+        assert_eq!(run(&[":kahan on", "0.1 + 0.2"]), "0.30000000000000004");
+    }
+
+    #[test]
+    fn significant_figures_output() {
+        assert_eq!(run(&[":sigfigs 3", "0.000123456"]), "1.23e-4");
+        assert_eq!(run(&[":sigfigs 3", "-0.000123456"]), "-1.23e-4");
+        assert_eq!(run(&[":sigfigs 3", "123456"]), "1.23e5");
+        assert_eq!(run(&[":sigfigs 3", "1/3"]), "0.333");
+        assert_eq!(run(&[":sigfigs 3", "1.5"]), "1.50");
+        assert_eq!(run(&[":sigfigs 1", "0.96"]), "1");
+        assert_eq!(run(&[":sigfigs 3", ":sigfigs off", "1/3"]), "0.3333333333333333");
+    }
+
+    #[test]
+    fn floats_print_shortest() {
+        assert_eq!(run(&["1/3"]), "0.3333333333333333");
+        assert_eq!(run(&["0.1 + 0.2"]), "0.30000000000000004");
+        assert_eq!(run(&[":places 3", "1/3"]), "0.333");
+        assert_eq!(run(&["1/0.1"]), "10");
+        assert_eq!(run(&["1e21"]), "1e21");
+        assert_eq!(run(&["1e-7"]), "1e-7");
+        assert_eq!(run(&["-0.0"]), "-0");
+        assert_eq!(run(&[":places 3", "2"]), "2.000");
+        assert_eq!(run(&[":places 3", "0.000123456"]), "0.000");
+        assert_eq!(run(&[":places 3", ":places off", "1/3"]), "0.3333333333333333");
+    }
+
+    #[test]
+    fn fraction_output() {
+        assert_eq!(run(&[":fractions on", "0.75"]), "3/4");
+        assert_eq!(run(&[":fractions on", "-0.75"]), "-3/4");
+        assert_eq!(run(&[":fractions on", "1.5"]), "3/2");
+        assert_eq!(run(&[":fractions on", "1/7"]), "1/7");
+        assert_eq!(run(&[":fractions on", "2"]), "2");
+        // Floats not close to a small fraction print as usual.
+        assert_eq!(run(&[":fractions on", "0.1 + 0.2"]), "0.30000000000000004");
+        assert_eq!(run(&[":fractions on", "sqrt(2)"]), "1.4142135623730951");
+        assert_eq!(run(&[":fractions on", ":fractions off", "0.75"]), "0.75");
+    }
+
+    #[test]
+    fn engineering_notation_output() {
+        assert_eq!(run(&[":eng on", "12500"]), "12.5e3");
+        assert_eq!(run(&[":eng on", "0.0000047"]), "4.7e-6");
+        assert_eq!(run(&[":eng on", "123456789"]), "123.456789e6");
+        assert_eq!(run(&[":eng on", "1000"]), "1e3");
+        assert_eq!(run(&[":eng on", "-0.001"]), "-1e-3");
+        assert_eq!(run(&[":eng on", "0.5"]), "500e-3");
+        assert_eq!(run(&[":eng on", "999"]), "999");
+        assert_eq!(run(&[":eng on", "0"]), "0");
+        assert_eq!(run(&[":eng on", ":eng off", "12500"]), "12500");
+    }
+
+    #[test]
+    fn decimal_comma_locale() {
+        assert_eq!(run(&[":comma on", "3,14 * 2"]), "6,28");
+        assert_eq!(run(&[":comma on", "0,1 + 0,2"]), "0,30000000000000004");
+        assert_eq!(run(&[":comma on", "1.234.567,5"]), "1234567,5");
+        assert_eq!(run(&[":comma on", "1.23,5"]), "Thousands groups must have three digits: `1.23,5`");
+        // As with thousands commas, arguments are separated by a comma and a space.
+        assert_eq!(run(&[":comma on", "max(1,5, 2)"]), "2");
+        assert_eq!(run(&[":comma on", "[1,5, 2]"]), "[1,5, 2]");
+        assert_eq!(run(&[":comma on", ":comma off", "max(1,5)"]), "5");
+    }
+
+    #[test]
+    fn grouped_thousands_output() {
+        assert_eq!(run(&[":group on", "1234567.89"]), "1,234,567.89");
+        assert_eq!(run(&[":group _", "1234567.89"]), "1_234_567.89");
+        assert_eq!(run(&[":group '", "1234567"]), "1'234'567");
+        assert_eq!(run(&[":group on", "-1234567"]), "-1,234,567");
+        assert_eq!(run(&[":group on", "1234"]), "1,234");
+        assert_eq!(run(&[":group on", "999"]), "999");
+        assert_eq!(run(&[":group on", "2^70"]), "1,180,591,620,717,411,303,424");
+        assert_eq!(run(&[":group on", "0.000001234"]), "1.234e-6");
+        assert_eq!(run(&[":group on", ":group off", "1234567"]), "1234567");
+        // The separator follows the decimal comma, unless one was given.
+        assert_eq!(run(&[":comma on", ":group on", ":comma off", "1234567.891"]), "1,234,567.891");
+        assert_eq!(run(&[":group on", ":comma on", "1234567,891"]), "1.234.567,891");
+        assert_eq!(run(&[":group _", ":comma on", "1234567,891"]), "1_234_567,891");
+    }
+
+    #[test]
+    fn results_show_in_other_bases() {
+        assert_eq!(run(&[":outbase hex", "1024"]), "0x400");
+        assert_eq!(run(&["255 @ bin"]), "0b11111111");
+        assert_eq!(run(&[":outbase hex", "2^70 + 1"]), "0x400000000000000001");
+        assert_eq!(run(&["255 @ oct"]), "0o377");
+        assert_eq!(run(&["-255 @ hex"]), "-0xff");
+        assert_eq!(run(&["1 + 2 @ hex"]), "0x3");
+        assert_eq!(run(&["255 @ dec"]), "255");
+        assert_eq!(run(&["1.5 @ hex"]), "Expected an integer, got: `1.5`");
+        assert_eq!(run(&[":outbase hex", "255 @ bin"]), "0b11111111");
+        assert_eq!(run(&[":outbase bin", "5"]), "0b101");
+        // Only integer results change base.
+        assert_eq!(run(&[":outbase hex", "1.5"]), "1.5");
+        assert_eq!(run(&[":outbase hex", ":outbase dec", "1024"]), "1024");
+    }
+
+    // Constants in a body fold under the settings it runs with, not those it was defined under.
+    #[test]
+    fn folding_follows_settings() {
+        assert_eq!(run(&[":mod 17", "f(x) = 10 + 10 + x", ":mod off", "f(0)"]), "20");
+        assert_eq!(run(&["f(x) = 10 + 10 + x", ":mod 17", "f(0)"]), "3");
+        assert_eq!(run(&["f(x) = 0.1 + 0.2 + 0.3 + x", ":kahan on", "f(0)"]), "0.6");
+        assert_eq!(run(&["f(x) = 0.1 + 0.2 + x", ":exact on", "f(0)"]), "3/10");
+        let mut env = Environment::new();
+        env.run("f(x) = 1e20 + 1 + x").unwrap();
+        assert!(env.take_warnings().is_empty());
+        env.run("f(0)").unwrap();
+        assert_eq!(env.take_warnings().len(), 1);
+        assert_eq!(run(&["f(x) = 2^10 * x", "f(3)"]), "3072");
+        assert_eq!(run(&["f(x) = 1 m + x", "f(2 m)"]), "3 m");
+        // Variables are looked up when the body runs, and failures stay where they were.
+        assert_eq!(run(&["a = 2", "f(x) = a + 1 + x", "a = 5", "f(0)"]), "6");
+        assert_eq!(run(&["f(x) = 1/0 + x"]), "");
+        assert_eq!(run(&["f(x) = 1/0 + x", "f(1)"]), "Division by zero: `1 / 0` (`:ieee on` allows it)");
+        assert_eq!(run(&["f(x) = sqrt(-1) + x", "f(0)"]), "`sqrt` is not defined for `-1`");
+        assert_eq!(run(&["f(x) = 1e308 * 10 + x", "f(0)"]), "`1e308 * 10.0` gives inf (`:ieee on` allows it)");
+    }
+
+    #[test]
+    fn recursion_is_limited() {
+        assert_eq!(run(&["r(x) = r(x)", "r(1)"]), "Maximum call depth exceeded in `r`");
+        assert_eq!(run(&["g(n) = n < 1 ? 0 : 1 + g(n-1)", "g(240)"]), "240");
+        assert_eq!(run(&["g(n) = if(n < 1, 0, 1 + g(n-1))", "g(250)"]), "250");
+        assert_eq!(run(&["g(n) = n < 1 ? 0 : 1 + g(n-1)", "g(300)"]), "Maximum call depth exceeded in `g`");
+    }
+
+    #[test]
+    fn precision_warnings() {
+        let mut env = Environment::new();
+        assert_eq!(env.run("1e16 + 1 - 1e16").unwrap().unwrap(), "0");
+        assert!(matches!(env.take_warnings()[..], [Warning::Absorbed(..)]));
+        assert!(env.take_warnings().is_empty());
+        // Iterations inside lazy functions stay quiet, whether or not they get anywhere.
+        assert!(env.run("solve(x^2 + 1, x, 0)").is_err());
+        assert!(env.take_warnings().is_empty());
+        env.run("solve(x^3 - 8, x, 1)").unwrap();
+        assert!(env.take_warnings().is_empty());
+        env.run("1.00000000001 - 1").unwrap();
+        assert!(matches!(env.take_warnings()[..], [Warning::Cancelled(.., 10)]));
+        env.run("1.000001 - 1").unwrap();
+        assert!(env.take_warnings().is_empty());
+        env.run("1e-200 * 1e-200").unwrap();
+        assert!(matches!(env.take_warnings()[..], [Warning::Underflow(..)]));
+        env.run("0.1 + 0.2 - 0.3").unwrap();
+        assert!(matches!(env.take_warnings()[..], [Warning::Cancelled(.., 15)]));
+        env.run("2 - 1").unwrap();
+        assert!(env.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn uncertain_values() {
+        assert_eq!(run(&["5.0±0.1"]), "5 ± 0.1");
+        assert_eq!(run(&["(5±0.1) * 2"]), "10 ± 0.2");
+        assert_eq!(run(&["(3±0.4) + (4±0.3)"]), "7 ± 0.5");
+        assert_eq!(run(&["(5±0.1) + 1"]), "6 ± 0.1");
+        assert_eq!(run(&["-(5±0.1)"]), "-5 ± 0.1");
+        assert_eq!(run(&["(2±0.1) * (3±0.2)"]), "6 ± 0.5");
+        assert_eq!(run(&["(6±0.3) / (2±0.1)"]), "3 ± 0.21213203435596428");
+        assert_eq!(run(&["(2±0.1)^2"]), "4 ± 0.4");
+        assert_eq!(run(&["sqrt(4±0.4)"]), "2 ± 0.1");
+        assert_eq!(run(&["ln(2±0.1)"]), "0.6931471805599453 ± 0.05");
+        // Errors of separate values are independent, even when they're equal.
+        assert_eq!(run(&["(5±0.1) - (5±0.1)"]), "0 ± 0.14142135623730953");
+    }
+
+    #[test]
+    fn eval_gives_numbers() {
+        assert_eq!(eval("2 * (3 + 4)").unwrap(), 14.0);
+        assert!(matches!(eval("1 +").unwrap_err().inner(), NotEnoughTokens(_)));
+        assert!(matches!(eval("f(x) = x"), Err(Generic(_))));
+    }
+
+    #[test]
+    fn errors_are_located() {
+        let error = Environment::new().run("1 + 2 * foo(3)").unwrap_err();
+        assert_eq!(error.to_string(), "Unknown function: `foo`");
+        assert_eq!(error.span(), Some(Span { start: 8, end: 11 }));
+        assert_eq!(Environment::new().run("1 + $").unwrap_err().span(), Some(Span { start: 4, end: 5 }));
+        assert_eq!(Environment::new().run("(1 + 2]").unwrap_err().span(), Some(Span { start: 6, end: 7 }));
+        let span = |input: &str| Environment::new().run(input).unwrap_err().span();
+        assert_eq!(span("[1, 2] + [1, 2, 3]"), Some(Span { start: 7, end: 8 }));
+        assert_eq!(span("2 * 3 +"), Some(Span { start: 6, end: 7 }));
+        assert_eq!(span("max(1, 2) + sqrt(1, 2)"), Some(Span { start: 12, end: 16 }));
+        assert_eq!(span("2 + ln(-1)"), Some(Span { start: 4, end: 6 }));
+        assert_eq!(span("[1, x]"), Some(Span { start: 4, end: 5 }));
+        assert_eq!(span("[[1, 2], [3]]"), Some(Span { start: 0, end: 13 }));
+        assert_eq!(span("f(x) = [1, 2] + [1, 2, 3]; 1 + f(0)"), Some(Span { start: 31, end: 32 }));
+        assert_eq!(span("1.5 @ hex"), Some(Span { start: 4, end: 9 }));
+        assert_eq!(span("1 + x/0"), Some(Span { start: 0, end: 7 }));
+        assert_eq!(span("  foo(1)"), Some(Span { start: 2, end: 5 }));
+        assert_eq!(span("5 / 0"), Some(Span { start: 2, end: 3 }));
+        assert_eq!(span("(1 + 2"), Some(Span { start: 0, end: 1 }));
+        assert_eq!(span("y += 1"), Some(Span { start: 0, end: 1 }));
+        assert_eq!(span("pi = 3"), Some(Span { start: 0, end: 2 }));
+        assert_eq!(span("1e"), Some(Span { start: 0, end: 2 }));
+        assert_eq!(span("sqrt()"), Some(Span { start: 0, end: 4 }));
+        assert_eq!(span("1, 2"), Some(Span { start: 1, end: 2 }));
+    }
+
+    #[test]
+    fn errors_have_sources() {
+        let error = Environment::new().run("0x1ffffffffffffffffffffffffffffffffffffff").unwrap_err();
+        assert!(error.source().is_some());
+        assert!(Environment::new().command("rates /nonexistent").unwrap_err().source().is_some());
+        let error = Environment::new().load_constants("g = 9.81\nc = 1 / 0").unwrap_err();
+        assert!(matches!(&error, BadConstant(2, inner) if matches!(**inner, At(_, _))));
+        assert_eq!(error.source().unwrap().to_string(), "Division by zero: `1 / 0` (`:ieee on` allows it)");
+        let error = Environment::new().load_rates("EUR = lots").unwrap_err();
+        assert!(error.source().and_then(Error::source).is_some());
+        assert!(Environment::new().run("1 +").unwrap_err().source().is_none());
+        // Sources are the standard errors underneath, whatever span is around them.
+        let error = Environment::new().run("1e5.5").unwrap_err();
+        assert!(error.source().unwrap().is::<ParseFloatError>());
+        assert!(Environment::new().run("1e").unwrap_err().source().is_none());
+        let error = Environment::new().run("1 + 0x1ffffffffffffffffffffffffffffffffffffff").unwrap_err();
+        assert!(error.source().unwrap().is::<ParseIntError>());
+        let error = Environment::new().command("rates /nonexistent").unwrap_err();
+        assert!(matches!(error.source().and_then(|source| source.downcast_ref::<io::Error>()), Some(error) if error.kind() == io::ErrorKind::NotFound));
+        assert!(format!("{:?}", Environment::new().run("1 +").unwrap_err()).contains("NotEnoughTokens"));
+    }
+
+    #[test]
+    fn semicolons_separate_statements() {
+        assert_eq!(run(&["x = 2; x * 3"]), "6");
+        assert_eq!(run(&["1; 2;"]), "2");
+        assert!(parse(";").is_err());
+        assert_eq!(parse("1; 2").unwrap(), vec![Expr::Num(1.0), Expr::Num(2.0)]);
+        assert_eq!(run(&["x = 3; y = 4; sqrt(x*x + y*y)"]), "5");
+        assert_eq!(run(&["x = 1; x += 1; x"]), "2");
+        assert_eq!(run(&["1;;2"]), "2");
+        assert_eq!(run(&[";"]), "");
+    }
+
+    #[test]
+    fn misplaced_tokens_are_errors() {
+        for input in [";", ";;", "(1;2)", "1;)", "f(;)", "[;]", "1 ? ; : 2"] {
+            let _ = parse(input);
+            let _ = run(&[input]);
+        }
+    }
+
+}
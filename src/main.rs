@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+use std::fs;
+use std::error::Error;
+use rpn_remade::{Environment, MathError, CONSTANTS_FILE, RATES_FILE};
+
+// Languages whose locales write `3,14` for `3.14`.
+const COMMA_LANGUAGES: &[&str] = &["cs", "da", "de", "es", "fi", "fr", "it", "nb", "nl", "pl", "pt", "ru", "sv", "tr"];
+
+fn main() {
+    println!("Shunting Yard algorithm calculator, enter an expression to be evaluated.");
+    println!("Type `exit` to exit, or `:setting value` to change a setting");
+    let mut input = String::new();
+    let mut env = Environment::new();
+    if let Ok(source) = fs::read_to_string(CONSTANTS_FILE) {
+        if let Err(error) = env.load_constants(&source) {
+            println!("{}", report(&error));
+        }
+    }
+    if let Ok(source) = fs::read_to_string(RATES_FILE) {
+        if let Err(error) = env.load_rates(&source) {
+            println!("{}", report(&error));
+        }
+    }
+    // The same precedence as `setlocale` gives them for LC_NUMERIC.
+    let locale = ["LC_ALL", "LC_NUMERIC", "LANG"].iter().find_map(|var| std::env::var(var).ok().filter(|locale| !locale.is_empty()));
+    if locale.is_some_and(|locale| COMMA_LANGUAGES.iter().any(|language| locale.starts_with(language))) {
+        if let Err(error) = env.command("comma on") {
+            println!("{}", report(&error));
+        }
+    }
+    // Any `--setting` flag turns that setting on.
+    for arg in std::env::args().skip(1) {
+        let result = match arg.strip_prefix("--") {
+            Some(setting) => env.command(&format!("{} on", setting)),
+            None => Err(MathError::BadCommand(arg)),
+        };
+        if let Err(error) = result {
+            println!("{}", report(&error));
+        }
+    }
+    loop {
+        print!(">>> ");
+        io::stdout().flush().expect("Cannot flush stdout.");
+        io::stdin()
+            .read_line(&mut input)
+            .expect("Failed to read stdin.");
+
+        match input.trim() {
+            "exit" => {
+                println!("Goodbye.");
+                return;
+            },
+            command if command.starts_with(':') => {
+                if let Err(error) = env.command(&command[1..]) {
+                    println!("{}", report(&error));
+                }
+            },
+            _ => {
+                match env.run(&input) {
+                    Ok(Some(shown)) => println!("{}", shown),
+                    Ok(None) => (),
+                    Err(error) => {
+                        // Under where it is in the input, past the prompt.
+                        if let Some(span) = error.span() {
+                            let (before, within) = (input[..span.start].chars().count(), input[span.start..span.end].chars().count());
+                            println!("    {}{}", " ".repeat(before), "^".repeat(within.max(1)));
+                        }
+                        println!("{}", report(&error));
+                    },
+                }
+                for warning in env.take_warnings() {
+                    println!("Warning: {}", warning);
+                }
+            },
+        }
+        input.clear();
+    }
+}
+
+// The error followed by what caused it, as `error: cause`.
+fn report(error: &MathError) -> String {
+    let mut report = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        report += &format!(": {}", cause);
+        source = cause.source();
+    }
+    report
+}
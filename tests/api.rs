@@ -0,0 +1,94 @@
+use std::error::Error;
+use rpn_remade::{eval, eval_in, parse, Environment, MathError, Numeric, Operator, UnaryOperator};
+
+#[test]
+fn eval_gives_numbers() {
+    assert_eq!(eval("2 * (3 + 4)").unwrap(), 14.0);
+    assert_eq!(eval("x = 3; y = 4; sqrt(x*x + y*y)").unwrap(), 5.0);
+    assert!(eval("1 / 0").is_err());
+    assert_eq!(eval("2^70").unwrap(), 2f64.powi(70));
+    assert!(matches!(eval("[1, 2]"), Err(MathError::ExpectedNumber(_))));
+    assert!(matches!(eval("f(x) = x"), Err(MathError::Generic(_))));
+    assert!(matches!(eval(""), Err(MathError::NotEnoughTokens(_))));
+    assert!(matches!(eval("# just a comment"), Err(MathError::NotEnoughTokens(_))));
+}
+
+#[test]
+fn environments_keep_state() {
+    let mut env = Environment::new();
+    assert_eq!(env.run("f(x) = x^2 + 1").unwrap(), None);
+    assert_eq!(env.run("f(3)").unwrap().as_deref(), Some("10"));
+    env.command("places 2").unwrap();
+    assert_eq!(env.run("1/3").unwrap().as_deref(), Some("0.33"));
+    assert!(matches!(env.command("bogus"), Err(MathError::BadCommand(_))));
+}
+
+#[test]
+fn parse_gives_trees() {
+    let statements = parse("1 + 2 * x; y").unwrap();
+    assert_eq!(statements.len(), 2);
+    assert_eq!(statements[0].to_string(), "1 + 2*x");
+}
+
+#[test]
+fn errors_compose_with_question_mark() {
+    fn run() -> Result<f64, Box<dyn Error + Send + Sync>> {
+        Ok(eval("1e")?)
+    }
+    let error = run().unwrap_err();
+    assert_eq!(error.to_string(), "Cannot parse literal: `1e`");
+    assert!(format!("{:?}", error).contains("ParseNum"));
+}
+
+// Exact fractions, as a library user might bring their own number type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Ratio(i64, i64);
+impl Ratio {
+    fn new(num: i64, den: i64) -> Option<Self> {
+        fn gcd(a: i64, b: i64) -> i64 {
+            if b == 0 { a.abs() } else { gcd(b, a % b) }
+        }
+        let divisor = Some(gcd(num, den) * den.signum()).filter(|_| den != 0)?;
+        Some(Ratio(num / divisor, den / divisor))
+    }
+}
+impl Numeric for Ratio {
+    type Context = ();
+
+    fn from_f64(float: f64, _: ()) -> Option<Self> {
+        Some(Ratio(float as i64, 1)).filter(|_| float.fract() == 0.0)
+    }
+
+    fn call(oper: &Operator, Ratio(a, b): Self, Ratio(c, d): Self, _: ()) -> Option<Self> {
+        match oper {
+            Operator::Add => Ratio::new(a * d + c * b, b * d),
+            Operator::Sub => Ratio::new(a * d - c * b, b * d),
+            Operator::Mul => Ratio::new(a * c, b * d),
+            Operator::Div => Ratio::new(a * d, b * c),
+            _ => None,
+        }
+    }
+
+    fn call_unary(oper: &UnaryOperator, Ratio(a, b): Self, _: ()) -> Option<Self> {
+        match oper {
+            UnaryOperator::Neg => Some(Ratio(-a, b)),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn eval_in_user_number_types() {
+    assert_eq!(eval_in::<Ratio>("1/3 + 1/6", ()).unwrap(), Ratio(1, 2));
+    assert_eq!(eval_in::<Ratio>("-(2 - 8) / 4", ()).unwrap(), Ratio(3, 2));
+    assert!(matches!(eval_in::<Ratio>("sqrt(2)", ()), Err(MathError::Unsupported(_))));
+    assert!(matches!(eval_in::<Ratio>("1 / 0", ()), Err(MathError::Unsupported(_))));
+    assert!(matches!(eval_in::<Ratio>("2^3", ()), Err(MathError::Unsupported(_))));
+    assert!(matches!(eval_in::<Ratio>("0.5", ()), Err(MathError::Unsupported(_))));
+    assert_eq!(eval_in::<Ratio>("(1 - 1/2) * (1 + 1/2)", ()).unwrap(), Ratio(3, 4));
+
+    let mut env = Environment::new();
+    env.run("x = 4").unwrap();
+    assert_eq!(env.eval_in::<Ratio>("x / 6", ()).unwrap(), Ratio(2, 3));
+    assert_eq!(env.eval_in::<f64>("x / 8", ()).unwrap(), 0.5);
+}